@@ -1,13 +1,15 @@
-use crate::{ast::*, casm::*};
-use json;
-use std::collections::HashMap;
+use crate::ast::{Span, line_col};
+use crate::casm::*;
+use crate::diagnostic::{Diagnostic, Diagnostics, codes};
+use crate::felt::{Felt, Field};
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Instruction {
     pub offdst: i32,
     pub offop0: i32,
     pub offop1: i32,
-    pub imm: Option<u64>,
+    pub imm: Option<Felt>,
     pub dst: u8,
     pub op0: u8,
     pub op1: u8,
@@ -17,12 +19,32 @@ pub struct Instruction {
     pub opcode: u8,
 }
 
+// Offsets are encoded on 16 bits, biased by 2^15.
+const OFFSET_BOUND: i32 = 1 << 15;
+
 impl Instruction {
-    pub fn to_bytes(&self) -> (u64, Option<u64>) {
+    pub fn check_offsets(&self) -> Result<(), String> {
+        for (name, offset) in [
+            ("dst", self.offdst),
+            ("op0", self.offop0),
+            ("op1", self.offop1),
+        ] {
+            if !(-OFFSET_BOUND..OFFSET_BOUND).contains(&offset) {
+                return Err(format!(
+                    "{} offset {} is out of range [-2^15, 2^15)",
+                    name, offset
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> (u64, Option<Felt>) {
+        debug_assert!(self.check_offsets().is_ok());
         let mut res: u64 = 0;
-        res += (self.offdst + 0x8000) as u64;
-        res += ((self.offop0 + 0x8000) as u64) << 16;
-        res += ((self.offop1 + 0x8000) as u64) << 32;
+        res += (self.offdst + OFFSET_BOUND) as u64;
+        res += ((self.offop0 + OFFSET_BOUND) as u64) << 16;
+        res += ((self.offop1 + OFFSET_BOUND) as u64) << 32;
         res += (self.dst as u64) << 48;
         res += (self.op0 as u64) << 49;
         res += (self.op1 as u64) << 50;
@@ -35,20 +57,46 @@ impl Instruction {
             None => (res, None),
         }
     }
+
+    // Inverse of to_bytes, the immediate is only read when op1 is taken from it.
+    pub fn from_bytes(word: u64, imm: Option<Felt>) -> Result<Self, String> {
+        if word >> 63 != 0 {
+            return Err(format!("Invalid instruction {:#x}", word));
+        }
+        let offset = |shift: u32| ((word >> shift) & 0xffff) as i32 - OFFSET_BOUND;
+        let op1 = ((word >> 50) & 7) as u8;
+        let imm = if op1 == 1 {
+            Some(imm.ok_or(format!("Missing immediate after {:#x}", word))?)
+        } else {
+            None
+        };
+        Ok(Instruction {
+            offdst: offset(0),
+            offop0: offset(16),
+            offop1: offset(32),
+            imm,
+            dst: ((word >> 48) & 1) as u8,
+            op0: ((word >> 49) & 1) as u8,
+            op1,
+            res: ((word >> 53) & 3) as u8,
+            pc_update: ((word >> 55) & 7) as u8,
+            ap_update: ((word >> 58) & 3) as u8,
+            opcode: ((word >> 60) & 7) as u8,
+        })
+    }
 }
 
-pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
-    match instruction {
+// Labels, jumps and calls to labels have to be resolved first.
+pub fn build_instruction(
+    instruction: CasmInstruction,
+    field: &Field,
+) -> Result<Instruction, String> {
+    Ok(match instruction {
         CasmInstruction::CallRel(offset) => {
             let offdst = 0;
             let offop0 = 1;
             let offop1 = 1;
-            let imm = if offset < 0 {
-                0x7FFFFFFF + offset
-            } else {
-                offset
-            };
-            let imm = Some(imm as u64);
+            let imm = Some(field.reduce_i128(offset as i128));
             let dst = 0;
             let op0 = 0;
             let op1 = 1;
@@ -74,7 +122,7 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
             let offdst = 0;
             let offop0 = 1;
             let offop1 = 1;
-            let imm = Some(address as u64);
+            let imm = Some(field.reduce(&address));
             let dst = 0;
             let op0 = 0;
             let op1 = 1;
@@ -106,22 +154,22 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
             let offop1 = match op {
                 Operand::DerefFp(offset) => offset,
                 Operand::DerefAp(offset) => offset,
-                Operand::Int(n) => 1,
+                Operand::Int(_) => 1,
             };
             let imm = match op {
-                Operand::Int(n) => Some(n),
+                Operand::Int(n) => Some(field.reduce(&n)),
                 _ => None,
             };
             let dst = match left {
-                Operand::DerefFp(offset) => 1,
-                Operand::DerefAp(offset) => 0,
+                Operand::DerefFp(_) => 1,
+                Operand::DerefAp(_) => 0,
                 _ => unreachable!(),
             };
             let op0 = 1;
             let op1 = match op {
-                Operand::DerefFp(offset) => 2,
-                Operand::DerefAp(offset) => 4,
-                Operand::Int(n) => 1,
+                Operand::DerefFp(_) => 2,
+                Operand::DerefAp(_) => 4,
+                Operand::Int(_) => 1,
             };
             let res = 0;
             let pc_update = 0;
@@ -150,31 +198,31 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
             let offop0 = match op1 {
                 Operand::DerefFp(offset) => offset,
                 Operand::DerefAp(offset) => offset,
-                Operand::Int(n) => unreachable!(),
+                Operand::Int(_) => unreachable!(),
             };
             let offop1 = match op2 {
                 Operand::DerefFp(offset) => offset,
                 Operand::DerefAp(offset) => offset,
-                Operand::Int(n) => 1,
+                Operand::Int(_) => 1,
             };
             let imm = match op2 {
-                Operand::Int(n) => Some(n),
+                Operand::Int(n) => Some(field.reduce(&n)),
                 _ => None,
             };
             let dst = match left {
-                Operand::DerefFp(offset) => 1,
-                Operand::DerefAp(offset) => 0,
+                Operand::DerefFp(_) => 1,
+                Operand::DerefAp(_) => 0,
                 _ => unreachable!(),
             };
             let op0 = match op1 {
-                Operand::DerefFp(offset) => 1,
-                Operand::DerefAp(offset) => 0,
-                Operand::Int(n) => unreachable!(),
+                Operand::DerefFp(_) => 1,
+                Operand::DerefAp(_) => 0,
+                Operand::Int(_) => unreachable!(),
             };
             let op1 = match op2 {
-                Operand::DerefFp(offset) => 2,
-                Operand::DerefAp(offset) => 4,
-                Operand::Int(n) => 1,
+                Operand::DerefFp(_) => 2,
+                Operand::DerefAp(_) => 4,
+                Operand::Int(_) => 1,
             };
             let res = 1;
             let pc_update = 0;
@@ -203,31 +251,31 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
             let offop0 = match op1 {
                 Operand::DerefFp(offset) => offset,
                 Operand::DerefAp(offset) => offset,
-                Operand::Int(n) => 1,
+                Operand::Int(_) => 1,
             };
             let offop1 = match op2 {
                 Operand::DerefFp(offset) => offset,
                 Operand::DerefAp(offset) => offset,
-                Operand::Int(n) => 1,
+                Operand::Int(_) => 1,
             };
             let imm = match op2 {
-                Operand::Int(n) => Some(n),
+                Operand::Int(n) => Some(field.reduce(&n)),
                 _ => None,
             };
             let dst = match left {
-                Operand::DerefFp(offset) => 1,
-                Operand::DerefAp(offset) => 0,
+                Operand::DerefFp(_) => 1,
+                Operand::DerefAp(_) => 0,
                 _ => unreachable!(),
             };
             let op0 = match op1 {
-                Operand::DerefFp(offset) => 1,
-                Operand::DerefAp(offset) => 0,
-                Operand::Int(n) => unreachable!(),
+                Operand::DerefFp(_) => 1,
+                Operand::DerefAp(_) => 0,
+                Operand::Int(_) => unreachable!(),
             };
             let op1 = match op2 {
-                Operand::DerefFp(offset) => 2,
-                Operand::DerefAp(offset) => 4,
-                Operand::Int(n) => 1,
+                Operand::DerefFp(_) => 2,
+                Operand::DerefAp(_) => 4,
+                Operand::Int(_) => 1,
             };
             let res = 2;
             let pc_update = 0;
@@ -247,6 +295,53 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
                 opcode,
             }
         }
+        CasmInstruction::Deref {
+            left,
+            op,
+            offset,
+            incr_ap,
+        } => {
+            let offdst = match left {
+                Operand::DerefFp(offset) => offset,
+                Operand::DerefAp(offset) => offset,
+                _ => unreachable!(),
+            };
+            let offop0 = match op {
+                Operand::DerefFp(offset) => offset,
+                Operand::DerefAp(offset) => offset,
+                _ => unreachable!(),
+            };
+            let offop1 = offset;
+            let imm = None;
+            let dst = match left {
+                Operand::DerefFp(_) => 1,
+                Operand::DerefAp(_) => 0,
+                _ => unreachable!(),
+            };
+            let op0 = match op {
+                Operand::DerefFp(_) => 1,
+                Operand::DerefAp(_) => 0,
+                _ => unreachable!(),
+            };
+            let op1 = 0;
+            let res = 0;
+            let pc_update = 0;
+            let ap_update = if incr_ap { 2 } else { 0 };
+            let opcode = 4;
+            Instruction {
+                offdst,
+                offop0,
+                offop1,
+                imm,
+                dst,
+                op0,
+                op1,
+                res,
+                pc_update,
+                ap_update,
+                opcode,
+            }
+        }
         CasmInstruction::Ret => {
             let offdst = -2;
             let offop0 = -1;
@@ -277,7 +372,7 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
             let offdst = -1;
             let offop0 = -1;
             let offop1 = 1;
-            let imm = Some(n);
+            let imm = Some(Felt::from(n));
             let dst = 1;
             let op0 = 1;
             let op1 = 1;
@@ -307,19 +402,14 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
             };
             let offop0 = -1;
             let offop1 = 1;
-            let imm = if offset < 0 {
-                0x7FFFFFFF + offset
-            } else {
-                offset
-            };
-            let imm = Some(imm as u64);
+            let imm = Some(field.reduce_i128(offset as i128));
             let dst = match op {
-                Operand::DerefFp(offset) => 2,
-                Operand::DerefAp(offset) => 4,
+                Operand::DerefFp(_) => 1,
+                Operand::DerefAp(_) => 0,
                 _ => unreachable!(),
             };
             let op0 = 1;
-            let op1 = 0;
+            let op1 = 1;
             let res = 0;
             let pc_update = 4;
             let ap_update = 0;
@@ -339,16 +429,11 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
             }
         }
         CasmInstruction::JmpRel(offset) => {
-            let offdst = 0;
-            let offop0 = 0;
+            let offdst = -1;
+            let offop0 = -1;
             let offop1 = 1;
-            let imm = if offset < 0 {
-                0x7FFFFFFF + offset
-            } else {
-                offset
-            };
-            let imm = Some(imm as u64);
-            let dst = 0;
+            let imm = Some(field.reduce_i128(offset as i128));
+            let dst = 1;
             let op0 = 1;
             let op1 = 1;
             let res = 0;
@@ -369,22 +454,60 @@ pub fn build_instruction(instruction: CasmInstruction) -> Instruction {
                 opcode,
             }
         }
-        _ => todo!(),
-    }
+        instruction => return Err(format!("'{:?}' is not a Cairo instruction", instruction)),
+    })
 }
 
-fn nops(i: CasmInstruction) -> u64 {
-    let bytecode = build_instruction(i);
-    if let Some(imm) = bytecode.imm {
-        return 2;
+// the instructions that cannot be encoded take a cell until build_instructions reports them
+fn nops(i: CasmInstruction, field: &Field) -> u64 {
+    match build_instruction(i, field) {
+        Ok(bytecode) if bytecode.imm.is_some() => 2,
+        _ => 1,
     }
-    return 1;
 }
 
 pub struct Assembler {
     pub casm: Vec<CasmInstruction>,
     pub instructions: Vec<Instruction>,
     pub function_adresses: HashMap<String, u64>,
+    pub hints: BTreeMap<u64, Vec<Hint>>,
+    pub builtins: Vec<String>,
+    pub field: Field,
+    // source span of the instructions starting at each pc
+    pub locations: BTreeMap<u64, Span>,
+    // file name and contents the locations refer to, needed for debug_info
+    pub source: Option<(String, String)>,
+    // labels that start a function, the other ones are jump targets within it
+    pub functions: Vec<String>,
+}
+
+// Formats an fp-relative reference the way cairo-lang writes it in the reference manager.
+fn fp_reference(offset: i32) -> String {
+    match offset {
+        0 => "[cast(fp, felt*)]".to_string(),
+        n if n < 0 => format!("[cast(fp + ({}), felt*)]", n),
+        n => format!("[cast(fp + {}, felt*)]", n),
+    }
+}
+
+// Reads back a reference written by `fp_reference`.
+pub fn parse_fp_reference(value: &str) -> Option<i32> {
+    let inner = value.strip_prefix("[cast(fp")?.strip_suffix(", felt*)]")?;
+    let offset = inner.trim().strip_prefix('+').unwrap_or(inner).trim();
+    if offset.is_empty() {
+        return Some(0);
+    }
+    let offset = offset
+        .strip_prefix('(')
+        .and_then(|offset| offset.strip_suffix(')'))
+        .unwrap_or(offset);
+    offset.parse().ok()
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Assembler {
@@ -393,10 +516,36 @@ impl Assembler {
             casm: Vec::new(),
             instructions: Vec::new(),
             function_adresses: HashMap::new(),
+            hints: BTreeMap::new(),
+            builtins: Vec::new(),
+            field: Field::M31,
+            locations: BTreeMap::new(),
+            source: None,
+            functions: Vec::new(),
+        }
+    }
+
+    // Offset from pc to a label, which must be defined.
+    fn relative_offset(
+        &self,
+        label: &str,
+        pc: u64,
+        location: Option<Span>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> i32 {
+        if let Some(address) = self.function_adresses.get(label) {
+            return *address as i32 - pc as i32;
+        }
+        let mut diagnostic =
+            Diagnostic::error(codes::UNKNOWN_LABEL, format!("Unknown label '{}'", label));
+        if let Some(span) = location {
+            diagnostic = diagnostic.with_span(span);
         }
+        diagnostics.push(diagnostic);
+        0
     }
 
-    pub fn resolve_jumps(&mut self) {
+    pub fn resolve_jumps(&mut self) -> Result<(), Diagnostics> {
         let mut new = Vec::new();
         let mut instruction_number = 0;
         //let mut function_adresses = HashMap::new();
@@ -405,72 +554,193 @@ impl Assembler {
                 CasmInstruction::Label(label) => {
                     self.function_adresses.insert(label, instruction_number);
                 }
-                CasmInstruction::Call(label) => {
+                CasmInstruction::Hint(_) | CasmInstruction::Location(_) => {}
+                CasmInstruction::Call(_) => {
                     instruction_number += 2;
                 }
-                CasmInstruction::Jmp(label) => {
+                CasmInstruction::Jmp(_) => {
                     instruction_number += 2;
                 }
-                CasmInstruction::JmpIfNeq(label, op) => {
+                CasmInstruction::JmpIfNeq(_, _) => {
                     instruction_number += 2;
                 }
                 _ => {
-                    instruction_number += nops(instruction.clone());
+                    instruction_number += nops(instruction.clone(), &self.field);
                 }
             }
         }
         instruction_number = 0;
+        let mut location = None;
+        let mut diagnostics = Vec::new();
         for instruction in self.casm.clone() {
             match instruction {
                 CasmInstruction::Call(label) => {
-                    new.push(CasmInstruction::CallRel(
-                        self.function_adresses[&label] as i32 - instruction_number as i32,
-                    ));
+                    new.push(CasmInstruction::CallRel(self.relative_offset(
+                        &label,
+                        instruction_number,
+                        location,
+                        &mut diagnostics,
+                    )));
                     instruction_number += 2;
                 }
-                CasmInstruction::Label(label) => {}
+                CasmInstruction::Label(_) => {}
+                CasmInstruction::Hint(hint) => {
+                    // hints are attached to the pc of the next instruction
+                    self.hints.entry(instruction_number).or_default().push(hint);
+                }
+                CasmInstruction::Location(span) => {
+                    self.locations.insert(instruction_number, span);
+                    location = Some(span);
+                }
                 CasmInstruction::Jmp(label) => {
-                    new.push(CasmInstruction::JmpRel(
-                        self.function_adresses[&label] as i32 - instruction_number as i32,
-                    ));
+                    new.push(CasmInstruction::JmpRel(self.relative_offset(
+                        &label,
+                        instruction_number,
+                        location,
+                        &mut diagnostics,
+                    )));
                     instruction_number += 2;
                 }
                 CasmInstruction::JmpIfNeq(label, op) => {
                     new.push(CasmInstruction::JmpIfNeqRel(
-                        self.function_adresses[&label] as i32 - instruction_number as i32,
+                        self.relative_offset(
+                            &label,
+                            instruction_number,
+                            location,
+                            &mut diagnostics,
+                        ),
                         op,
                     ));
                     instruction_number += 2;
                 }
                 _ => {
                     new.push(instruction.clone());
-                    instruction_number += nops(instruction);
+                    instruction_number += nops(instruction, &self.field);
                 }
             }
         }
         self.casm = new;
+        match diagnostics.is_empty() {
+            true => Ok(()),
+            false => Err(Diagnostics(diagnostics)),
+        }
     }
 
-    pub fn build_instructions(&mut self) {
-        for instruction in self.casm.clone() {
-            self.instructions.push(build_instruction(instruction));
+    // The error is located on the code the instruction was compiled from, if known.
+    pub fn build_instructions(&mut self) -> Result<(), Diagnostics> {
+        let mut pc = 0;
+        for casm_instruction in self.casm.clone() {
+            let instruction = build_instruction(casm_instruction.clone(), &self.field)
+                .and_then(|instruction| instruction.check_offsets().map(|_| instruction));
+            let instruction = match instruction {
+                Ok(instruction) => instruction,
+                Err(error) => {
+                    let mut diagnostic = Diagnostic::error(
+                        codes::ASSEMBLY_ERROR,
+                        format!("Cannot encode {:?}", casm_instruction),
+                    )
+                    .with_note(format!("{} at pc {}", error, pc));
+                    if let Some((_, span)) = self.locations.range(..=pc).next_back() {
+                        diagnostic = diagnostic.with_span(*span);
+                    }
+                    return Err(Diagnostics(vec![diagnostic]));
+                }
+            };
+            pc += if instruction.imm.is_some() { 2 } else { 1 };
+            self.instructions.push(instruction);
         }
+        Ok(())
+    }
+
+    // Source location of every instruction, in the debug_info format of cairo-lang.
+    fn debug_info(&self) -> json::JsonValue {
+        let mut debug_info = json::JsonValue::new_object();
+        debug_info["file_contents"] = json::JsonValue::new_object();
+        debug_info["instruction_locations"] = json::JsonValue::new_object();
+        let Some((file_name, source)) = &self.source else {
+            return debug_info;
+        };
+        debug_info["file_contents"][file_name.as_str()] = json::JsonValue::from(source.as_str());
+        let functions: BTreeMap<u64, &String> = self
+            .functions
+            .iter()
+            .filter_map(|name| Some((*self.function_adresses.get(name)?, name)))
+            .collect();
+        let mut pc = 0;
+        for instruction in self.instructions.iter() {
+            if let Some((_, span)) = self.locations.range(..=pc).next_back() {
+                let (start_line, start_col) = line_col(source, span.0);
+                let (end_line, end_col) = line_col(source, span.1);
+                let mut location = json::JsonValue::new_object();
+                location["accessible_scopes"] = json::JsonValue::new_array();
+                let function = functions.range(..=pc).next_back();
+                for scope in accessible_scopes(function.map_or("", |(_, name)| name.as_str())) {
+                    let _ = location["accessible_scopes"].push(scope);
+                }
+                location["flow_tracking_data"]["ap_tracking"]["group"] = json::JsonValue::from(0);
+                location["flow_tracking_data"]["ap_tracking"]["offset"] = json::JsonValue::from(0);
+                location["flow_tracking_data"]["reference_ids"] = json::JsonValue::new_object();
+                location["hints"] = json::JsonValue::new_array();
+                location["inst"]["end_col"] = json::JsonValue::from(end_col);
+                location["inst"]["end_line"] = json::JsonValue::from(end_line);
+                location["inst"]["input_file"]["filename"] =
+                    json::JsonValue::from(file_name.as_str());
+                location["inst"]["start_col"] = json::JsonValue::from(start_col);
+                location["inst"]["start_line"] = json::JsonValue::from(start_line);
+                debug_info["instruction_locations"][pc.to_string()] = location;
+            }
+            pc += if instruction.imm.is_some() { 2 } else { 1 };
+        }
+        debug_info
     }
 
     pub fn to_json(&self) -> String {
         let mut data = json::JsonValue::new_object();
         data["attributes"] = json::JsonValue::new_array();
         data["builtins"] = json::JsonValue::new_array();
+        for builtin in self.builtins.iter() {
+            let _ = data["builtins"].push(builtin.clone());
+        }
         data["compiler_version"] = json::JsonValue::from("0.1");
+        data["debug_info"] = self.debug_info();
         data["data"] = json::JsonValue::new_array();
         for instruction in self.instructions.clone() {
             let (bytes, imm) = instruction.to_bytes();
-            data["data"].push(format!("{:#x}", bytes));
+            let _ = data["data"].push(format!("{:#x}", bytes));
             if let Some(imm) = imm {
-                data["data"].push(format!("{:#x}", imm));
+                let _ = data["data"].push(format!("{:#x}", imm));
             }
         }
         data["hints"] = json::JsonValue::new_object();
+        let mut references = json::JsonValue::new_array();
+        for (pc, hints) in self.hints.clone() {
+            let mut hint_list = json::JsonValue::new_array();
+            for hint in hints {
+                let mut hint_data = json::JsonValue::new_object();
+                hint_data["accessible_scopes"] = json::JsonValue::new_array();
+                for scope in hint.accessible_scopes.clone() {
+                    let _ = hint_data["accessible_scopes"].push(scope);
+                }
+                hint_data["code"] = json::JsonValue::from(hint.code);
+                hint_data["flow_tracking_data"] = json::JsonValue::new_object();
+                hint_data["flow_tracking_data"]["ap_tracking"]["group"] = json::JsonValue::from(0);
+                hint_data["flow_tracking_data"]["ap_tracking"]["offset"] = json::JsonValue::from(0);
+                hint_data["flow_tracking_data"]["reference_ids"] = json::JsonValue::new_object();
+                let scope = hint.accessible_scopes.last().unwrap();
+                for (name, offset) in hint.references {
+                    let mut reference = json::JsonValue::new_object();
+                    reference["ap_tracking_data"]["group"] = json::JsonValue::from(0);
+                    reference["ap_tracking_data"]["offset"] = json::JsonValue::from(0);
+                    reference["pc"] = json::JsonValue::from(pc);
+                    reference["value"] = json::JsonValue::from(fp_reference(offset));
+                    hint_data["flow_tracking_data"]["reference_ids"]
+                        [format!("{}.{}", scope, name)] = json::JsonValue::from(references.len());
+                    let _ = references.push(reference);
+                }
+                let _ = hint_list.push(hint_data);
+            }
+            data["hints"][pc.to_string()] = hint_list;
+        }
         data["identifiers"] = json::JsonValue::new_object();
         for (label, address) in self.function_adresses.clone() {
             let label2 = format!("__main__.{}", label);
@@ -480,9 +750,9 @@ impl Assembler {
             data["identifiers"][label2.clone()]["type"] = json::JsonValue::from("function");
         }
         data["main_scope"] = json::JsonValue::from("__main__");
-        data["prime"] = json::JsonValue::from("0x7fffffff");
+        data["prime"] = json::JsonValue::from(format!("{:#x}", self.field.prime));
         data["reference_manager"] = json::JsonValue::new_object();
-        data["reference_manager"]["references"] = json::JsonValue::new_array();
+        data["reference_manager"]["references"] = references;
         data.to_string()
     }
 }
@@ -59,6 +59,12 @@ pub struct Identifier {
     pub token: Token,
 }
 
+#[derive(Clone)]
+pub struct TypedIdentifier {
+    pub ident: Identifier,
+    pub type_: Option<Type>,
+}
+
 #[derive(Clone)]
 pub enum ExprAssignment {
     Expr(Expr),
@@ -91,29 +97,48 @@ pub enum InstructionType {
     DataWord,
 }
 
+// Functions
+
+#[derive(Clone)]
+pub struct Function {
+    // `@name` lines before `func`
+    pub decorators: Vec<Identifier>,
+    pub name: Identifier,
+    pub implicit_args: Vec<TypedIdentifier>,
+    pub args: Vec<TypedIdentifier>,
+    pub return_type: Option<Type>,
+    pub body: Vec<CodeElement>,
+    // the `//` comment block right above the declaration, without the slashes
+    pub doc: Option<String>,
+}
+
+#[allow(dead_code)]
 #[derive(Clone)]
 pub enum CodeElement {
     Instruction(Instruction),
-    Const,
-    Reference(Identifier, Expr),
-    LocalVar(Identifier, Option<Expr>),
+    // name, value and doc comment
+    Const(Identifier, Expr, Option<String>),
+    Reference(TypedIdentifier, Expr),
+    LocalVar(TypedIdentifier, Option<Expr>),
     TempVar,
     CompoundAssertEqual(Expr, Expr),
     StaticAssert,
     Return(Expr),
     If(Expr, Vec<CodeElement>, Vec<CodeElement>),
-    FuncCall,
+    FuncCall(Expr),
     Label,
-    Function(Identifier, Vec<Identifier>, Vec<CodeElement>),
-    Struct,
-    NameSpace,
+    Function(Function),
+    // name, members and doc comment
+    Struct(Identifier, Vec<TypedIdentifier>, Option<String>),
+    // name, members and doc comment
+    NameSpace(Identifier, Vec<CodeElement>, Option<String>),
     TypeDef,
     WithAttr,
     With,
-    Hint,
-    Directive,
+    Hint(Token),
+    Directive(Token, Vec<Identifier>),
     Import,
-    AllocLocals,
+    AllocLocals(Token),
 }
 
 impl Expr {
@@ -225,6 +250,86 @@ impl Expr {
     }
 }
 
+// Source spans, as byte ranges in the input
+
+pub type Span = (usize, usize);
+
+// 1-based line and column of a byte offset
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn token_span(token: &Token) -> Option<Span> {
+    // tokens made up by the compiler have an empty span
+    if token.span.0 < token.span.1 {
+        Some(token.span)
+    } else {
+        None
+    }
+}
+
+fn join_spans(a: Option<Span>, b: Option<Span>) -> Option<Span> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.max(b.1))),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+impl Expr {
+    // Smallest span covering the tokens of the expression.
+    pub fn span(&self) -> Option<Span> {
+        let mut span = self.token.as_ref().and_then(token_span);
+        if let Some(ident) = &self.ident {
+            span = join_spans(span, token_span(&ident.token));
+        }
+        for child in [&self.left, &self.right].into_iter().flatten() {
+            span = join_spans(span, child.span());
+        }
+        for arg in self.paren_args.iter().chain(self.brace_args.iter()) {
+            span = join_spans(
+                span,
+                match arg {
+                    ExprAssignment::Expr(expr) => expr.span(),
+                    ExprAssignment::Assign(ident, expr) => {
+                        join_spans(token_span(&ident.token), expr.span())
+                    }
+                },
+            );
+        }
+        span
+    }
+}
+
+impl CodeElement {
+    // Span of a statement, functions and blocks excluded.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CodeElement::Instruction(instruction) => instruction
+                .args
+                .iter()
+                .fold(None, |span, arg| join_spans(span, arg.span())),
+            CodeElement::Reference(ident, expr) => {
+                join_spans(token_span(&ident.ident.token), expr.span())
+            }
+            CodeElement::LocalVar(ident, expr) => join_spans(
+                token_span(&ident.ident.token),
+                expr.as_ref().and_then(|expr| expr.span()),
+            ),
+            CodeElement::CompoundAssertEqual(left, right) => join_spans(left.span(), right.span()),
+            CodeElement::Return(expr) | CodeElement::FuncCall(expr) => expr.span(),
+            CodeElement::If(condition, _, _) => condition.span(),
+            CodeElement::Hint(token) | CodeElement::AllocLocals(token) => token_span(token),
+            _ => None,
+        }
+    }
+}
+
 impl Instruction {
     pub fn new_unary(instruction_type: InstructionType, child: Expr, increment_ap: bool) -> Self {
         Self {
@@ -345,6 +450,25 @@ impl Debug for Type {
     }
 }
 
+impl Debug for TypedIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with_indent(f, 0)
+    }
+}
+
+impl TypedIdentifier {
+    fn fmt_with_indent(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        write!(f, "{:indent$}", "", indent = indent * 2)?;
+        write!(f, "'{}'", self.ident.token.lexeme)?;
+        if let Some(type_) = &self.type_ {
+            write!(f, ":")?;
+            writeln!(f)?;
+            type_.fmt_with_indent(f, indent + 1)?;
+        }
+        Ok(())
+    }
+}
+
 impl Debug for ExprAssignment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.fmt_with_indent(f, 0)
@@ -415,7 +539,7 @@ impl Expr {
             }
 
             // Print args if present
-            for (i, arg) in self.paren_args.iter().enumerate() {
+            for arg in self.paren_args.iter() {
                 arg.fmt_with_indent(f, indent + 1)?;
                 writeln!(f)?;
             }
@@ -480,14 +604,18 @@ impl CodeElement {
                 writeln!(f)?;
                 instr.fmt_with_indent(f, indent + 1)
             }
-            CodeElement::Const => write!(f, "Const"),
+            CodeElement::Const(name, value, _) => {
+                write!(f, "Const '{}' = ", name.token.lexeme)?;
+                writeln!(f)?;
+                value.fmt_with_indent(f, indent + 1)
+            }
             CodeElement::Reference(ident, expr) => {
-                write!(f, "Reference '{}' = ", ident.token.lexeme)?;
+                write!(f, "Reference '{}' = ", ident.ident.token.lexeme)?;
                 writeln!(f)?;
                 expr.fmt_with_indent(f, indent + 1)
             }
             CodeElement::LocalVar(ident, expr) => {
-                write!(f, "LocalVar '{}' = ", ident.token.lexeme)?;
+                write!(f, "LocalVar '{}' = ", ident.ident.token.lexeme)?;
                 writeln!(f)?;
                 if let Some(expr) = expr {
                     expr.fmt_with_indent(f, indent + 1)
@@ -540,42 +668,86 @@ impl CodeElement {
                 }
                 Ok(())
             }
-            CodeElement::FuncCall => write!(f, "FuncCall"),
+            CodeElement::FuncCall(expr) => {
+                write!(f, "FuncCall")?;
+                writeln!(f)?;
+                expr.fmt_with_indent(f, indent + 1)
+            }
             CodeElement::Label => write!(f, "Label"),
-            CodeElement::Function(ident, args, body) => {
-                write!(f, "Function '{}'", ident.token.lexeme)?;
+            CodeElement::Function(function) => {
+                write!(f, "Function '{}'", function.name.token.lexeme)?;
                 writeln!(f)?;
+                for decorator in function.decorators.iter() {
+                    write!(f, "{:indent$}", "", indent = (indent + 1) * 2)?;
+                    write!(f, "Decorator '{}'", decorator.token.lexeme)?;
+                    writeln!(f)?;
+                }
+                if !function.implicit_args.is_empty() {
+                    write!(f, "{:indent$}", "", indent = (indent + 1) * 2)?;
+                    write!(f, "Implicit arguments:")?;
+                    writeln!(f)?;
+                    for arg in function.implicit_args.iter() {
+                        arg.fmt_with_indent(f, indent + 2)?;
+                        writeln!(f)?;
+                    }
+                }
                 write!(f, "{:indent$}", "", indent = (indent + 1) * 2)?;
                 write!(f, "Arguments:")?;
                 writeln!(f)?;
-                for (i, arg) in args.iter().enumerate() {
-                    write!(f, "{:indent$}", "", indent = (indent + 2) * 2)?;
-                    write!(f, "'{}'", arg.token.lexeme)?;
-                    if i < args.len() - 1 {
+                for (i, arg) in function.args.iter().enumerate() {
+                    arg.fmt_with_indent(f, indent + 2)?;
+                    if i < function.args.len() - 1 {
                         writeln!(f)?;
                     }
                 }
                 writeln!(f)?;
+                if let Some(return_type) = &function.return_type {
+                    write!(f, "{:indent$}", "", indent = (indent + 1) * 2)?;
+                    write!(f, "Returns:")?;
+                    writeln!(f)?;
+                    return_type.fmt_with_indent(f, indent + 2)?;
+                    writeln!(f)?;
+                }
                 write!(f, "{:indent$}", "", indent = (indent + 1) * 2)?;
                 write!(f, "Body:")?;
                 writeln!(f)?;
-                for (i, elem) in body.iter().enumerate() {
+                for (i, elem) in function.body.iter().enumerate() {
                     elem.fmt_with_indent(f, indent + 2)?;
-                    if i < body.len() - 1 {
+                    if i < function.body.len() - 1 {
                         writeln!(f)?;
                     }
                 }
                 Ok(())
             }
-            CodeElement::Struct => write!(f, "Struct"),
-            CodeElement::NameSpace => write!(f, "NameSpace"),
+            CodeElement::Struct(name, members, _) => {
+                write!(f, "Struct '{}'", name.token.lexeme)?;
+                for member in members.iter() {
+                    writeln!(f)?;
+                    member.fmt_with_indent(f, indent + 1)?;
+                }
+                Ok(())
+            }
+            CodeElement::NameSpace(name, members, _) => {
+                write!(f, "NameSpace '{}'", name.token.lexeme)?;
+                for member in members.iter() {
+                    writeln!(f)?;
+                    member.fmt_with_indent(f, indent + 1)?;
+                }
+                Ok(())
+            }
             CodeElement::TypeDef => write!(f, "TypeDef"),
             CodeElement::WithAttr => write!(f, "WithAttr"),
             CodeElement::With => write!(f, "With"),
-            CodeElement::Hint => write!(f, "Hint"),
-            CodeElement::Directive => write!(f, "Directive"),
+            CodeElement::Hint(token) => write!(f, "Hint '{}'", token.lexeme),
+            CodeElement::Directive(token, args) => {
+                write!(f, "Directive '{}'", token.lexeme)?;
+                for arg in args.iter() {
+                    write!(f, " '{}'", arg.token.lexeme)?;
+                }
+                Ok(())
+            }
             CodeElement::Import => write!(f, "Import"),
-            CodeElement::AllocLocals => write!(f, "AllocLocals"),
+            CodeElement::AllocLocals(_) => write!(f, "AllocLocals"),
         }
     }
 }
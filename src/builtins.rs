@@ -0,0 +1,151 @@
+use crate::felt::{Felt, Field};
+use crate::minivm::{Memory, Relocatable, Value};
+use crate::poseidon::{self, STATE_WIDTH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuiltinKind {
+    Output,
+    RangeCheck,
+    Bitwise,
+    Poseidon,
+}
+
+impl BuiltinKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "output" => Some(BuiltinKind::Output),
+            "range_check" => Some(BuiltinKind::RangeCheck),
+            "bitwise" => Some(BuiltinKind::Bitwise),
+            "poseidon" => Some(BuiltinKind::Poseidon),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuiltinKind::Output => "output",
+            BuiltinKind::RangeCheck => "range_check",
+            BuiltinKind::Bitwise => "bitwise",
+            BuiltinKind::Poseidon => "poseidon",
+        }
+    }
+
+    // Number of memory cells used by one application of the builtin.
+    pub fn cells_per_instance(&self) -> u64 {
+        match self {
+            BuiltinKind::Output => 1,
+            BuiltinKind::RangeCheck => 1,
+            // x, y, x & y, x ^ y, x | y
+            BuiltinKind::Bitwise => 5,
+            // input state, then the permuted state
+            BuiltinKind::Poseidon => 2 * STATE_WIDTH as u64,
+        }
+    }
+}
+
+// Checked on every write to the segment of a builtin.
+pub type ValidationRule = Box<dyn Fn(Relocatable, Value) -> Result<(), String>>;
+
+fn validate_range_check(bound: Felt) -> ValidationRule {
+    Box::new(move |addr, value| match value {
+        Value::Int(n) if n < bound => Ok(()),
+        _ => Err(format!(
+            "Range check failed at {}: {} is not in [0, {})",
+            addr, value, bound
+        )),
+    })
+}
+
+fn validate_integer(addr: Relocatable, value: Value) -> Result<(), String> {
+    match value {
+        Value::Int(_) => Ok(()),
+        Value::Ptr(_) => Err(format!(
+            "Builtin cell {}: {} is not an integer",
+            addr, value
+        )),
+    }
+}
+
+pub struct BuiltinRunner {
+    pub kind: BuiltinKind,
+    pub base: Relocatable,
+}
+
+impl BuiltinRunner {
+    pub fn new(kind: BuiltinKind, base: Relocatable) -> Self {
+        Self { kind, base }
+    }
+
+    pub fn validation_rule(&self, field: &Field) -> Option<ValidationRule> {
+        match self.kind {
+            BuiltinKind::Output => None,
+            BuiltinKind::RangeCheck => Some(validate_range_check(field.range_check_bound())),
+            BuiltinKind::Bitwise | BuiltinKind::Poseidon => Some(Box::new(validate_integer)),
+        }
+    }
+
+    // Computes the output cells of the builtin from its input cells.
+    pub fn deduce(
+        &self,
+        memory: &Memory,
+        addr: Relocatable,
+        field: &Field,
+    ) -> Result<Option<Value>, String> {
+        match self.kind {
+            BuiltinKind::Bitwise => Ok(self.deduce_bitwise(memory, addr)),
+            BuiltinKind::Poseidon => self.deduce_poseidon(memory, addr, field),
+            _ => Ok(None),
+        }
+    }
+
+    // Reads the first n cells of the instance containing addr, if they are all integers.
+    fn inputs(&self, memory: &Memory, addr: Relocatable, n: u64) -> Option<Vec<Felt>> {
+        let index = addr.offset % self.kind.cells_per_instance();
+        let instance = Relocatable::new(addr.segment, addr.offset - index);
+        (0..n)
+            .map(
+                |i| match memory.get(Relocatable::new(instance.segment, instance.offset + i)) {
+                    Some(Value::Int(x)) => Some(x),
+                    _ => None,
+                },
+            )
+            .collect()
+    }
+
+    fn deduce_bitwise(&self, memory: &Memory, addr: Relocatable) -> Option<Value> {
+        let index = addr.offset % self.kind.cells_per_instance();
+        if index < 2 {
+            return None;
+        }
+        let inputs = self.inputs(memory, addr, 2)?;
+        let (x, y) = (inputs[0], inputs[1]);
+        let value = match index {
+            2 => x & y,
+            3 => x ^ y,
+            _ => x | y,
+        };
+        Some(Value::Int(value))
+    }
+
+    fn deduce_poseidon(
+        &self,
+        memory: &Memory,
+        addr: Relocatable,
+        field: &Field,
+    ) -> Result<Option<Value>, String> {
+        let index = (addr.offset % self.kind.cells_per_instance()) as usize;
+        if index < STATE_WIDTH {
+            return Ok(None);
+        }
+        let Some(inputs) = self.inputs(memory, addr, STATE_WIDTH as u64) else {
+            return Ok(None);
+        };
+        let mut state = [0; STATE_WIDTH];
+        for (x, input) in state.iter_mut().zip(inputs) {
+            // the permutation is defined over M31, see load_program
+            *x = field.reduce(&input).to_u64().unwrap();
+        }
+        poseidon::permute(&mut state);
+        Ok(Some(Value::Int(Felt::from(state[index - STATE_WIDTH]))))
+    }
+}
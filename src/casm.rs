@@ -1,14 +1,19 @@
+use crate::ast::Span;
+use crate::felt::Felt;
 use std::fmt::{self, Debug, Display};
 
+#[allow(dead_code)]
 #[derive(Clone)]
 pub enum CasmInstruction {
     Ret,
     Call(String),
     CallRel(i32),
-    CallAbs(u64),
-    IncrFp(u64),
+    CallAbs(Felt),
     IncrAp(u64),
     Label(String),
+    Hint(Hint),
+    // the following instructions come from this span of the source
+    Location(Span),
     Set {
         left: Operand,
         op: Operand,
@@ -24,9 +29,12 @@ pub enum CasmInstruction {
         op1: Operand,
         op2: Operand,
     },
+    // left = [[op] + offset]
     Deref {
         left: Operand,
         op: Operand,
+        offset: i32,
+        incr_ap: bool,
     },
     Jmp(String),
     JmpRel(i32),
@@ -34,9 +42,29 @@ pub enum CasmInstruction {
     JmpIfNeqRel(i32, Operand),
 }
 
+// A hint runs before the instruction that follows it in the casm stream.
+// `references` lists the identifiers visible to the hint as `ids.<name>`,
+// together with their offset from fp.
+#[derive(Clone, Debug)]
+pub struct Hint {
+    pub code: String,
+    pub accessible_scopes: Vec<String>,
+    pub references: Vec<(String, i32)>,
+}
+
+// `__main__` and the scopes of the namespaces of `function` down to its own, `math.double`
+// giving `__main__`, `__main__.math` and `__main__.math.double`.
+pub fn accessible_scopes(function: &str) -> Vec<String> {
+    let mut scopes = vec!["__main__".to_string()];
+    for part in function.split('.').filter(|part| !part.is_empty()) {
+        scopes.push(format!("{}.{}", scopes[scopes.len() - 1], part));
+    }
+    scopes
+}
+
 #[derive(Clone)]
 pub enum Operand {
-    Int(u64),
+    Int(Felt),
     DerefFp(i32),
     DerefAp(i32),
 }
@@ -68,9 +96,27 @@ impl Debug for CasmInstruction {
             CasmInstruction::Call(label) => write!(f, "call {};", label),
             CasmInstruction::CallRel(offset) => write!(f, "call rel {};", offset),
             CasmInstruction::CallAbs(offset) => write!(f, "call abs {};", offset),
-            CasmInstruction::IncrFp(n) => write!(f, "fp += {};", n),
             CasmInstruction::IncrAp(n) => write!(f, "ap += {};", n),
             CasmInstruction::Label(label) => write!(f, "{}:", label),
+            // the innermost scope is written, the outer ones are its prefixes
+            CasmInstruction::Hint(hint) => {
+                write!(f, "%{{ {} %}}", hint.code)?;
+                if let Some(scope) = hint.accessible_scopes.last()
+                    && scope != "__main__"
+                {
+                    write!(f, " scope({})", scope)?;
+                }
+                if !hint.references.is_empty() {
+                    let ids: Vec<String> = hint
+                        .references
+                        .iter()
+                        .map(|(name, offset)| format!("{}={}", name, Operand::DerefFp(*offset)))
+                        .collect();
+                    write!(f, " ids({})", ids.join(", "))?;
+                }
+                Ok(())
+            }
+            CasmInstruction::Location((start, end)) => write!(f, "// {}..{}", start, end),
             CasmInstruction::Set { left, op, incr_ap } => {
                 write!(
                     f,
@@ -86,8 +132,20 @@ impl Debug for CasmInstruction {
             CasmInstruction::Mul { left, op1, op2 } => {
                 write!(f, "{} = {} * {}, ap++;", left, op1, op2)
             }
-            CasmInstruction::Deref { left, op } => {
-                write!(f, "{} = {};", left, op)
+            CasmInstruction::Deref {
+                left,
+                op,
+                offset,
+                incr_ap,
+            } => {
+                write!(
+                    f,
+                    "{} = [{} + {}]{}",
+                    left,
+                    op,
+                    offset,
+                    if *incr_ap { ", ap++;" } else { ";" }
+                )
             }
             CasmInstruction::Jmp(label) => {
                 write!(f, "jmp {};", label)
@@ -95,11 +153,11 @@ impl Debug for CasmInstruction {
             CasmInstruction::JmpRel(offset) => {
                 write!(f, "jmp rel {};", offset)
             }
-            CasmInstruction::JmpIfNeq(offset, op) => {
-                write!(f, "jmp rel {} if {} != 0", offset, op)
+            CasmInstruction::JmpIfNeq(label, op) => {
+                write!(f, "jmp {} if {} != 0;", label, op)
             }
             CasmInstruction::JmpIfNeqRel(offset, op) => {
-                write!(f, "jmp rel {} if {} != 0", offset, op)
+                write!(f, "jmp rel {} if {} != 0;", offset, op)
             }
         }
     }
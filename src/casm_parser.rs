@@ -0,0 +1,289 @@
+// Parses casm in the textual form printed by `--emit casm` and `czc disasm`:
+// one instruction per `;`, `label:` lines, `// comments`, a `%builtins` line and
+// `%{ hint %}` blocks, optionally followed by `scope(__main__.f)` and the references
+// of the hint as `ids(x=[fp + 0], y=[fp + -3])`.
+
+use crate::casm::{CasmInstruction, Hint, Operand};
+use crate::felt::{Felt, Field};
+
+pub struct CasmProgram {
+    pub instructions: Vec<CasmInstruction>,
+    pub builtins: Vec<String>,
+}
+
+pub fn parse(text: &str, field: &Field) -> Result<CasmProgram, String> {
+    let mut program = CasmProgram {
+        instructions: Vec::new(),
+        builtins: Vec::new(),
+    };
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start();
+        let line = text[..text.len() - rest.len()].matches('\n').count() + 1;
+        let error = |message: String| format!("line {}: {}", line, message);
+        if rest.is_empty() {
+            return Ok(program);
+        } else if rest.starts_with("//") {
+            rest = rest.split_once('\n').map_or("", |(_, rest)| rest);
+        } else if let Some(hint) = rest.strip_prefix("%{") {
+            let (code, after) = hint
+                .split_once("%}")
+                .ok_or(error("Unterminated hint".to_string()))?;
+            let mut hint = Hint {
+                code: code.trim().to_string(),
+                accessible_scopes: vec!["__main__".to_string()],
+                references: Vec::new(),
+            };
+            rest = after;
+            if let Some((scope, after)) = annotation(rest, "scope") {
+                let parts: Vec<&str> = scope.trim().split('.').collect();
+                hint.accessible_scopes = (1..=parts.len()).map(|n| parts[..n].join(".")).collect();
+                rest = after;
+            }
+            if let Some((ids, after)) = annotation(rest, "ids") {
+                for reference in ids.split(',') {
+                    let (name, value) = reference
+                        .split_once('=')
+                        .ok_or(error(format!("Invalid reference '{}'", reference.trim())))?;
+                    let Operand::DerefFp(offset) = operand(value, field).map_err(error)? else {
+                        return Err(error(format!(
+                            "Hint references are [fp + k], got '{}'",
+                            value.trim()
+                        )));
+                    };
+                    hint.references.push((name.trim().to_string(), offset));
+                }
+                rest = after;
+            }
+            program.instructions.push(CasmInstruction::Hint(hint));
+        } else if let Some(directive) = rest.strip_prefix("%builtins") {
+            let (names, after) = directive.split_once('\n').unwrap_or((directive, ""));
+            program
+                .builtins
+                .extend(names.split_whitespace().map(|name| name.to_string()));
+            rest = after;
+        } else {
+            let end = rest
+                .find([';', ':', '\n'])
+                .ok_or(error(format!("Expected ';' after '{}'", rest.trim())))?;
+            let statement = rest[..end].trim();
+            match rest.as_bytes()[end] {
+                b':' if is_label(statement) => {
+                    program
+                        .instructions
+                        .push(CasmInstruction::Label(statement.to_string()));
+                }
+                b';' => program
+                    .instructions
+                    .push(instruction(statement, field).map_err(error)?),
+                _ => return Err(error(format!("Expected ';' after '{}'", statement))),
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+}
+
+// `name(...)` at the start of the line, with the text between the parentheses.
+fn annotation<'a>(s: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    s.trim_start_matches([' ', '\t'])
+        .strip_prefix(name)?
+        .strip_prefix('(')?
+        .split_once(')')
+}
+
+// Labels of the members of namespaces are dotted: `math.double`.
+fn is_label(s: &str) -> bool {
+    s.split('.').all(|part| {
+        part.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+fn integer(s: &str, field: &Field) -> Result<Felt, String> {
+    let s = s.trim();
+    match s.strip_prefix('-') {
+        Some(n) => n.trim().parse().map(|n| field.neg(&field.reduce(&n))),
+        None => s.parse().map(|n| field.reduce(&n)),
+    }
+}
+
+fn offset(s: &str) -> Result<i32, String> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    s.parse().map_err(|_| format!("Invalid offset '{}'", s))
+}
+
+// [fp + k], [ap + k], [fp - k], [fp], or an integer.
+fn operand(s: &str, field: &Field) -> Result<Operand, String> {
+    let s = s.trim();
+    let Some(inner) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) else {
+        return integer(s, field).map(Operand::Int);
+    };
+    let inner = inner.trim();
+    let (register, rest) = inner.split_at(inner.len().min(2));
+    let rest = rest.trim();
+    let offset = if rest.is_empty() {
+        0
+    } else if let Some(n) = rest.strip_prefix('+') {
+        offset(n)?
+    } else if let Some(n) = rest.strip_prefix('-') {
+        -offset(n)?
+    } else {
+        return Err(format!("Invalid operand '{}'", s));
+    };
+    match register {
+        "fp" => Ok(Operand::DerefFp(offset)),
+        "ap" => Ok(Operand::DerefAp(offset)),
+        _ => Err(format!("Invalid operand '{}'", s)),
+    }
+}
+
+// An operand that has to be a memory cell rather than an immediate.
+fn cell(s: &str, field: &Field) -> Result<Operand, String> {
+    match operand(s, field)? {
+        Operand::Int(_) => Err(format!("Expected [ap + k] or [fp + k], got '{}'", s.trim())),
+        op => Ok(op),
+    }
+}
+
+// Splits at the first `sep` outside of brackets.
+fn split_top_level<'a>(s: &'a str, sep: &str) -> Option<(&'a str, &'a str)> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ if depth == 0 && s[i..].starts_with(sep) => {
+                return Some((&s[..i], &s[i + sep.len()..]));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn instruction(statement: &str, field: &Field) -> Result<CasmInstruction, String> {
+    let words: Vec<&str> = statement.split_whitespace().collect();
+    let relative = |n: &str| offset(n).map_err(|_| format!("Invalid offset in '{}'", statement));
+    match words.as_slice() {
+        ["ret"] => return Ok(CasmInstruction::Ret),
+        ["call", "rel", n] => return Ok(CasmInstruction::CallRel(relative(n)?)),
+        ["call", "abs", n] => return integer(n, field).map(CasmInstruction::CallAbs),
+        ["call", label] if is_label(label) => {
+            return Ok(CasmInstruction::Call(label.to_string()));
+        }
+        ["jmp", "rel", n] => return Ok(CasmInstruction::JmpRel(relative(n)?)),
+        ["jmp", label] if is_label(label) => return Ok(CasmInstruction::Jmp(label.to_string())),
+        ["ap", "+=", n] => {
+            let n = integer(n, field)?
+                .to_u64()
+                .ok_or(format!("Invalid increment in '{}'", statement))?;
+            return Ok(CasmInstruction::IncrAp(n));
+        }
+        // fp only changes on call and ret
+        ["fp", "+=", _] => {
+            return Err(format!(
+                "Cannot encode '{}', only ap can be incremented",
+                statement
+            ));
+        }
+        _ => {}
+    }
+    if let Some(jump) = statement.strip_prefix("jmp ") {
+        let (target, condition) =
+            split_top_level(jump, " if ").ok_or(format!("Invalid jump '{}'", statement))?;
+        let op = condition
+            .trim()
+            .strip_suffix("!= 0")
+            .ok_or(format!("Expected '!= 0' in '{}'", statement))?;
+        let op = operand(op, field)?;
+        let target = target.trim();
+        return match target.strip_prefix("rel ") {
+            Some(n) => Ok(CasmInstruction::JmpIfNeqRel(relative(n)?, op)),
+            None if is_label(target) => Ok(CasmInstruction::JmpIfNeq(target.to_string(), op)),
+            None => Err(format!("Invalid jump target '{}'", target)),
+        };
+    }
+
+    let (assertion, incr_ap) = match split_top_level(statement, ",") {
+        Some((assertion, suffix)) if suffix.trim() == "ap++" => (assertion, true),
+        Some(_) => return Err(format!("Invalid instruction '{}'", statement)),
+        None => (statement, false),
+    };
+    let (left, right) =
+        split_top_level(assertion, "=").ok_or(format!("Invalid instruction '{}'", statement))?;
+    let left = cell(left, field)?;
+    let right = right.trim();
+    if let Some((op1, op2)) = split_top_level(right, " + ") {
+        return Ok(CasmInstruction::Add {
+            left,
+            op1: cell(op1, field)?,
+            op2: operand(op2, field)?,
+        });
+    }
+    if let Some((op1, op2)) = split_top_level(right, " * ") {
+        return Ok(CasmInstruction::Mul {
+            left,
+            op1: cell(op1, field)?,
+            op2: operand(op2, field)?,
+        });
+    }
+    // [[fp + k] + offset]
+    if let Some(inner) = right
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .filter(|inner| inner.trim_start().starts_with('['))
+    {
+        let (op, offset) = split_top_level(inner.trim(), "+")
+            .map(|(op, n)| (op, n.to_string()))
+            .or_else(|| split_top_level(inner.trim(), "-").map(|(op, n)| (op, format!("-{}", n))))
+            .unwrap_or((inner, "0".to_string()));
+        return Ok(CasmInstruction::Deref {
+            left,
+            op: cell(op, field)?,
+            offset: self::offset(&offset)?,
+            incr_ap,
+        });
+    }
+    Ok(CasmInstruction::Set {
+        left,
+        op: operand(right, field)?,
+        incr_ap,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_builtins_and_hint_references() {
+        let hint = "%{ memory[ap] = ids.a %} scope(__main__.f) ids(a=[fp + 0], b=[fp + -3])";
+        let text = format!("%builtins output range_check\nf:\n    {}\n    ret;\n", hint);
+        let program = parse(&text, &Field::M31).unwrap();
+        assert_eq!(program.builtins, ["output", "range_check"]);
+        let CasmInstruction::Hint(parsed) = &program.instructions[1] else {
+            panic!("expected a hint, got {:?}", program.instructions[1]);
+        };
+        assert_eq!(parsed.code, "memory[ap] = ids.a");
+        assert_eq!(parsed.accessible_scopes, ["__main__", "__main__.f"]);
+        assert_eq!(
+            parsed.references,
+            [("a".to_string(), 0), ("b".to_string(), -3)]
+        );
+        assert_eq!(format!("{:?}", program.instructions[1]), hint);
+        assert!(matches!(program.instructions[2], CasmInstruction::Ret));
+    }
+
+    #[test]
+    fn fp_cannot_be_incremented() {
+        let error = parse("f:\n    ap += 1;\n    fp += 1;\n    ret;\n", &Field::M31)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error,
+            "line 3: Cannot encode 'fp += 1', only ap can be incremented"
+        );
+    }
+}
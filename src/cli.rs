@@ -0,0 +1,769 @@
+// The czc command line: parses the arguments and runs the command.
+
+use crate::{
+    CompiledProgram, MessageFormat, Vm, casm, casm_parser, cst, debugger, disasm, doc, felt,
+    flatten, formatter, hints, lsp, minivm, profiler, source_map, testing, trace,
+};
+use std::env;
+
+const USAGE: &str = "Usage: czc [command] <file> [options]
+
+Commands:
+    run <file.cairo|file.json>  compile the program if needed and run it (default)
+    compile <file.cairo>        compile to a json program
+    assemble <file.casm>        assemble casm into a json program, read from stdin if
+                                the file is -, as in czc compile --emit casm | czc assemble -
+    disasm <file.json>          print the casm of a compiled program
+    check <file.cairo>          report errors without writing anything
+    fmt <file.cairo>            print the file in canonical layout
+    doc <file.cairo|dir>        write the API reference of the file, or of every .cairo
+                                file under dir, as markdown, or as html if -o ends in .html
+    test <file.cairo|dir>       run the functions named test_* of the file, or of every
+                                .cairo file under dir; those decorated with @expect_fail
+                                pass when they fail
+    debug <file.cairo>          run the program in the step debugger
+    lsp                         serve the language server protocol on stdin and stdout
+
+Options:
+    -o <path>                   write the output to <path> instead of stdout
+    --emit <stages>             comma-separated stages to print while compiling:
+                                tokens, ast, casm, resolved-casm, hex, json
+    --prime <m31|stark|prime>   target field (m31 by default)
+    --args <n>...               arguments of main
+    --input <file.json>         json exposed to hints as program_input
+    --max_steps <n>             fail after <n> steps, 1000000 by default with test
+    --message-format <format>   print errors as human-readable reports (default),
+                                or as json, one object per line on stderr
+    --deny-warnings             fail to compile programs with warnings
+    --filter <text>             with test, run only the tests whose name contains <text>
+    --check                     with fmt, fail instead of printing if the file is not
+                                in canonical layout
+    --trace_file <path>         write the relocated trace
+    --memory_file <path>        write the relocated memory
+    --air_public_input <path>   write the public input of the run
+    --profile                   print a profile of the run
+    --profile_output <path>     print a profile of the run and write its folded stacks
+                                to <path>";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Compile,
+    Assemble,
+    Disasm,
+    Check,
+    Fmt,
+    Doc,
+    Test,
+    Debug,
+    Lsp,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "run" => Some(Command::Run),
+            "compile" => Some(Command::Compile),
+            "assemble" => Some(Command::Assemble),
+            "disasm" => Some(Command::Disasm),
+            "check" => Some(Command::Check),
+            "fmt" => Some(Command::Fmt),
+            "doc" => Some(Command::Doc),
+            "test" => Some(Command::Test),
+            "debug" => Some(Command::Debug),
+            "lsp" => Some(Command::Lsp),
+            _ => None,
+        }
+    }
+}
+
+// Intermediate results of compilation that can be printed with --emit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Tokens,
+    Ast,
+    Casm,
+    ResolvedCasm,
+    Hex,
+    Json,
+}
+
+impl Stage {
+    fn from_name(name: &str) -> Result<Stage, String> {
+        match name {
+            "tokens" => Ok(Stage::Tokens),
+            "ast" => Ok(Stage::Ast),
+            "casm" => Ok(Stage::Casm),
+            "resolved-casm" => Ok(Stage::ResolvedCasm),
+            "hex" => Ok(Stage::Hex),
+            "json" => Ok(Stage::Json),
+            _ => Err(format!(
+                "Unknown stage '{}', expected tokens, ast, casm, resolved-casm, hex or json",
+                name
+            )),
+        }
+    }
+}
+
+struct Options {
+    command: Command,
+    path: String,
+    // where the output of the command goes, stdout if not set
+    output: Option<String>,
+    emit: Vec<Stage>,
+    // target field: m31 (default), stark, or the prime itself
+    field: felt::Field,
+    trace_file: Option<String>,
+    memory_file: Option<String>,
+    air_public_input: Option<String>,
+    max_steps: Option<u64>,
+    profile: bool,
+    // where to write the folded stacks of the profile
+    profile_output: Option<String>,
+    // arguments of main, as written on the command line
+    args: Vec<String>,
+    // json file exposed to hints as program_input
+    input: Option<String>,
+    message_format: MessageFormat,
+    deny_warnings: bool,
+    // fmt only checks that the file is formatted
+    check: bool,
+    // test only runs the tests whose name contains it
+    filter: Option<String>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    // --option=value is the same as --option value
+    let mut args = args
+        .flat_map(|arg| match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => {
+                vec![name.to_string(), value.to_string()]
+            }
+            _ => vec![arg],
+        })
+        .peekable();
+    let command = args
+        .next_if(|arg| Command::from_name(arg).is_some())
+        .and_then(|arg| Command::from_name(&arg))
+        .unwrap_or(Command::Run);
+    let mut path = None;
+    let mut options = Options {
+        command,
+        path: String::new(),
+        output: None,
+        emit: Vec::new(),
+        field: felt::Field::M31,
+        trace_file: None,
+        memory_file: None,
+        air_public_input: None,
+        max_steps: None,
+        profile: false,
+        profile_output: None,
+        args: Vec::new(),
+        input: None,
+        message_format: MessageFormat::Human,
+        deny_warnings: false,
+        check: false,
+        filter: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} expects a value", arg));
+        match arg.as_str() {
+            "-o" => options.output = Some(value()?),
+            "--emit" => {
+                for stage in value()?.split(',') {
+                    options.emit.push(Stage::from_name(stage)?);
+                }
+            }
+            "--prime" => options.field = felt::Field::from_name(&value()?)?,
+            "--message-format" => options.message_format = MessageFormat::from_name(&value()?)?,
+            "--trace_file" => options.trace_file = Some(value()?),
+            "--memory_file" => options.memory_file = Some(value()?),
+            "--air_public_input" => options.air_public_input = Some(value()?),
+            "--profile" => options.profile = true,
+            "--profile_output" => {
+                options.profile = true;
+                options.profile_output = Some(value()?);
+            }
+            "--input" => options.input = Some(value()?),
+            "--deny-warnings" => options.deny_warnings = true,
+            "--check" => options.check = true,
+            "--filter" => options.filter = Some(value()?),
+            "--args" => {
+                // every following integer, negative ones included
+                while let Some(arg) = args.next_if(|arg| {
+                    let digits = arg.strip_prefix('-').unwrap_or(arg);
+                    !digits.is_empty() && digits.parse::<felt::Felt>().is_ok()
+                }) {
+                    options.args.push(arg);
+                }
+            }
+            "--max_steps" => {
+                options.max_steps = Some(
+                    value()?
+                        .parse()
+                        .map_err(|_| "--max_steps expects an integer".to_string())?,
+                );
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("Unknown option {}\n\n{}", arg, USAGE));
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Unexpected argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+    // the language server is given its files by the editor
+    if options.command != Command::Lsp {
+        options.path = path.ok_or(format!("No file provided\n\n{}", USAGE))?;
+    }
+    if options.command == Command::Compile && options.emit.is_empty() {
+        options.emit.push(Stage::Json);
+    }
+    Ok(options)
+}
+
+// `-` reads stdin.
+fn read_file(path: &str) -> Result<String, String> {
+    if path == "-" {
+        return std::io::read_to_string(std::io::stdin())
+            .map_err(|e| format!("Could not read stdin: {}", e));
+    }
+    std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))
+}
+
+// Writes the output of a command to -o, or to stdout.
+fn write_output(options: &Options, text: &str) -> Result<(), String> {
+    match &options.output {
+        Some(path) => {
+            std::fs::write(path, text).map_err(|e| format!("Could not write {}: {}", path, e))
+        }
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+// Parses the arguments of main, checking their number against its signature.
+fn main_args(
+    compiler: Option<&crate::lower_to_casm::Compiler>,
+    options: &Options,
+) -> Result<Vec<felt::Felt>, String> {
+    let field = options.field;
+    let args = options
+        .args
+        .iter()
+        .map(|arg| match arg.strip_prefix('-') {
+            Some(n) => n.parse().map(|n| field.neg(&field.reduce(&n))),
+            None => arg.parse().map(|n| field.reduce(&n)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match compiler.and_then(|compiler| compiler.n_args("main")) {
+        Some(n) if n != args.len() => Err(format!(
+            "main expects {} arguments but {} were given",
+            n,
+            args.len()
+        )),
+        _ => Ok(args),
+    }
+}
+
+fn hint_processor(options: &Options) -> Result<hints::HintRegistry, String> {
+    let mut hint_processor = hints::HintRegistry::with_builtin_hints();
+    if let Some(path) = &options.input {
+        let contents = read_file(path)?;
+        hint_processor.program_input =
+            json::parse(&contents).map_err(|e| format!("Invalid input file {}: {}", path, e))?;
+    }
+    Ok(hint_processor)
+}
+
+// Writes the files requested on the command line once the program has run.
+fn write_outputs(vm: &minivm::MiniVm, options: &Options) -> Result<(), String> {
+    if let Some(path) = &options.trace_file {
+        trace::write_trace_file(vm, path)?;
+    }
+    if let Some(path) = &options.memory_file {
+        trace::write_memory_file(vm, path)?;
+    }
+    if let Some(path) = &options.air_public_input {
+        trace::write_public_input(vm, path)?;
+    }
+    Ok(())
+}
+
+// Locations are written as `// file:line:col` comments, before the first instruction of each
+// new line and column, if the source is known.
+fn listing(
+    instructions: &[casm::CasmInstruction],
+    builtins: &[String],
+    source: Option<(&str, &str)>,
+) -> String {
+    let mut text = String::new();
+    if !builtins.is_empty() {
+        text.push_str(&format!("%builtins {}\n", builtins.join(" ")));
+    }
+    let mut location = None;
+    let mut last_location = None;
+    for instruction in instructions.iter() {
+        match instruction {
+            casm::CasmInstruction::Label(_) => text.push_str(&format!("{:?}\n", instruction)),
+            casm::CasmInstruction::Location(span) => {
+                location = source.map(|(_, source)| crate::ast::line_col(source, span.0));
+            }
+            _ => {
+                if let (Some((file_name, _)), Some((line, col))) = (source, location)
+                    && last_location != location
+                {
+                    text.push_str(&format!("// {}:{}:{}\n", file_name, line, col));
+                    last_location = location;
+                }
+                text.push_str(&format!("    {:?}\n", instruction));
+            }
+        }
+    }
+    text
+}
+
+fn hex(assembler: &crate::Assembler) -> String {
+    let mut text = String::new();
+    for instruction in assembler.instructions.iter() {
+        let (bytes, imm) = instruction.to_bytes();
+        text.push_str(&format!("{:#x}\n", bytes));
+        if let Some(imm) = imm {
+            text.push_str(&format!("{:#x}\n", imm));
+        }
+    }
+    text
+}
+
+fn compile_options(options: &Options) -> crate::Options {
+    crate::Options {
+        field: options.field,
+        file_name: options.path.clone(),
+        deny_warnings: options.deny_warnings,
+    }
+}
+
+// Prints the diagnostics on the source, returning the message to exit with.
+fn report(diagnostics: &crate::Diagnostics, options: &Options, source: &str) -> String {
+    diagnostics.emit(&options.path, source, options.message_format);
+    let n = diagnostics.n_errors();
+    format!(
+        "Could not compile {} due to {} previous error{}",
+        options.path,
+        n,
+        if n == 1 { "" } else { "s" }
+    )
+}
+
+// Compiles the source, appending the stages selected with --emit to `emitted`.
+fn build(input: &str, options: &Options, emitted: &mut String) -> Result<CompiledProgram, String> {
+    let compiled = crate::compile_source(input, compile_options(options))
+        .map_err(|diagnostics| report(&diagnostics, options, input))?;
+    for warning in compiled.warnings().iter() {
+        warning.emit(&options.path, input, options.message_format);
+    }
+    for stage in options.emit.iter() {
+        match stage {
+            Stage::Tokens => {
+                for token in compiled.tokens().iter() {
+                    emitted.push_str(&format!("{:?} '{}'\n", token.token_type, token.lexeme));
+                }
+            }
+            Stage::Ast => {
+                for element in compiled.ast().iter() {
+                    emitted.push_str(&format!("{:?}\n", element));
+                }
+            }
+            Stage::Casm => emitted.push_str(&listing(
+                compiled.casm(),
+                &compiled.assembler().builtins,
+                Some((&options.path, input)),
+            )),
+            Stage::ResolvedCasm => emitted.push_str(&listing(
+                &compiled.assembler().casm,
+                &compiled.assembler().builtins,
+                Some((&options.path, input)),
+            )),
+            Stage::Hex => emitted.push_str(&hex(compiled.assembler())),
+            Stage::Json => {
+                emitted.push_str(&compiled.assembler().to_json());
+                emitted.push('\n');
+            }
+        }
+    }
+    Ok(compiled)
+}
+
+fn compile(options: &Options) -> Result<(), String> {
+    let input = read_file(&options.path)?;
+    let mut emitted = String::new();
+    build(&input, options, &mut emitted)?;
+    write_output(options, &emitted)
+}
+
+fn check(options: &Options) -> Result<(), String> {
+    let input = read_file(&options.path)?;
+    build(&input, options, &mut String::new())?;
+    Ok(())
+}
+
+fn assemble(options: &Options) -> Result<(), String> {
+    let input = read_file(&options.path)?;
+    let program = casm_parser::parse(&input, &options.field)
+        .map_err(|error| format!("{}:{}", options.path, error))?;
+    let assembler = crate::assemble(
+        program.instructions,
+        program.builtins,
+        &compile_options(options),
+    )
+    .map_err(|diagnostics| report(&diagnostics, options, &input))?;
+    let mut text = String::new();
+    if options.emit.contains(&Stage::ResolvedCasm) {
+        text.push_str(&listing(&assembler.casm, &assembler.builtins, None));
+    }
+    if options.emit.contains(&Stage::Hex) {
+        text.push_str(&hex(&assembler));
+    }
+    if options.emit.is_empty() || options.emit.contains(&Stage::Json) {
+        text.push_str(&assembler.to_json());
+        text.push('\n');
+    }
+    write_output(options, &text)
+}
+
+fn disasm(options: &Options) -> Result<(), String> {
+    let input = read_file(&options.path)?;
+    write_output(options, &disasm::listing(&input)?)
+}
+
+fn fmt(options: &Options) -> Result<(), String> {
+    let input = read_file(&options.path)?;
+    let (tokens, diagnostics) = cst::tokenize(&input);
+    if !diagnostics.is_empty() {
+        return Err(report(&crate::Diagnostics(diagnostics), options, &input));
+    }
+    let (tree, code_elements, diagnostics) = cst::parse(tokens, &input);
+    if !diagnostics.is_empty() {
+        return Err(report(&crate::Diagnostics(diagnostics), options, &input));
+    }
+    let formatted = formatter::format(&code_elements, &tree, &input)?;
+    if options.check {
+        if formatted != input {
+            return Err(format!(
+                "{} is not formatted, run czc fmt on it",
+                options.path
+            ));
+        }
+        return Ok(());
+    }
+    write_output(options, &formatted)
+}
+
+// The .cairo files under the directory, in order.
+fn cairo_files(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) -> Result<(), String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+    let mut paths = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            cairo_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "cairo")
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// The file, or the .cairo files under the directory.
+fn source_files(root: &std::path::Path) -> Result<Vec<std::path::PathBuf>, String> {
+    let mut files = Vec::new();
+    if root.is_dir() {
+        cairo_files(root, &mut files)?;
+    } else {
+        files.push(root.to_path_buf());
+    }
+    Ok(files)
+}
+
+fn doc(options: &Options) -> Result<(), String> {
+    let root = std::path::Path::new(&options.path);
+    let files = source_files(root)?;
+    let mut modules = Vec::new();
+    for file in files {
+        let path = file.display().to_string();
+        let input = read_file(&path)?;
+        let (tokens, mut diagnostics) = cst::tokenize(&input);
+        let (_, code_elements, parse_diagnostics) = cst::parse(tokens, &input);
+        diagnostics.extend(parse_diagnostics);
+        // the values of the constants are documented, they are computed on a well-formed tree
+        let code_elements = match diagnostics.is_empty() {
+            true => {
+                let (code_elements, flatten_diagnostics) = flatten::flatten(&code_elements);
+                diagnostics = flatten_diagnostics;
+                code_elements
+            }
+            false => code_elements,
+        };
+        if !diagnostics.is_empty() {
+            let diagnostics = crate::Diagnostics(diagnostics);
+            diagnostics.emit(&path, &input, options.message_format);
+            return Err(format!("Could not document {}", path));
+        }
+        // modules are named after their path in the tree
+        let relative = file.strip_prefix(root).unwrap_or(&file);
+        let relative = if relative.as_os_str().is_empty() {
+            file.file_name().map(std::path::Path::new).unwrap_or(&file)
+        } else {
+            relative
+        };
+        let name = relative
+            .with_extension("")
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(".");
+        modules.push(doc::Module {
+            name,
+            code_elements,
+        });
+    }
+    let format = match &options.output {
+        Some(path) if path.ends_with(".html") => doc::Format::Html,
+        _ => doc::Format::Markdown,
+    };
+    write_output(options, &doc::generate(&modules, format))
+}
+
+// Runs the tests of every file, reporting where the failing ones stopped.
+fn test(options: &Options) -> Result<(), String> {
+    let files = source_files(std::path::Path::new(&options.path))?;
+    let (mut passed, mut failed, mut steps) = (0, 0, 0);
+    for file in files {
+        let path = file.display().to_string();
+        let input = read_file(&path)?;
+        let compile_options = crate::Options {
+            file_name: path.clone(),
+            ..compile_options(options)
+        };
+        let compiled = match crate::compile_source(&input, compile_options) {
+            Ok(compiled) => compiled,
+            // with --filter, files without a selected test are not expected to compile
+            Err(_)
+                if options.filter.is_some()
+                    && !testing::declares_test(&input, options.filter.as_deref()) =>
+            {
+                continue;
+            }
+            Err(diagnostics) => {
+                diagnostics.emit(&path, &input, options.message_format);
+                println!("{} ... could not compile", path);
+                failed += 1;
+                continue;
+            }
+        };
+        let tests = testing::discover(&compiled, options.filter.as_deref());
+        if tests.is_empty() {
+            continue;
+        }
+        for warning in compiled.warnings().iter() {
+            warning.emit(&path, &input, options.message_format);
+        }
+        println!(
+            "running {} test{} from {}",
+            tests.len(),
+            if tests.len() == 1 { "" } else { "s" },
+            path
+        );
+        let source_map = source_map::SourceMap::new(
+            compiled.assembler(),
+            compiled.compiler().frame_layouts.keys(),
+        );
+        for test in tests.iter() {
+            let max_steps = options.max_steps.unwrap_or(testing::DEFAULT_MAX_STEPS);
+            let outcome =
+                match testing::run(&compiled, test, hint_processor(options)?, Some(max_steps)) {
+                    Ok(outcome) => outcome,
+                    Err(error) => {
+                        println!("test {} ... error: {}", test.name(), error);
+                        failed += 1;
+                        continue;
+                    }
+                };
+            steps += outcome.steps();
+            let status = match (&outcome.error, test.expect_fail) {
+                // running out of steps is not the failure @expect_fail is about
+                _ if outcome.hit_step_limit() => "FAILED, step limit reached",
+                (None, false) => "ok",
+                (Some(_), true) => "ok, failed as expected",
+                (None, true) => "FAILED, expected to fail",
+                (Some(_), false) => "FAILED",
+            };
+            // where the test stopped, as file:line:column
+            let location = outcome
+                .error
+                .as_ref()
+                .filter(|error| error.pc.segment == outcome.vm.state.program_base.segment)
+                .zip(source_map.as_ref())
+                .and_then(|(error, source_map)| source_map.location(error.pc.offset))
+                .map(|span| {
+                    let (line, column) = crate::ast::line_col(&input, span.0);
+                    format!(" at {}:{}:{}", path, line, column)
+                })
+                .unwrap_or_default();
+            if status.starts_with("ok") {
+                passed += 1;
+                println!(
+                    "test {} ... {} ({} steps)",
+                    test.name(),
+                    status,
+                    outcome.steps()
+                );
+                continue;
+            }
+            failed += 1;
+            println!(
+                "test {} ... {}{} ({} steps)",
+                test.name(),
+                status,
+                location,
+                outcome.steps()
+            );
+            if let Some(error) = &outcome.error {
+                match &source_map {
+                    Some(source_map) => {
+                        source_map.report_vm_error(&outcome.vm.state, error, options.message_format)
+                    }
+                    None => eprintln!("{}", error),
+                }
+            }
+        }
+    }
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed; {} steps",
+        result, passed, failed, steps
+    );
+    if failed > 0 {
+        return Err(format!(
+            "{} test{} failed",
+            failed,
+            if failed == 1 { "" } else { "s" }
+        ));
+    }
+    Ok(())
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let input = read_file(&options.path)?;
+    // compiled programs are run as they are, without source locations
+    let (program, compiled) = if options.path.ends_with(".json") {
+        (minivm::Program::from_json(&input)?, None)
+    } else {
+        let mut emitted = String::new();
+        let compiled = build(&input, options, &mut emitted)?;
+        if !options.emit.is_empty() {
+            write_output(options, &emitted)?;
+        }
+        (compiled.program()?, Some(compiled))
+    };
+    let source_map = compiled.as_ref().and_then(|compiled| {
+        source_map::SourceMap::new(
+            compiled.assembler(),
+            compiled.compiler().frame_layouts.keys(),
+        )
+    });
+
+    let mut vm = Vm::new();
+    vm.args = main_args(
+        compiled.as_ref().map(|compiled| compiled.compiler()),
+        options,
+    )?;
+    vm.hint_processor = hint_processor(options)?;
+    vm.max_steps = options.max_steps;
+    if let Err(error) = vm.run(&program) {
+        return match source_map {
+            Some(source_map) => {
+                source_map.report_vm_error(&vm.state, &error, options.message_format);
+                Err("Execution failed".to_string())
+            }
+            None => Err(format!("Execution failed: {}", error)),
+        };
+    }
+
+    let vm = vm.state;
+    // compiled programs do not tell how many values main returns
+    let n_return_values = compiled
+        .as_ref()
+        .and_then(|compiled| compiled.compiler().n_return_values("main"))
+        .unwrap_or(0);
+    println!("{}", vm.summary(n_return_values));
+    if !program.builtins.is_empty() {
+        println!("Program output:");
+        for value in vm.output() {
+            match value {
+                Some(value) => println!("  {}", value),
+                None => println!("  <missing>"),
+            }
+        }
+    }
+    write_outputs(&vm, options)?;
+    if options.profile {
+        let source_map = source_map.ok_or("Profiling needs the source of the program")?;
+        let profile = profiler::Profile::new(&vm, &source_map);
+        profile.print(&source_map);
+        if let Some(path) = &options.profile_output {
+            profile.write_folded(path)?;
+        }
+    }
+    Ok(())
+}
+
+fn debug(options: &Options) -> Result<(), String> {
+    let input = read_file(&options.path)?;
+    let compiled = build(&input, options, &mut String::new())?;
+    let args = main_args(Some(compiled.compiler()), options)?;
+    let hint_processor = hint_processor(options)?;
+    let mut debugger = debugger::Debugger::new(
+        compiled.assembler(),
+        compiled.compiler().frame_layouts.clone(),
+        compiled.compiler().n_return_values("main").unwrap_or(0),
+        &options.path,
+        &input,
+        &args,
+        hint_processor,
+    )?;
+    debugger.run();
+    Ok(())
+}
+
+pub fn main() {
+    if env::args().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    let result = parse_args(env::args().skip(1)).and_then(|options| match options.command {
+        Command::Run => run(&options),
+        Command::Compile => compile(&options),
+        Command::Assemble => assemble(&options),
+        Command::Disasm => disasm(&options),
+        Command::Check => check(&options),
+        Command::Fmt => fmt(&options),
+        Command::Doc => doc(&options),
+        Command::Test => test(&options),
+        Command::Debug => debug(&options),
+        Command::Lsp => lsp::run(),
+    });
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
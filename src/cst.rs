@@ -0,0 +1,319 @@
+// Concrete syntax tree: the tokens of a file with the whitespace and comments around them,
+// and the statements they group into. Printing every token with its trivia gives back the
+// source byte for byte. The ast is derived from the same parse.
+
+use crate::ast::{CodeElement, Span};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{self, Token, TokenType};
+use crate::parser::Parser;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    // spaces, tabs and carriage returns
+    Whitespace,
+    Newline,
+    Comment,
+    // characters no token starts with, reported by the lexer
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
+
+// A token of the code. Trailing trivia is what follows it on its line, up to the newline;
+// everything else before the next token is that token's leading trivia.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    pub token: Token,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+    // 1-based, the column in characters
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+    Function,
+    Struct,
+    Const,
+    Namespace,
+    If,
+    Let,
+    Local,
+    Assert,
+    Return,
+    Call,
+    Instruction,
+    Hint,
+    Directive,
+    AllocLocals,
+    // a statement the parser could not make sense of
+    Error,
+}
+
+impl SyntaxKind {
+    pub fn of(code_element: &CodeElement) -> Self {
+        match code_element {
+            CodeElement::Function(_) => SyntaxKind::Function,
+            CodeElement::Struct(..) => SyntaxKind::Struct,
+            CodeElement::Const(..) => SyntaxKind::Const,
+            CodeElement::NameSpace(..) => SyntaxKind::Namespace,
+            CodeElement::If(..) => SyntaxKind::If,
+            CodeElement::Reference(..) => SyntaxKind::Let,
+            CodeElement::LocalVar(..) => SyntaxKind::Local,
+            CodeElement::CompoundAssertEqual(..) => SyntaxKind::Assert,
+            CodeElement::Return(_) => SyntaxKind::Return,
+            CodeElement::FuncCall(_) => SyntaxKind::Call,
+            CodeElement::Instruction(_) => SyntaxKind::Instruction,
+            CodeElement::Hint(_) => SyntaxKind::Hint,
+            CodeElement::Directive(..) => SyntaxKind::Directive,
+            CodeElement::AllocLocals(_) => SyntaxKind::AllocLocals,
+            _ => SyntaxKind::Error,
+        }
+    }
+}
+
+// A statement, from its first token to its last, with the statements of its blocks.
+#[derive(Debug, Clone)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    // indices of the first token and of the one after the last
+    pub tokens: (usize, usize),
+    pub children: Vec<SyntaxNode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    // ends with the Eof token, whose leading trivia is the end of the file
+    pub tokens: Vec<SyntaxToken>,
+    pub nodes: Vec<SyntaxNode>,
+}
+
+// Splits whitespace between tokens into trivia.
+fn gap_trivia(source: &str, start: usize, end: usize, trivia: &mut Vec<Trivia>) {
+    for (i, c) in source[start..end].char_indices() {
+        let kind = match c {
+            '\n' => TriviaKind::Newline,
+            ' ' | '\t' | '\r' => TriviaKind::Whitespace,
+            _ => TriviaKind::Skipped,
+        };
+        let position = start + i;
+        // runs of spaces and skipped characters are one piece, newlines are one each
+        match trivia.last_mut() {
+            Some(last)
+                if last.kind == kind && last.span.1 == position && kind != TriviaKind::Newline =>
+            {
+                last.text.push(c);
+                last.span.1 = position + c.len_utf8();
+            }
+            _ => trivia.push(Trivia {
+                kind,
+                text: c.to_string(),
+                span: (position, position + c.len_utf8()),
+            }),
+        }
+    }
+}
+
+pub fn tokenize(source: &str) -> (Vec<SyntaxToken>, Vec<Diagnostic>) {
+    let (tokens, diagnostics) = lexer::lex_with_comments(source);
+    let mut syntax_tokens: Vec<SyntaxToken> = Vec::new();
+    let mut pending: Vec<Trivia> = Vec::new();
+    // set after a token until the end of its line
+    let mut trailing = false;
+    let mut end = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+    for token in tokens {
+        let mut trivia = Vec::new();
+        gap_trivia(source, end, token.span.0, &mut trivia);
+        if token.token_type == TokenType::Comment {
+            trivia.push(Trivia {
+                kind: TriviaKind::Comment,
+                text: token.lexeme.clone(),
+                span: token.span,
+            });
+        }
+        for piece in trivia {
+            if piece.kind == TriviaKind::Newline {
+                trailing = false;
+                line += 1;
+                line_start = piece.span.1;
+            }
+            match syntax_tokens.last_mut() {
+                Some(last) if trailing => last.trailing.push(piece),
+                _ => pending.push(piece),
+            }
+        }
+        end = token.span.1;
+        if token.token_type == TokenType::Comment {
+            continue;
+        }
+        // hints span lines
+        let column = source[line_start..token.span.0].chars().count() + 1;
+        let (token_line, token_column) = (line, column);
+        for (i, c) in token.lexeme.char_indices() {
+            if c == '\n' {
+                line += 1;
+                line_start = token.span.0 + i + 1;
+            }
+        }
+        syntax_tokens.push(SyntaxToken {
+            token,
+            leading: std::mem::take(&mut pending),
+            trailing: Vec::new(),
+            line: token_line,
+            column: token_column,
+        });
+        trailing = true;
+    }
+    (syntax_tokens, diagnostics)
+}
+
+// Parses the tokens of `tokenize`, giving the tree and the ast derived from it.
+pub fn parse(
+    tokens: Vec<SyntaxToken>,
+    source: &str,
+) -> (SyntaxTree, Vec<CodeElement>, Vec<Diagnostic>) {
+    let code = tokens.iter().map(|token| token.token.clone()).collect();
+    let mut parser = Parser::new(code, source.to_string());
+    let (mut code_elements, diagnostics) = parser.parse();
+    let tree = SyntaxTree {
+        tokens,
+        nodes: parser.take_nodes(),
+    };
+    attach_docs(&tree, &tree.nodes, &mut code_elements);
+    (tree, code_elements, diagnostics)
+}
+
+// Gives the declarations of the file and of its namespaces their doc comments.
+fn attach_docs(tree: &SyntaxTree, nodes: &[SyntaxNode], code_elements: &mut [CodeElement]) {
+    for (node, code_element) in nodes.iter().zip(code_elements.iter_mut()) {
+        match code_element {
+            CodeElement::Function(function) => function.doc = tree.doc(node),
+            CodeElement::Struct(_, _, doc) | CodeElement::Const(_, _, doc) => *doc = tree.doc(node),
+            CodeElement::NameSpace(_, members, doc) => {
+                *doc = tree.doc(node);
+                attach_docs(tree, &node.children, members);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Trivia {
+    pub fn is_comment(&self) -> bool {
+        self.kind == TriviaKind::Comment
+    }
+}
+
+impl SyntaxTree {
+    // The source the tree was parsed from.
+    #[allow(dead_code)]
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for token in self.tokens.iter() {
+            for trivia in token.leading.iter() {
+                text.push_str(&trivia.text);
+            }
+            text.push_str(&token.token.lexeme);
+            for trivia in token.trailing.iter() {
+                text.push_str(&trivia.text);
+            }
+        }
+        text
+    }
+
+    // The tokens of the code, Eof included.
+    pub fn code_tokens(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().map(|token| &token.token)
+    }
+
+    pub fn comments(&self) -> impl Iterator<Item = &Trivia> {
+        self.tokens
+            .iter()
+            .flat_map(|token| token.leading.iter().chain(token.trailing.iter()))
+            .filter(|trivia| trivia.is_comment())
+    }
+
+    // From the start of the first token of the node to the end of its last.
+    pub fn span(&self, node: &SyntaxNode) -> Span {
+        let first = &self.tokens[node.tokens.0].token;
+        let last = &self.tokens[node.tokens.1.max(node.tokens.0 + 1) - 1].token;
+        (first.span.0, last.span.1)
+    }
+
+    // The comments on the lines right above the node, without a blank line in between.
+    pub fn comments_above(&self, node: &SyntaxNode) -> Vec<&Trivia> {
+        let mut comments = Vec::new();
+        let mut newlines = 0;
+        for trivia in self.tokens[node.tokens.0].leading.iter().rev() {
+            match trivia.kind {
+                TriviaKind::Newline => {
+                    newlines += 1;
+                    if newlines > 1 {
+                        break;
+                    }
+                }
+                TriviaKind::Comment => {
+                    newlines = 0;
+                    comments.push(trivia);
+                }
+                _ => {}
+            }
+        }
+        comments.reverse();
+        comments
+    }
+
+    // The text of the comments above the node, one line each, without the `//` and the
+    // space after it.
+    pub fn doc(&self, node: &SyntaxNode) -> Option<String> {
+        let comments = self.comments_above(node);
+        if comments.is_empty() {
+            return None;
+        }
+        let lines: Vec<&str> = comments
+            .iter()
+            .map(|comment| {
+                let text = &comment.text[2..];
+                text.strip_prefix(' ').unwrap_or(text).trim_end()
+            })
+            .collect();
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_tree_prints_back_its_source() {
+        let source = "%builtins output\n\
+            // Doubles.\n\
+            func double(x: felt) -> felt {  // trailing\n\
+            \t%{\n        memory[ap] = ids.x\n    %}\n\
+            \x20   return x * 2;\n\
+            }\n\
+            \n\
+            namespace m {\n    const N = -3;   \n}\n// the end";
+        for source in [source.to_string(), source.replace('\n', "\r\n")] {
+            let (tokens, diagnostics) = tokenize(&source);
+            assert!(diagnostics.is_empty());
+            let (tree, code_elements, diagnostics) = parse(tokens, &source);
+            assert!(diagnostics.is_empty());
+            assert_eq!(tree.text(), source);
+            let CodeElement::Function(function) = &code_elements[1] else {
+                panic!("expected a function, got {:?}", code_elements[1]);
+            };
+            assert_eq!(function.doc.as_deref(), Some("Doubles."));
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use crate::assembler::{Assembler, Instruction};
+use crate::ast::{Span, line_col};
+use crate::diagnostic::MessageFormat;
+use crate::disasm;
+use crate::felt::Felt;
+use crate::hints::HintRegistry;
+use crate::minivm::{MiniVm, Program, Relocatable, Value};
+use crate::source_map::SourceMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Commands:
+  s, step [n]         run n instructions (default 1)
+  n, next             run to the next instruction, stepping over calls
+  finish              run until the current function returns
+  c, continue         run until a breakpoint, a watchpoint or the end
+  b, break <where>    break at a label or a source line, list breakpoints without argument
+  delete              remove all breakpoints and watchpoints
+  watch <cell>        stop when a cell is written
+  p, print <cell>     print a cell
+  i, info             show the current state
+  q, quit             leave the debugger
+A cell is a local name, [fp + k], [ap + k] or segment:offset.";
+
+// Interactive step debugger over the vm, reading commands from stdin.
+pub struct Debugger<'a> {
+    vm: MiniVm,
+    hint_processor: HintRegistry,
+    file_name: &'a str,
+    source: &'a str,
+    function_adresses: HashMap<String, u64>,
+    frame_layouts: HashMap<String, Vec<(String, i32)>>,
+    // of main
+    n_return_values: usize,
+    locations: BTreeMap<u64, Span>,
+    breakpoints: BTreeSet<u64>,
+    watches: Vec<Relocatable>,
+    stopped: bool,
+    source_map: Option<SourceMap>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(
+        assembler: &Assembler,
+        frame_layouts: HashMap<String, Vec<(String, i32)>>,
+        n_return_values: usize,
+        file_name: &'a str,
+        source: &'a str,
+        args: &[Felt],
+        hint_processor: HintRegistry,
+    ) -> Result<Self, String> {
+        let mut vm = MiniVm::new();
+        vm.load_program(&Program::from_assembler(assembler, "main")?, args)?;
+        Ok(Self {
+            vm,
+            hint_processor,
+            file_name,
+            source,
+            function_adresses: assembler.function_adresses.clone(),
+            locations: assembler.locations.clone(),
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+            stopped: false,
+            source_map: SourceMap::new(assembler, frame_layouts.keys()),
+            frame_layouts,
+            n_return_values,
+        })
+    }
+
+    pub fn run(&mut self) {
+        println!("Type 'help' for the list of commands.");
+        self.show();
+        let stdin = io::stdin();
+        loop {
+            print!("(czc) ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                println!();
+                return;
+            }
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("");
+            let argument = words.collect::<Vec<_>>().join(" ");
+            match command {
+                "" => {}
+                "s" | "step" => {
+                    let n = argument.parse().unwrap_or(1);
+                    let mut count = 0;
+                    self.resume(|_| {
+                        count += 1;
+                        count >= n
+                    });
+                }
+                "n" | "next" => self.next(),
+                "finish" => self.finish(),
+                "c" | "continue" => self.resume(|_| false),
+                "b" | "break" => self.add_breakpoint(&argument),
+                "delete" => {
+                    self.breakpoints.clear();
+                    self.watches.clear();
+                }
+                "watch" => match self.cell(&argument) {
+                    Ok(addr) => {
+                        println!("Watching {}", addr);
+                        self.watches.push(addr);
+                    }
+                    Err(error) => println!("{}", error),
+                },
+                "p" | "print" => match self.cell(&argument) {
+                    Ok(addr) => println!("{} = {}", addr, self.format_cell(addr)),
+                    Err(error) => println!("{}", error),
+                },
+                "i" | "info" => self.show(),
+                "h" | "help" => println!("{}", HELP),
+                "q" | "quit" => return,
+                _ => println!("Unknown command '{}', type 'help'", command),
+            }
+        }
+    }
+
+    // Steps until `done` returns true, a breakpoint or watchpoint is hit, or the program ends.
+    fn resume(&mut self, mut done: impl FnMut(&MiniVm) -> bool) {
+        if self.stopped {
+            println!("The program is not running");
+            return;
+        }
+        let mut first = true;
+        loop {
+            if self.vm.pc == self.vm.end {
+                self.stopped = true;
+                println!("{}", self.vm.summary(self.n_return_values));
+                return;
+            }
+            if !first
+                && self
+                    .program_pc()
+                    .is_some_and(|pc| self.breakpoints.contains(&pc))
+            {
+                println!("Breakpoint at {}", self.position());
+                break;
+            }
+            first = false;
+
+            let watched: Vec<Option<Value>> = self
+                .watches
+                .iter()
+                .map(|addr| self.vm.memory.get(*addr))
+                .collect();
+            if let Err(error) = self.vm.step(&mut self.hint_processor) {
+                self.stopped = true;
+                match &self.source_map {
+                    Some(source_map) => {
+                        source_map.report_vm_error(&self.vm, &error, MessageFormat::Human)
+                    }
+                    None => println!("Execution failed: {}", error),
+                }
+                return;
+            }
+            let changed: Vec<Relocatable> = self
+                .watches
+                .iter()
+                .zip(watched)
+                .filter(|(addr, old)| self.vm.memory.get(**addr) != *old)
+                .map(|(addr, _)| *addr)
+                .collect();
+            if !changed.is_empty() {
+                for addr in changed {
+                    println!("Watchpoint: {} = {}", addr, self.format_cell(addr));
+                }
+                break;
+            }
+            if done(&self.vm) {
+                break;
+            }
+        }
+        self.show();
+    }
+
+    fn next(&mut self) {
+        match self.current_instruction() {
+            Some((instruction, size)) if instruction.opcode == 1 => {
+                let return_pc = self.vm.pc.add(size as i64).unwrap();
+                let fp = self.vm.fp;
+                self.resume(|vm| vm.pc == return_pc && vm.fp == fp);
+            }
+            _ => self.resume(|_| true),
+        }
+    }
+
+    fn finish(&mut self) {
+        let fp = self.vm.fp;
+        let caller_fp = fp.add(-2).ok().and_then(|addr| self.vm.memory.get(addr));
+        self.resume(|vm| {
+            vm.trace.last().is_some_and(|entry| entry.fp == fp)
+                && Some(Value::Ptr(vm.fp)) == caller_fp
+        });
+    }
+
+    fn add_breakpoint(&mut self, argument: &str) {
+        if argument.is_empty() {
+            for pc in self.breakpoints.iter() {
+                println!("  {}", self.describe_pc(*pc));
+            }
+            return;
+        }
+        let pcs: Vec<u64> = if let Ok(line) = argument.parse::<usize>() {
+            // only the first instruction of each run of instructions on that line
+            let mut previous_line = 0;
+            let mut pcs = Vec::new();
+            for (pc, span) in self.locations.iter() {
+                let span_line = self.line_of(span.0);
+                if span_line == line && previous_line != line {
+                    pcs.push(*pc);
+                }
+                previous_line = span_line;
+            }
+            pcs
+        } else {
+            self.function_adresses
+                .get(argument)
+                .copied()
+                .into_iter()
+                .collect()
+        };
+        if pcs.is_empty() {
+            println!("No code at '{}'", argument);
+        }
+        for pc in pcs {
+            println!("Breakpoint at {}", self.describe_pc(pc));
+            self.breakpoints.insert(pc);
+        }
+    }
+
+    // Resolves a cell given as a local name, [fp + k], [ap + k] or segment:offset.
+    fn cell(&self, argument: &str) -> Result<Relocatable, String> {
+        let argument = argument.trim();
+        if let Some(inner) = argument
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            let inner: String = inner.chars().filter(|c| !c.is_whitespace()).collect();
+            let (register, offset) = inner.split_at(inner.len().min(2));
+            let base = match register {
+                "fp" => self.vm.fp,
+                "ap" => self.vm.ap,
+                _ => return Err(format!("Unknown register in '{}'", argument)),
+            };
+            let offset = match offset.strip_prefix('+') {
+                _ if offset.is_empty() => Ok(0),
+                Some(offset) => offset.parse::<i64>(),
+                None => offset.parse::<i64>(),
+            }
+            .map_err(|_| format!("Invalid offset in '{}'", argument))?;
+            return base.add(offset);
+        }
+        if let Some((segment, offset)) = argument.split_once(':')
+            && let (Ok(segment), Ok(offset)) = (segment.parse(), offset.parse())
+        {
+            return Ok(Relocatable::new(segment, offset));
+        }
+        let (_, offset) = self
+            .frame()
+            .and_then(|(_, layout)| layout.iter().rev().find(|(name, _)| name == argument))
+            .ok_or(format!("Unknown cell '{}'", argument))?;
+        self.vm.fp.add(*offset as i64)
+    }
+
+    fn format_cell(&self, addr: Relocatable) -> String {
+        match self.vm.memory.get(addr) {
+            Some(value) => value.to_string(),
+            None => "<unknown>".to_string(),
+        }
+    }
+
+    fn program_pc(&self) -> Option<u64> {
+        (self.vm.pc.segment == self.vm.program_base.segment).then_some(self.vm.pc.offset)
+    }
+
+    // Instruction at pc, and its size.
+    fn current_instruction(&self) -> Option<(Instruction, u64)> {
+        let word = match self.vm.memory.get(self.vm.pc)? {
+            Value::Int(word) => word.to_u64()?,
+            Value::Ptr(_) => return None,
+        };
+        let imm = match self.vm.memory.get(self.vm.pc.add(1).ok()?) {
+            Some(Value::Int(imm)) => Some(imm),
+            _ => None,
+        };
+        let instruction = Instruction::from_bytes(word, imm).ok()?;
+        let size = if instruction.imm.is_some() { 2 } else { 1 };
+        Some((instruction, size))
+    }
+
+    // Function containing the current pc, with the names of its frame cells.
+    fn frame(&self) -> Option<(&String, &Vec<(String, i32)>)> {
+        let pc = self.program_pc()?;
+        self.frame_layouts
+            .iter()
+            .filter_map(|(name, layout)| {
+                let address = *self.function_adresses.get(name)?;
+                (address <= pc).then_some((address, name, layout))
+            })
+            .max_by_key(|(address, _, _)| *address)
+            .map(|(_, name, layout)| (name, layout))
+    }
+
+    // Closest label before pc, as label+offset.
+    fn describe_pc(&self, pc: u64) -> String {
+        match self
+            .function_adresses
+            .iter()
+            .filter(|(_, address)| **address <= pc)
+            .max_by_key(|(_, address)| **address)
+        {
+            Some((label, address)) if *address == pc => format!("{} (pc {})", label, pc),
+            Some((label, address)) => format!("{}+{} (pc {})", label, pc - address, pc),
+            None => format!("pc {}", pc),
+        }
+    }
+
+    fn position(&self) -> String {
+        match self.program_pc() {
+            Some(pc) => self.describe_pc(pc),
+            None => self.vm.pc.to_string(),
+        }
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        line_col(self.source, offset).0
+    }
+
+    fn show(&self) {
+        println!(
+            "pc={} ap={} fp={}  {}",
+            self.vm.pc,
+            self.vm.ap,
+            self.vm.fp,
+            self.position()
+        );
+        if let Some((instruction, _)) = self.current_instruction() {
+            match disasm::to_casm(&instruction, &self.vm.field) {
+                Ok(casm) => println!("  {:?}", casm),
+                Err(error) => println!("  {}", error),
+            }
+        }
+        if let Some(pc) = self.program_pc()
+            && let Some((_, span)) = self.locations.range(..=pc).next_back()
+        {
+            let (line, col) = line_col(self.source, span.0);
+            let text = self.source.lines().nth(line - 1).unwrap_or("");
+            println!(
+                "  {}:{}:{} | {}",
+                self.file_name,
+                line,
+                col,
+                text.trim_end()
+            );
+            // underline the part of the line the instruction comes from
+            let width = (span.1 - span.0).min(text.len() + 1 - col).max(1);
+            println!(
+                "  {} | {}{}",
+                " ".repeat(format!("{}:{}:{}", self.file_name, line, col).len()),
+                " ".repeat(col - 1),
+                "^".repeat(width)
+            );
+        }
+        if let Some((_, layout)) = self.frame() {
+            // a rebound name shows its latest written cell
+            let mut cells: Vec<(&String, i32)> = Vec::new();
+            for (name, offset) in layout.iter().filter(|(name, _)| !name.starts_with('%')) {
+                let written = self
+                    .vm
+                    .fp
+                    .add(*offset as i64)
+                    .is_ok_and(|addr| self.vm.memory.get(addr).is_some());
+                match cells.iter_mut().find(|(other, _)| *other == name) {
+                    Some(cell) if written => cell.1 = *offset,
+                    Some(_) => {}
+                    None => cells.push((name, *offset)),
+                }
+            }
+            for (name, offset) in cells {
+                let value = self
+                    .vm
+                    .fp
+                    .add(offset as i64)
+                    .map(|addr| self.format_cell(addr))
+                    .unwrap_or_else(|error| error);
+                println!("  {} = {}  [fp + {}]", name, value, offset);
+            }
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use crate::ast::{Span, line_col};
+use ariadne::{Color, Label, Report, ReportKind, Source};
+use std::fmt::{self, Display};
+
+// Error codes, one per kind of problem. Codes are never reused once published.
+pub mod codes {
+    // a character sequence no token starts with
+    pub const UNKNOWN_TOKEN: &str = "E0001";
+    // the tokens do not form a valid program
+    pub const SYNTAX_ERROR: &str = "E0002";
+    // %builtins lists a builtin the runner does not provide
+    pub const UNKNOWN_BUILTIN: &str = "E0003";
+    // a named argument the callee does not declare
+    pub const UNKNOWN_ARGUMENT: &str = "E0004";
+    // an `if` condition that is not a comparison
+    pub const INVALID_CONDITION: &str = "E0005";
+    // `assert x = y` where x is not a name
+    pub const INVALID_ASSIGNMENT: &str = "E0006";
+    // valid syntax the compiler cannot lower yet
+    pub const UNSUPPORTED: &str = "E0007";
+    // an instruction that cannot be encoded
+    pub const ASSEMBLY_ERROR: &str = "E0008";
+    // the vm stopped on an error
+    pub const RUNTIME_ERROR: &str = "E0009";
+    // a name not bound in the current scope
+    pub const UNKNOWN_IDENTIFIER: &str = "E0010";
+    // a call to a function the program does not define
+    pub const UNKNOWN_FUNCTION: &str = "E0011";
+    // a jump or call to a label casm does not define
+    pub const UNKNOWN_LABEL: &str = "E0012";
+    // a value whose type differs from the declared one
+    pub const TYPE_MISMATCH: &str = "E0013";
+    // a call with more or fewer arguments than the callee declares
+    pub const WRONG_ARGUMENT_COUNT: &str = "E0014";
+    // a cast between types of different shapes
+    pub const INVALID_CAST: &str = "E0015";
+    // `x.y` where x is not a struct, or the struct has no member y
+    pub const INVALID_MEMBER_ACCESS: &str = "E0016";
+    // an operator applied to values of the wrong types
+    pub const INVALID_OPERANDS: &str = "E0017";
+    // a type name that is not a struct of the program
+    pub const UNKNOWN_TYPE: &str = "E0018";
+    // an integer literal wider than 256 bits
+    pub const INVALID_LITERAL: &str = "E0019";
+
+    // warnings of the lint pass, see lints.rs
+    pub const UNUSED_LOCAL: &str = "W0001";
+    pub const UNUSED_ARGUMENT: &str = "W0002";
+    pub const UNREACHABLE_CODE: &str = "W0003";
+    pub const ALLOC_LOCALS: &str = "W0004";
+    pub const SHADOWING: &str = "W0005";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// How diagnostics are printed: ariadne reports, or one json object per line for tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+impl MessageFormat {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!(
+                "Unknown message format '{}', expected human or json",
+                name
+            )),
+        }
+    }
+}
+
+// A problem found in a program, located in its source when possible.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // one of `codes`
+    pub code: String,
+    pub message: String,
+    // the offending code, with an optional label
+    pub span: Option<Span>,
+    pub label: Option<String>,
+    // related code, e.g. a declaration
+    pub secondary: Vec<(Span, String)>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &str, message: String) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            message,
+            span: None,
+            label: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn error(code: &str, message: String) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &str, message: String) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, label: String) -> Self {
+        self.span = Some(span);
+        self.label = Some(label);
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, label: String) -> Self {
+        self.secondary.push((span, label));
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn emit(&self, file_name: &str, source: &str, format: MessageFormat) {
+        match format {
+            MessageFormat::Human => self.report(file_name, source),
+            MessageFormat::Json => eprintln!("{}", self.to_json(file_name, source).dump()),
+        }
+    }
+
+    // Prints the diagnostic on the source it refers to.
+    pub fn report(&self, file_name: &str, source: &str) {
+        let Some(span) = self.span else {
+            eprintln!("{}[{}]: {}", self.severity, self.code, self.message);
+            for note in self.notes.iter() {
+                eprintln!("  = note: {}", note);
+            }
+            if let Some(help) = &self.help {
+                eprintln!("  = help: {}", help);
+            }
+            return;
+        };
+        let (kind, color) = match self.severity {
+            Severity::Error => (ReportKind::Error, Color::Red),
+            Severity::Warning => (ReportKind::Warning, Color::Yellow),
+        };
+        let location = |span: Span| (file_name, span.0..span.1);
+        // ariadne only underlines labels with a message
+        let primary = Label::new(location(span))
+            .with_color(color)
+            .with_message(self.label.as_deref().unwrap_or(""));
+        let mut report = Report::build(kind, location(span))
+            .with_code(&self.code)
+            .with_message(&self.message)
+            .with_label(primary);
+        for (span, label) in self.secondary.iter() {
+            report = report.with_label(
+                Label::new(location(*span))
+                    .with_message(label)
+                    .with_color(Color::Blue),
+            );
+        }
+        report.with_notes(self.notes.iter());
+        if let Some(help) = &self.help {
+            report = report.with_help(help);
+        }
+        let _ = report.finish().eprint((file_name, Source::from(source)));
+    }
+
+    // Spans are given both as byte offsets and as 1-based lines and columns.
+    pub fn to_json(&self, file_name: &str, source: &str) -> json::JsonValue {
+        let span_json = |span: Span, label: Option<&String>, primary: bool| {
+            let (line_start, column_start) = line_col(source, span.0);
+            let (line_end, column_end) = line_col(source, span.1);
+            json::object! {
+                file_name: file_name,
+                byte_start: span.0,
+                byte_end: span.1,
+                line_start: line_start,
+                column_start: column_start,
+                line_end: line_end,
+                column_end: column_end,
+                is_primary: primary,
+                label: label.cloned(),
+            }
+        };
+        let mut spans = json::JsonValue::new_array();
+        if let Some(span) = self.span {
+            let _ = spans.push(span_json(span, self.label.as_ref(), true));
+        }
+        for (span, label) in self.secondary.iter() {
+            let _ = spans.push(span_json(*span, Some(label), false));
+        }
+        json::object! {
+            severity: self.severity.to_string(),
+            code: self.code.clone(),
+            message: self.message.clone(),
+            spans: spans,
+            notes: self.notes.clone(),
+            help: self.help.clone(),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn report(&self, file_name: &str, source: &str) {
+        self.emit(file_name, source, MessageFormat::Human);
+    }
+
+    pub fn emit(&self, file_name: &str, source: &str, format: MessageFormat) {
+        for diagnostic in self.0.iter() {
+            diagnostic.emit(file_name, source, format);
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.0.iter().any(Diagnostic::is_error)
+    }
+
+    pub fn n_errors(&self) -> usize {
+        self.0
+            .iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .count()
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
@@ -0,0 +1,270 @@
+use crate::assembler::Instruction;
+use crate::casm::{CasmInstruction, Operand};
+use crate::felt::Field;
+use crate::minivm::Program;
+use std::collections::BTreeMap;
+
+fn register_operand(fp: u8, offset: i32) -> Operand {
+    if fp == 1 {
+        Operand::DerefFp(offset)
+    } else {
+        Operand::DerefAp(offset)
+    }
+}
+
+fn relative_offset(instruction: &Instruction, field: &Field) -> Result<i32, String> {
+    let imm = instruction.imm.unwrap();
+    field
+        .signed(&imm)
+        .and_then(|offset| i32::try_from(offset).ok())
+        .ok_or(format!("Relative offset {} is out of range", imm))
+}
+
+// Recovers the casm instruction from its encoding, for the forms the assembler emits.
+pub fn to_casm(instruction: &Instruction, field: &Field) -> Result<CasmInstruction, String> {
+    let left = register_operand(instruction.dst, instruction.offdst);
+    let op0 = register_operand(instruction.op0, instruction.offop0);
+    let op1 = match (instruction.op1, instruction.imm) {
+        (1, Some(imm)) => Some(Operand::Int(imm)),
+        (2, _) => Some(Operand::DerefFp(instruction.offop1)),
+        (4, _) => Some(Operand::DerefAp(instruction.offop1)),
+        _ => None,
+    };
+    let incr_ap = instruction.ap_update == 2;
+    let casm = match (
+        instruction.opcode,
+        instruction.res,
+        instruction.pc_update,
+        instruction.ap_update,
+        op1,
+    ) {
+        (1, 0, 2, 0, Some(Operand::Int(_))) => {
+            CasmInstruction::CallRel(relative_offset(instruction, field)?)
+        }
+        (1, 0, 1, 0, Some(Operand::Int(imm))) => CasmInstruction::CallAbs(imm),
+        (2, 0, 1, 0, _) => CasmInstruction::Ret,
+        (0, 0, 0, 1, Some(Operand::Int(imm))) => CasmInstruction::IncrAp(
+            imm.to_u64()
+                .ok_or(format!("ap += {} does not fit in 64 bits", imm))?,
+        ),
+        (0, 0, 2, 0, Some(Operand::Int(_))) => {
+            CasmInstruction::JmpRel(relative_offset(instruction, field)?)
+        }
+        (0, 0, 4, 0, Some(Operand::Int(_))) => {
+            CasmInstruction::JmpIfNeqRel(relative_offset(instruction, field)?, left)
+        }
+        (4, 0, 0, 0 | 2, Some(op)) => CasmInstruction::Set { left, op, incr_ap },
+        (4, 0, 0, 0 | 2, None) if instruction.op1 == 0 => CasmInstruction::Deref {
+            left,
+            op: op0,
+            offset: instruction.offop1,
+            incr_ap,
+        },
+        (4, 1, 0, 2, Some(op2)) => CasmInstruction::Add {
+            left,
+            op1: op0,
+            op2,
+        },
+        (4, 2, 0, 2, Some(op2)) => CasmInstruction::Mul {
+            left,
+            op1: op0,
+            op2,
+        },
+        _ => return Err(format!("Unsupported instruction {:?}", instruction)),
+    };
+    Ok(casm)
+}
+
+// Casm listing of a program in the json format of `Assembler::to_json`, in the text
+// form read back by `czc assemble`.
+pub fn listing(text: &str) -> Result<String, String> {
+    let program = Program::from_json(text)?;
+    let json = json::parse(text).map_err(|e| format!("Invalid program: {}", e))?;
+    let mut labels: BTreeMap<u64, Vec<&str>> = BTreeMap::new();
+    for (name, identifier) in json["identifiers"].entries() {
+        if let Some(pc) = identifier["pc"].as_u64() {
+            let name = name.strip_prefix("__main__.").unwrap_or(name);
+            labels.entry(pc).or_default().push(name);
+        }
+    }
+
+    labels.values_mut().for_each(|names| names.sort());
+
+    let mut out = String::new();
+    if !program.builtins.is_empty() {
+        out.push_str(&format!("%builtins {}\n", program.builtins.join(" ")));
+    }
+    let mut pc = 0;
+    while pc < program.data.len() {
+        for label in labels.get(&(pc as u64)).into_iter().flatten() {
+            out.push_str(&format!("{}:\n", label));
+        }
+        for hint in program.hints.get(&(pc as u64)).into_iter().flatten() {
+            out.push_str(&format!("    {:?}\n", CasmInstruction::Hint(hint.clone())));
+        }
+        let word = program.data[pc].to_u64().ok_or(format!(
+            "Invalid instruction {} at pc {}",
+            program.data[pc], pc
+        ))?;
+        let instruction = Instruction::from_bytes(word, program.data.get(pc + 1).copied())
+            .map_err(|error| format!("{} at pc {}", error, pc))?;
+        let casm = to_casm(&instruction, &program.field)
+            .map_err(|error| format!("{} at pc {}", error, pc))?;
+        out.push_str(&format!("    {:<40} // pc {}\n", format!("{:?}", casm), pc));
+        pc += if instruction.imm.is_some() { 2 } else { 1 };
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::build_instruction;
+    use crate::felt::Felt;
+
+    // Encodes, decodes and re-assembles an instruction, which must give it back unchanged.
+    fn round_trip(instruction: &Instruction, field: &Field) -> Result<Instruction, String> {
+        let (word, imm) = instruction.to_bytes();
+        let decoded = Instruction::from_bytes(word, imm)?;
+        build_instruction(to_casm(&decoded, field)?, field)
+    }
+
+    // Offsets at and around the bounds of the 16-bit encoding, and a few in between.
+    fn offsets() -> Vec<i32> {
+        let mut offsets = vec![-(1 << 15), -(1 << 15) + 1, -2, -1, 0, 1, 2, (1 << 15) - 2];
+        offsets.push((1 << 15) - 1);
+        // xorshift, to cover offsets away from the bounds
+        let mut state: u32 = 0x9e3779b9;
+        for _ in 0..8 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            offsets.push((state & 0xffff) as i32 - (1 << 15));
+        }
+        offsets
+    }
+
+    fn registers() -> Vec<Operand> {
+        offsets()
+            .into_iter()
+            .flat_map(|offset| [Operand::DerefFp(offset), Operand::DerefAp(offset)])
+            .collect()
+    }
+
+    // Immediates are field elements, -1 and -2 included.
+    fn immediates(field: &Field) -> Vec<Felt> {
+        let minus = |n: u64| field.prime.overflowing_sub(&Felt::from(n)).0;
+        vec![
+            Felt::ZERO,
+            Felt::ONE,
+            Felt::from(1 << 15),
+            Felt::from(u32::MAX as u64).min(minus(3)),
+            field.prime.shr1(),
+            minus(2),
+            minus(1),
+        ]
+    }
+
+    // Relative jumps as far as the smaller prime can represent, both ways.
+    fn relative_offsets() -> Vec<i32> {
+        let half = (1 << 30) - 1;
+        let mut offsets = offsets();
+        offsets.extend([-half, -(1 << 16), 1 << 16, half]);
+        offsets
+    }
+
+    fn instructions(field: &Field) -> Vec<CasmInstruction> {
+        let registers = registers();
+        let operands: Vec<Operand> = registers
+            .iter()
+            .cloned()
+            .chain(immediates(field).into_iter().map(Operand::Int))
+            .collect();
+        let mut instructions = vec![CasmInstruction::Ret];
+        for offset in relative_offsets() {
+            instructions.push(CasmInstruction::CallRel(offset));
+            instructions.push(CasmInstruction::JmpRel(offset));
+            for condition in registers.iter() {
+                instructions.push(CasmInstruction::JmpIfNeqRel(offset, condition.clone()));
+            }
+        }
+        for imm in immediates(field) {
+            instructions.push(CasmInstruction::CallAbs(imm));
+            if let Some(n) = imm.to_u64() {
+                instructions.push(CasmInstruction::IncrAp(n));
+            }
+        }
+        for left in registers.iter() {
+            for op in operands.iter() {
+                for incr_ap in [false, true] {
+                    instructions.push(CasmInstruction::Set {
+                        left: left.clone(),
+                        op: op.clone(),
+                        incr_ap,
+                    });
+                }
+            }
+            for op in registers.iter() {
+                for offset in offsets() {
+                    for incr_ap in [false, true] {
+                        instructions.push(CasmInstruction::Deref {
+                            left: left.clone(),
+                            op: op.clone(),
+                            offset,
+                            incr_ap,
+                        });
+                    }
+                }
+                for op2 in operands.iter() {
+                    instructions.push(CasmInstruction::Add {
+                        left: left.clone(),
+                        op1: op.clone(),
+                        op2: op2.clone(),
+                    });
+                    instructions.push(CasmInstruction::Mul {
+                        left: left.clone(),
+                        op1: op.clone(),
+                        op2: op2.clone(),
+                    });
+                }
+            }
+        }
+        instructions
+    }
+
+    #[test]
+    fn every_instruction_survives_disassembly() {
+        for field in [Field::M31, Field::STARK] {
+            for casm in instructions(&field) {
+                let instruction = build_instruction(casm.clone(), &field).unwrap();
+                assert_eq!(instruction.check_offsets(), Ok(()), "{:?}", casm);
+                assert_eq!(
+                    round_trip(&instruction, &field),
+                    Ok(instruction.clone()),
+                    "{:?}",
+                    casm
+                );
+                let (word, imm) = instruction.to_bytes();
+                let decoded = Instruction::from_bytes(word, imm).unwrap();
+                let disassembled = to_casm(&decoded, &field).unwrap();
+                assert_eq!(format!("{:?}", disassembled), format!("{:?}", casm));
+            }
+        }
+    }
+
+    #[test]
+    fn offsets_outside_16_bits_are_rejected() {
+        for offset in [-(1 << 15) - 1, 1 << 15] {
+            let instruction = build_instruction(
+                CasmInstruction::Set {
+                    left: Operand::DerefAp(offset),
+                    op: Operand::DerefFp(0),
+                    incr_ap: false,
+                },
+                &Field::M31,
+            )
+            .unwrap();
+            assert!(instruction.check_offsets().is_err());
+        }
+    }
+}
@@ -0,0 +1,456 @@
+// API reference of a tree of modules: the functions with their signatures, the structs with
+// the layout of their members, the constants with their values, the namespaces, their doc
+// comments, and links from every struct a type names to its definition. Written as Markdown
+// or as a standalone HTML page.
+
+use crate::ast::{CodeElement, Expr, Function, Type, TypedIdentifier};
+use crate::formatter;
+use std::collections::HashMap;
+
+pub struct Module {
+    // dotted path of the file in the tree, `math.vec` for math/vec.cairo
+    pub name: String,
+    // flattened, the members of namespaces after them with qualified names
+    pub code_elements: Vec<CodeElement>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+// Code with links in it.
+enum Segment {
+    Text(String),
+    // the struct name and the anchor of its definition
+    Link(String, String),
+}
+
+struct Generator<'a> {
+    format: Format,
+    // members of the structs of every module, by name, and the anchor of each
+    structs: HashMap<&'a str, (&'a [TypedIdentifier], String)>,
+    out: String,
+}
+
+pub fn generate(modules: &[Module], format: Format) -> String {
+    let mut structs = HashMap::new();
+    for module in modules.iter() {
+        for element in module.code_elements.iter() {
+            if let CodeElement::Struct(name, members, _) = element {
+                let name = name.token.lexeme.as_str();
+                structs.insert(name, (members.as_slice(), anchor(&module.name, name)));
+            }
+        }
+    }
+    let mut generator = Generator {
+        format,
+        structs,
+        out: String::new(),
+    };
+    generator.document(modules);
+    generator.out
+}
+
+fn anchor(module: &str, name: &str) -> String {
+    format!("{}.{}", module, name)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn push_text(segments: &mut Vec<Segment>, text: &str) {
+    match segments.last_mut() {
+        Some(Segment::Text(last)) => last.push_str(text),
+        _ => segments.push(Segment::Text(text.to_string())),
+    }
+}
+
+fn documented(module: &Module) -> impl Iterator<Item = &CodeElement> {
+    module.code_elements.iter().filter(|element| {
+        matches!(
+            element,
+            CodeElement::Function(_)
+                | CodeElement::Struct(..)
+                | CodeElement::Const(..)
+                | CodeElement::NameSpace(..)
+        )
+    })
+}
+
+fn name_of(element: &CodeElement) -> &str {
+    match element {
+        CodeElement::Function(function) => &function.name.token.lexeme,
+        CodeElement::Struct(name, _, _)
+        | CodeElement::Const(name, _, _)
+        | CodeElement::NameSpace(name, _, _) => &name.token.lexeme,
+        _ => "",
+    }
+}
+
+impl<'a> Generator<'a> {
+    fn line(&mut self, text: &str) {
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn document(&mut self, modules: &[Module]) {
+        match self.format {
+            Format::Markdown => self.line("# API reference\n"),
+            Format::Html => {
+                self.line("<!DOCTYPE html>");
+                self.line("<html>");
+                self.line("<head>");
+                self.line("<meta charset=\"utf-8\">");
+                self.line("<title>API reference</title>");
+                self.line("<style>");
+                self.line("body { font-family: sans-serif; max-width: 60em; margin: auto; }");
+                self.line("pre, code { font-family: monospace; }");
+                self.line("pre { background: #f4f4f4; padding: 0.5em; }");
+                self.line("table { border-collapse: collapse; }");
+                self.line("td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; }");
+                self.line("</style>");
+                self.line("</head>");
+                self.line("<body>");
+                self.line("<h1>API reference</h1>");
+            }
+        }
+        self.contents(modules);
+        for module in modules.iter() {
+            self.module(module);
+        }
+        if self.format == Format::Html {
+            self.line("</body>");
+            self.line("</html>");
+        }
+    }
+
+    // The modules and what they define, linked to their sections.
+    fn contents(&mut self, modules: &[Module]) {
+        match self.format {
+            Format::Markdown => {
+                for module in modules.iter() {
+                    self.line(&format!("- [{}](#{})", module.name, module.name));
+                    for element in documented(module) {
+                        let name = name_of(element);
+                        self.line(&format!("  - [{}](#{})", name, anchor(&module.name, name)));
+                    }
+                }
+                self.line("");
+            }
+            Format::Html => {
+                self.line("<ul>");
+                for module in modules.iter() {
+                    let name = escape(&module.name);
+                    self.line(&format!("<li><a href=\"#{}\">{}</a>", name, name));
+                    self.line("<ul>");
+                    for element in documented(module) {
+                        let name = name_of(element);
+                        self.line(&format!(
+                            "<li><a href=\"#{}\">{}</a></li>",
+                            escape(&anchor(&module.name, name)),
+                            escape(name)
+                        ));
+                    }
+                    self.line("</ul>");
+                    self.line("</li>");
+                }
+                self.line("</ul>");
+            }
+        }
+    }
+
+    fn module(&mut self, module: &Module) {
+        match self.format {
+            Format::Markdown => {
+                self.line(&format!("<a id=\"{}\"></a>\n", module.name));
+                self.line(&format!("## {}\n", module.name));
+            }
+            Format::Html => {
+                let name = escape(&module.name);
+                self.line(&format!("<h2 id=\"{}\">{}</h2>", name, name));
+            }
+        }
+        for element in documented(module) {
+            match element {
+                CodeElement::Function(function) => self.function(&module.name, function),
+                CodeElement::Struct(name, members, doc) => {
+                    self.struct_(&module.name, &name.token.lexeme, members, doc)
+                }
+                CodeElement::Const(name, value, doc) => {
+                    self.constant(&module.name, &name.token.lexeme, value, doc)
+                }
+                CodeElement::NameSpace(name, _, doc) => {
+                    self.heading(&module.name, "namespace", &name.token.lexeme);
+                    self.doc(doc);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn heading(&mut self, module: &str, keyword: &str, name: &str) {
+        let id = anchor(module, name);
+        match self.format {
+            Format::Markdown => {
+                self.line(&format!("<a id=\"{}\"></a>\n", id));
+                self.line(&format!("### {} {}\n", keyword, name));
+            }
+            Format::Html => self.line(&format!(
+                "<h3 id=\"{}\">{} {}</h3>",
+                escape(&id),
+                keyword,
+                escape(name)
+            )),
+        }
+    }
+
+    // Paragraphs are separated by empty lines, as in Markdown.
+    fn doc(&mut self, doc: &Option<String>) {
+        let Some(doc) = doc else {
+            return;
+        };
+        match self.format {
+            Format::Markdown => self.line(&format!("{}\n", doc)),
+            Format::Html => {
+                for paragraph in doc.split("\n\n") {
+                    self.line(&format!("<p>{}</p>", escape(paragraph.trim())));
+                }
+            }
+        }
+    }
+
+    fn function(&mut self, module: &str, function: &Function) {
+        self.heading(module, "func", &function.name.token.lexeme);
+        let segments = self.signature(function);
+        match self.format {
+            Format::Markdown => {
+                // links do not work inside code blocks, they are listed below it
+                self.line("```cairo");
+                let mut links = Vec::new();
+                for segment in segments.iter() {
+                    match segment {
+                        Segment::Text(text) => self.out.push_str(text),
+                        Segment::Link(name, id) => {
+                            self.out.push_str(name);
+                            let link = format!("[`{}`](#{})", name, id);
+                            if !links.contains(&link) {
+                                links.push(link);
+                            }
+                        }
+                    }
+                }
+                self.line("\n```\n");
+                if !links.is_empty() {
+                    self.line(&format!("Types: {}\n", links.join(", ")));
+                }
+            }
+            Format::Html => {
+                let code = self.render(&segments);
+                self.line(&format!("<pre><code>{}</code></pre>", code));
+            }
+        }
+        self.doc(&function.doc);
+    }
+
+    fn constant(&mut self, module: &str, name: &str, value: &Expr, doc: &Option<String>) {
+        self.heading(module, "const", name);
+        let value = formatter::expression(value).unwrap_or_default();
+        let declaration = format!("const {} = {};", name, value);
+        match self.format {
+            Format::Markdown => self.line(&format!("```cairo\n{}\n```\n", declaration)),
+            Format::Html => self.line(&format!("<pre><code>{}</code></pre>", escape(&declaration))),
+        }
+        self.doc(doc);
+    }
+
+    fn struct_(
+        &mut self,
+        module: &str,
+        name: &str,
+        members: &[TypedIdentifier],
+        doc: &Option<String>,
+    ) {
+        self.heading(module, "struct", name);
+        self.doc(doc);
+        let mut rows = Vec::new();
+        let mut offset = Some(0);
+        for member in members.iter() {
+            let type_ = match &member.type_ {
+                Some(type_) => self.type_(type_),
+                None => self.type_(&Type::Felt),
+            };
+            let type_ = self.render(&type_);
+            let offset_text = offset.map_or("?".to_string(), |offset| offset.to_string());
+            rows.push((offset_text, member.ident.token.lexeme.clone(), type_));
+            let size = member
+                .type_
+                .as_ref()
+                .map_or(Some(1), |type_| self.size(type_, &mut Vec::new()));
+            offset = offset.zip(size).map(|(offset, size)| offset + size);
+        }
+        let size = offset.map_or("unknown".to_string(), |size| size.to_string());
+        match self.format {
+            Format::Markdown => {
+                if !rows.is_empty() {
+                    self.line("| Offset | Member | Type |");
+                    self.line("| --- | --- | --- |");
+                    for (offset, member, type_) in rows {
+                        self.line(&format!("| {} | `{}` | {} |", offset, member, type_));
+                    }
+                    self.line("");
+                }
+                self.line(&format!("Size: {}\n", size));
+            }
+            Format::Html => {
+                if !rows.is_empty() {
+                    self.line("<table>");
+                    self.line("<tr><th>Offset</th><th>Member</th><th>Type</th></tr>");
+                    for (offset, member, type_) in rows {
+                        self.line(&format!(
+                            "<tr><td>{}</td><td><code>{}</code></td><td><code>{}</code></td></tr>",
+                            offset,
+                            escape(&member),
+                            type_
+                        ));
+                    }
+                    self.line("</table>");
+                }
+                self.line(&format!("<p>Size: {}</p>", size));
+            }
+        }
+    }
+
+    // Cells taken by a value of the type, None for structs that are not defined or contain
+    // themselves. `visiting` holds the structs being measured.
+    fn size(&self, type_: &Type, visiting: &mut Vec<String>) -> Option<usize> {
+        match type_ {
+            Type::Felt | Type::CodeOffset | Type::Pointer(_) | Type::Pointer2(_) => Some(1),
+            Type::Tuple(types) => types.iter().map(|type_| self.size(type_, visiting)).sum(),
+            Type::Named(_, inner) => self.size(inner, visiting),
+            Type::Struct(name) => {
+                let name = &name.token.lexeme;
+                if visiting.contains(name) {
+                    return None;
+                }
+                let (members, _) = self.structs.get(name.as_str())?;
+                visiting.push(name.clone());
+                let size = members
+                    .iter()
+                    .map(|member| match &member.type_ {
+                        Some(type_) => self.size(type_, visiting),
+                        None => Some(1),
+                    })
+                    .sum();
+                visiting.pop();
+                size
+            }
+            Type::Error => None,
+        }
+    }
+
+    fn type_(&self, type_: &Type) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        self.push_type(type_, &mut segments);
+        segments
+    }
+
+    fn push_type(&self, type_: &Type, segments: &mut Vec<Segment>) {
+        match type_ {
+            Type::Felt => push_text(segments, "felt"),
+            Type::CodeOffset => push_text(segments, "codeoffset"),
+            Type::Pointer(inner) => {
+                self.push_type(inner, segments);
+                push_text(segments, "*");
+            }
+            Type::Pointer2(inner) => {
+                self.push_type(inner, segments);
+                push_text(segments, "**");
+            }
+            Type::Tuple(types) => {
+                push_text(segments, "(");
+                for (i, type_) in types.iter().enumerate() {
+                    if i > 0 {
+                        push_text(segments, ", ");
+                    }
+                    self.push_type(type_, segments);
+                }
+                push_text(segments, ")");
+            }
+            Type::Struct(name) => {
+                let name = &name.token.lexeme;
+                match self.structs.get(name.as_str()) {
+                    Some((_, id)) => segments.push(Segment::Link(name.clone(), id.clone())),
+                    None => push_text(segments, name),
+                }
+            }
+            Type::Named(name, inner) => {
+                push_text(segments, &format!("{}: ", name.token.lexeme));
+                self.push_type(inner, segments);
+            }
+            Type::Error => push_text(segments, "?"),
+        }
+    }
+
+    fn typed_identifiers(&self, identifiers: &[TypedIdentifier], segments: &mut Vec<Segment>) {
+        for (i, identifier) in identifiers.iter().enumerate() {
+            if i > 0 {
+                push_text(segments, ", ");
+            }
+            push_text(segments, &identifier.ident.token.lexeme);
+            if let Some(type_) = &identifier.type_ {
+                push_text(segments, ": ");
+                self.push_type(type_, segments);
+            }
+        }
+    }
+
+    // The declaration of the function with its decorators, without the body.
+    fn signature(&self, function: &Function) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        for decorator in function.decorators.iter() {
+            push_text(&mut segments, &format!("@{}\n", decorator.token.lexeme));
+        }
+        push_text(
+            &mut segments,
+            &format!("func {}", function.name.token.lexeme),
+        );
+        if !function.implicit_args.is_empty() {
+            push_text(&mut segments, "{");
+            self.typed_identifiers(&function.implicit_args, &mut segments);
+            push_text(&mut segments, "}");
+        }
+        push_text(&mut segments, "(");
+        self.typed_identifiers(&function.args, &mut segments);
+        push_text(&mut segments, ")");
+        if let Some(return_type) = &function.return_type {
+            push_text(&mut segments, " -> ");
+            self.push_type(return_type, &mut segments);
+        }
+        segments
+    }
+
+    // Inline code with links to the structs: code spans around the text in Markdown, the
+    // contents of a <code> element in HTML.
+    fn render(&self, segments: &[Segment]) -> String {
+        let mut text = String::new();
+        for segment in segments.iter() {
+            match (self.format, segment) {
+                (Format::Markdown, Segment::Text(code)) => text.push_str(&format!("`{}`", code)),
+                (Format::Markdown, Segment::Link(name, id)) => {
+                    text.push_str(&format!("[`{}`](#{})", name, id))
+                }
+                (Format::Html, Segment::Text(code)) => text.push_str(&escape(code)),
+                (Format::Html, Segment::Link(name, id)) => {
+                    text.push_str(&format!("<a href=\"#{}\">{}</a>", escape(id), escape(name)))
+                }
+            }
+        }
+        text
+    }
+}
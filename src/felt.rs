@@ -0,0 +1,589 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Display, LowerHex};
+use std::ops::{BitAnd, BitOr, BitXor};
+use std::str::FromStr;
+
+// Unsigned 256 bit integer, stored as little-endian 64 bit limbs.
+// Field elements are kept reduced by `Field`, but the type itself is a plain integer:
+// instruction words do not fit in small fields such as M31 and are stored unreduced.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Felt {
+    limbs: [u64; 4],
+}
+
+impl Felt {
+    pub const ZERO: Felt = Felt::from_limbs([0, 0, 0, 0]);
+    pub const ONE: Felt = Felt::from_limbs([1, 0, 0, 0]);
+
+    pub const fn from_limbs(limbs: [u64; 4]) -> Self {
+        Self { limbs }
+    }
+
+    pub fn to_u64(self) -> Option<u64> {
+        if self.limbs[1..].iter().all(|limb| *limb == 0) {
+            Some(self.limbs[0])
+        } else {
+            None
+        }
+    }
+
+    pub fn to_i128(self) -> Option<i128> {
+        if self.limbs[2] == 0 && self.limbs[3] == 0 && self.limbs[1] >> 63 == 0 {
+            Some(((self.limbs[1] as i128) << 64) | self.limbs[0] as i128)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (i, limb) in self.limbs.iter().enumerate() {
+            bytes[8 * i..8 * i + 8].copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Felt::ZERO
+    }
+
+    // Number of significant bits.
+    pub fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.limbs[i] != 0 {
+                return 64 * i as u32 + 64 - self.limbs[i].leading_zeros();
+            }
+        }
+        0
+    }
+
+    pub fn bit(&self, i: u32) -> bool {
+        (self.limbs[(i / 64) as usize] >> (i % 64)) & 1 == 1
+    }
+
+    pub fn overflowing_add(&self, other: &Felt) -> (Felt, bool) {
+        let mut limbs = [0; 4];
+        let mut carry = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (sum, c1) = self.limbs[i].overflowing_add(other.limbs[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        (Felt::from_limbs(limbs), carry)
+    }
+
+    pub fn overflowing_sub(&self, other: &Felt) -> (Felt, bool) {
+        let mut limbs = [0; 4];
+        let mut borrow = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (diff, b1) = self.limbs[i].overflowing_sub(other.limbs[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        (Felt::from_limbs(limbs), borrow)
+    }
+
+    // Shifts left by one bit, returning the bit shifted out.
+    fn shl1(&self) -> (Felt, bool) {
+        let mut limbs = [0; 4];
+        let mut carry = 0;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = (self.limbs[i] << 1) | carry;
+            carry = self.limbs[i] >> 63;
+        }
+        (Felt::from_limbs(limbs), carry == 1)
+    }
+
+    pub fn shr1(&self) -> Felt {
+        let mut limbs = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let high = if i < 3 { self.limbs[i + 1] << 63 } else { 0 };
+            *limb = (self.limbs[i] >> 1) | high;
+        }
+        Felt::from_limbs(limbs)
+    }
+
+    // 2^n, for n < 256.
+    pub fn pow2(n: u32) -> Felt {
+        let mut limbs = [0; 4];
+        limbs[(n / 64) as usize] = 1 << (n % 64);
+        Felt::from_limbs(limbs)
+    }
+
+    // Schoolbook long division, one bit at a time.
+    pub fn div_rem(&self, divisor: &Felt) -> (Felt, Felt) {
+        assert!(!divisor.is_zero(), "Division by zero");
+        if let (Some(a), Some(b)) = (self.to_u64(), divisor.to_u64()) {
+            return (Felt::from(a / b), Felt::from(a % b));
+        }
+        let mut quotient = Felt::ZERO;
+        let mut remainder = Felt::ZERO;
+        for i in (0..self.bits()).rev() {
+            let (shifted, overflow) = remainder.shl1();
+            remainder = shifted;
+            remainder.limbs[0] |= self.bit(i) as u64;
+            if overflow || remainder >= *divisor {
+                remainder = remainder.overflowing_sub(divisor).0;
+                quotient.limbs[(i / 64) as usize] |= 1 << (i % 64);
+            }
+        }
+        (quotient, remainder)
+    }
+
+    fn map_limbs(&self, other: &Felt, f: impl Fn(u64, u64) -> u64) -> Felt {
+        let mut limbs = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = f(self.limbs[i], other.limbs[i]);
+        }
+        Felt::from_limbs(limbs)
+    }
+}
+
+impl From<u64> for Felt {
+    fn from(n: u64) -> Self {
+        Felt::from_limbs([n, 0, 0, 0])
+    }
+}
+
+impl Ord for Felt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.iter().rev().cmp(other.limbs.iter().rev())
+    }
+}
+
+impl PartialOrd for Felt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl BitAnd for Felt {
+    type Output = Felt;
+    fn bitand(self, other: Felt) -> Felt {
+        self.map_limbs(&other, |a, b| a & b)
+    }
+}
+
+impl BitOr for Felt {
+    type Output = Felt;
+    fn bitor(self, other: Felt) -> Felt {
+        self.map_limbs(&other, |a, b| a | b)
+    }
+}
+
+impl BitXor for Felt {
+    type Output = Felt;
+    fn bitxor(self, other: Felt) -> Felt {
+        self.map_limbs(&other, |a, b| a ^ b)
+    }
+}
+
+// Parses decimal, or hexadecimal with a 0x prefix.
+impl FromStr for Felt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (digits, radix) = match s.strip_prefix("0x") {
+            Some(hex) => (hex, 16),
+            None => (s, 10),
+        };
+        if digits.is_empty() {
+            return Err(format!("Invalid integer '{}'", s));
+        }
+        let radix_felt = Felt::from(radix as u64);
+        let mut value = Felt::ZERO;
+        for c in digits.chars() {
+            let digit = c
+                .to_digit(radix)
+                .ok_or(format!("Invalid integer '{}'", s))?;
+            // value * radix + digit, by repeated doubling
+            let mut product = Felt::ZERO;
+            for i in (0..radix_felt.bits()).rev() {
+                let (doubled, overflow) = product.shl1();
+                product = doubled;
+                let mut overflow = overflow;
+                if radix_felt.bit(i) {
+                    let (sum, carry) = product.overflowing_add(&value);
+                    product = sum;
+                    overflow |= carry;
+                }
+                if overflow {
+                    return Err(format!("Integer '{}' does not fit in 256 bits", s));
+                }
+            }
+            let (sum, overflow) = product.overflowing_add(&Felt::from(digit as u64));
+            if overflow {
+                return Err(format!("Integer '{}' does not fit in 256 bits", s));
+            }
+            value = sum;
+        }
+        Ok(value)
+    }
+}
+
+impl Display for Felt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(n) = self.to_u64() {
+            return Display::fmt(&n, f);
+        }
+        // 19 decimal digits at a time
+        let chunk = Felt::from(10_000_000_000_000_000_000);
+        let mut chunks = Vec::new();
+        let mut rest = *self;
+        while !rest.is_zero() {
+            let (quotient, remainder) = rest.div_rem(&chunk);
+            chunks.push(remainder.limbs[0]);
+            rest = quotient;
+        }
+        let mut digits = chunks.pop().unwrap().to_string();
+        for chunk in chunks.iter().rev() {
+            digits.push_str(&format!("{:019}", chunk));
+        }
+        f.pad_integral(true, "", &digits)
+    }
+}
+
+impl LowerHex for Felt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let top = (0..4).rev().find(|i| self.limbs[*i] != 0).unwrap_or(0);
+        let mut digits = format!("{:x}", self.limbs[top]);
+        for i in (0..top).rev() {
+            digits.push_str(&format!("{:016x}", self.limbs[i]));
+        }
+        f.pad_integral(true, "0x", &digits)
+    }
+}
+
+impl Debug for Felt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+// Miller-Rabin with the first twelve primes as bases: exact below 2^64, and for larger
+// numbers a composite passing all twelve rounds is vanishingly unlikely.
+fn is_prime(n: &Felt) -> bool {
+    const BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    for base in BASES {
+        let base = Felt::from(base);
+        if *n == base {
+            return true;
+        }
+        if n.div_rem(&base).1.is_zero() {
+            return false;
+        }
+    }
+    // n - 1 = d * 2^s with d odd
+    let minus_one = n.overflowing_sub(&Felt::ONE).0;
+    let mut d = minus_one;
+    let mut s = 0;
+    while !d.bit(0) {
+        d = d.shr1();
+        s += 1;
+    }
+    let field = Field { prime: *n };
+    'bases: for base in BASES {
+        let mut x = field.pow(&Felt::from(base), &d);
+        if x == Felt::ONE || x == minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = field.mul(&x, &x);
+            if x == minus_one {
+                continue 'bases;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// Arithmetic modulo the prime of the target field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub prime: Felt,
+}
+
+impl Field {
+    // 2^31 - 1
+    pub const M31: Field = Field {
+        prime: Felt::from_limbs([0x7fffffff, 0, 0, 0]),
+    };
+    // 2^251 + 17 * 2^192 + 1
+    pub const STARK: Field = Field {
+        prime: Felt::from_limbs([1, 0, 0, 0x0800000000000011]),
+    };
+
+    // Target fields by name, or any other prime given as an integer.
+    pub fn from_name(name: &str) -> Result<Field, String> {
+        match name {
+            "m31" => Ok(Field::M31),
+            "stark" => Ok(Field::STARK),
+            _ => {
+                let prime: Felt = name.parse().map_err(|_| {
+                    format!(
+                        "Unknown prime '{}', expected m31, stark or an integer",
+                        name
+                    )
+                })?;
+                if prime.bits() < 2 || !prime.bit(0) || !is_prime(&prime) {
+                    return Err(format!("{} is not an odd prime", name));
+                }
+                // instruction offsets and range checked limbs are 16-bit values
+                if prime < Felt::pow2(16) {
+                    return Err(format!("{} is too small, primes start above 2^16", name));
+                }
+                Ok(Field { prime })
+            }
+        }
+    }
+
+    pub fn reduce(&self, a: &Felt) -> Felt {
+        if *a < self.prime {
+            *a
+        } else {
+            a.div_rem(&self.prime).1
+        }
+    }
+
+    pub fn add(&self, a: &Felt, b: &Felt) -> Felt {
+        let (sum, overflow) = self.reduce(a).overflowing_add(&self.reduce(b));
+        if overflow || sum >= self.prime {
+            sum.overflowing_sub(&self.prime).0
+        } else {
+            sum
+        }
+    }
+
+    pub fn neg(&self, a: &Felt) -> Felt {
+        let a = self.reduce(a);
+        if a.is_zero() {
+            a
+        } else {
+            self.prime.overflowing_sub(&a).0
+        }
+    }
+
+    pub fn sub(&self, a: &Felt, b: &Felt) -> Felt {
+        self.add(a, &self.neg(b))
+    }
+
+    pub fn mul(&self, a: &Felt, b: &Felt) -> Felt {
+        let (a, b) = (self.reduce(a), self.reduce(b));
+        if let (Some(p), Some(a), Some(b)) = (self.prime.to_u64(), a.to_u64(), b.to_u64()) {
+            return Felt::from((a as u128 * b as u128 % p as u128) as u64);
+        }
+        // double and add, from the most significant bit of b
+        let mut res = Felt::ZERO;
+        for i in (0..b.bits()).rev() {
+            res = self.add(&res, &res);
+            if b.bit(i) {
+                res = self.add(&res, &a);
+            }
+        }
+        res
+    }
+
+    pub fn pow(&self, base: &Felt, exp: &Felt) -> Felt {
+        let base = self.reduce(base);
+        let mut res = Felt::ONE;
+        for i in (0..exp.bits()).rev() {
+            res = self.mul(&res, &res);
+            if exp.bit(i) {
+                res = self.mul(&res, &base);
+            }
+        }
+        res
+    }
+
+    pub fn inv(&self, a: &Felt) -> Felt {
+        let exp = self.prime.overflowing_sub(&Felt::from(2)).0;
+        self.pow(a, &exp)
+    }
+
+    pub fn reduce_i128(&self, n: i128) -> Felt {
+        let magnitude = Felt::from_limbs([
+            n.unsigned_abs() as u64,
+            (n.unsigned_abs() >> 64) as u64,
+            0,
+            0,
+        ]);
+        if n < 0 {
+            self.neg(&magnitude)
+        } else {
+            self.reduce(&magnitude)
+        }
+    }
+
+    // Maps a field element to a signed integer, values above prime / 2 are negative.
+    pub fn signed(&self, a: &Felt) -> Option<i64> {
+        let a = self.reduce(a);
+        if a <= self.prime.shr1() {
+            a.to_u64().and_then(|n| i64::try_from(n).ok())
+        } else {
+            let n = self.prime.overflowing_sub(&a).0.to_u64()?;
+            i64::try_from(n).ok().map(|n| -n)
+        }
+    }
+
+    // Values below this bound are accepted by range checks: 8 limbs of 16 bits like cairo,
+    // or as many limbs as fit below the prime for smaller fields (one for M31).
+    pub fn range_check_bound(&self) -> Felt {
+        let mut limbs = 8;
+        while limbs > 1 && Felt::pow2(16 * limbs) >= self.prime {
+            limbs -= 1;
+        }
+        Felt::pow2(16 * limbs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STARK_DECIMAL: &str =
+        "3618502788666131213697322783095070105623107215331596699973092056135872020481";
+
+    fn felt(s: &str) -> Felt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_decimal_and_hex() {
+        assert_eq!(felt("0"), Felt::ZERO);
+        assert_eq!(felt("2147483647"), Field::M31.prime);
+        assert_eq!(felt("0x7fffffff"), Field::M31.prime);
+        assert_eq!(felt(STARK_DECIMAL), Field::STARK.prime);
+        assert_eq!(
+            felt("0x800000000000011000000000000000000000000000000000000000000000001"),
+            Field::STARK.prime
+        );
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(felt(max), Felt::from_limbs([u64::MAX; 4]));
+    }
+
+    #[test]
+    fn rejects_invalid_integers() {
+        for s in ["", "0x", "12a", "-1", "0xg"] {
+            assert!(s.parse::<Felt>().is_err(), "{}", s);
+        }
+        // 2^256
+        let overflow =
+            "115792089237316195423570985008687907853269984665640564039457584007913129639936";
+        assert!(overflow.parse::<Felt>().is_err());
+        assert!(format!("0x1{}", "0".repeat(64)).parse::<Felt>().is_err());
+    }
+
+    #[test]
+    fn displays_decimal_and_hex() {
+        assert_eq!(Field::M31.prime.to_string(), "2147483647");
+        assert_eq!(Field::STARK.prime.to_string(), STARK_DECIMAL);
+        assert_eq!(format!("{:x}", Field::M31.prime), "7fffffff");
+        assert_eq!(
+            format!("{:#x}", Field::STARK.prime),
+            "0x800000000000011000000000000000000000000000000000000000000000001"
+        );
+        assert_eq!(Felt::pow2(64).to_string(), "18446744073709551616");
+        assert_eq!(format!("{:x}", Felt::pow2(64)), "10000000000000000");
+    }
+
+    #[test]
+    fn divides_with_remainder() {
+        assert_eq!(felt("17").div_rem(&felt("5")), (felt("3"), felt("2")));
+        let a = Felt::pow2(255)
+            .overflowing_add(&felt("12345678901234567890"))
+            .0;
+        let b = felt("1000000000000000000000000000007");
+        assert_eq!(
+            a.div_rem(&b),
+            (
+                felt("57896044618658097711785492503938681614304385648"),
+                felt("837783572213566911557668688322")
+            )
+        );
+        assert_eq!(b.div_rem(&a), (Felt::ZERO, b));
+    }
+
+    #[test]
+    fn m31_arithmetic() {
+        let field = Field::M31;
+        let minus_one = felt("2147483646");
+        assert_eq!(field.add(&minus_one, &felt("2")), Felt::ONE);
+        assert_eq!(field.sub(&Felt::ZERO, &Felt::ONE), minus_one);
+        assert_eq!(field.neg(&Felt::ZERO), Felt::ZERO);
+        assert_eq!(
+            field.mul(&felt("123456789"), &felt("987654321")),
+            felt("2137109934")
+        );
+        assert_eq!(field.mul(&Felt::pow2(30), &felt("4")), felt("2"));
+        assert_eq!(field.inv(&felt("7")), felt("1840700269"));
+        assert_eq!(field.inv(&felt("2")), Felt::pow2(30));
+        assert_eq!(field.reduce(&Felt::pow2(31)), Felt::ONE);
+        assert_eq!(field.signed(&minus_one), Some(-1));
+        assert_eq!(field.signed(&felt("5")), Some(5));
+        assert_eq!(field.signed(&felt("1073741823")), Some(1073741823));
+        assert_eq!(field.signed(&felt("1073741824")), Some(-1073741823));
+        assert_eq!(field.reduce_i128(-3), felt("2147483644"));
+    }
+
+    #[test]
+    fn stark_arithmetic() {
+        let field = Field::STARK;
+        let minus_one = field.prime.overflowing_sub(&Felt::ONE).0;
+        let x = felt("0x123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef");
+        let y = felt("0xfedcba9876543210fedcba9876543210fedcba9876543210fedcba987654321");
+        assert_eq!(field.add(&minus_one, &Felt::ONE), Felt::ZERO);
+        assert_eq!(
+            field.add(&x, &y),
+            felt("482467038488817261232985032335706214795712756917572656433478025619618992398")
+        );
+        assert_eq!(
+            field.mul(&x, &y),
+            felt("1739239947416241301825252161959984971738573243350466825513941158949827822780")
+        );
+        assert_eq!(field.mul(&minus_one, &minus_one), Felt::ONE);
+        assert_eq!(
+            field.inv(&x),
+            felt("904206241677938452828503336127309529219564870519068093367954232664070564947")
+        );
+        assert_eq!(
+            field.inv(&felt("2")),
+            felt("1809251394333065606848661391547535052811553607665798349986546028067936010241")
+        );
+        assert_eq!(field.signed(&minus_one), Some(-1));
+        assert_eq!(field.signed(&felt("42")), Some(42));
+        assert_eq!(field.signed(&x), None);
+        assert_eq!(field.reduce_i128(-1), minus_one);
+    }
+
+    #[test]
+    fn checks_primes_by_name() {
+        assert_eq!(Field::from_name("m31"), Ok(Field::M31));
+        assert_eq!(Field::from_name("stark"), Ok(Field::STARK));
+        assert_eq!(Field::from_name("0x7fffffff"), Ok(Field::M31));
+        assert_eq!(Field::from_name(STARK_DECIMAL), Ok(Field::STARK));
+        // 2^16 + 1
+        assert!(Field::from_name("65537").is_ok());
+        // 2^61 - 1
+        assert!(Field::from_name("2305843009213693951").is_ok());
+        // 91 = 7 * 13, 561 is a Carmichael number, 2^31 + 1 is divisible by 3
+        for name in ["0", "1", "2", "4", "91", "561", "2147483649", "goldilocks"] {
+            assert!(Field::from_name(name).is_err(), "{}", name);
+        }
+        let composite = Field::STARK.prime.overflowing_add(&Felt::pow2(1)).0;
+        assert!(Field::from_name(&composite.to_string()).is_err());
+    }
+
+    #[test]
+    fn rejects_primes_below_2_16() {
+        // 65521 is the largest prime below 2^16
+        for name in ["3", "97", "65521"] {
+            assert_eq!(
+                Field::from_name(name),
+                Err(format!("{} is too small, primes start above 2^16", name))
+            );
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, codes};
+use crate::felt::Felt;
+use crate::lexer::{Token, TokenType};
+use std::collections::{HashMap, HashSet};
+
+// Removes namespaces and constants before the checks and lowering. The members of a namespace
+// become declarations of the file named `Namespace.member`, and the names used inside it are
+// qualified when the namespace declares them. Constants are evaluated, and replaced by their
+// values where they are used.
+//
+// A constant is an integer expression of literals and the constants declared before it.
+
+struct Flattener {
+    // qualified names of the functions and structs
+    declarations: HashSet<String>,
+    // values of the constants, by qualified name
+    constants: HashMap<String, i128>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub fn flatten(code_elements: &[CodeElement]) -> (Vec<CodeElement>, Vec<Diagnostic>) {
+    let mut flattener = Flattener {
+        declarations: HashSet::new(),
+        constants: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
+    flattener.declare(code_elements, &[]);
+    let mut flattened = Vec::new();
+    flattener.elements(code_elements, &[], &mut flattened);
+    (flattened, flattener.diagnostics)
+}
+
+// `name` in the namespace at `path`.
+fn qualified(path: &[String], name: &str) -> String {
+    path.iter()
+        .map(String::as_str)
+        .chain(std::iter::once(name))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn renamed(ident: &Identifier, name: String) -> Identifier {
+    Identifier {
+        token: Token {
+            lexeme: name,
+            ..ident.token.clone()
+        },
+    }
+}
+
+// The value of a constant where it is used, at `span`.
+fn literal(value: i128, span: Span) -> Expr {
+    let token = Token {
+        token_type: TokenType::Int,
+        lexeme: value.unsigned_abs().to_string(),
+        span,
+    };
+    let literal = Expr::new_terminal(ExprType::IntegerLiteral, token);
+    if value < 0 {
+        Expr::new_unary(ExprType::Neg, literal)
+    } else {
+        literal
+    }
+}
+
+impl Flattener {
+    // The declaration `name` refers to from the namespace at `path`: the innermost of the
+    // enclosing namespaces that declares it, or the file.
+    fn resolve(
+        &self,
+        path: &[String],
+        name: &str,
+        declared: impl Fn(&str) -> bool,
+    ) -> Option<String> {
+        (0..=path.len())
+            .rev()
+            .map(|depth| qualified(&path[..depth], name))
+            .find(|candidate| declared(candidate))
+    }
+
+    fn declaration(&self, path: &[String], name: &str) -> Option<String> {
+        self.resolve(path, name, |candidate| {
+            self.declarations.contains(candidate)
+        })
+    }
+
+    fn constant(&self, path: &[String], name: &str) -> Option<i128> {
+        self.resolve(path, name, |candidate| {
+            self.constants.contains_key(candidate)
+        })
+        .map(|name| self.constants[&name])
+    }
+
+    fn error(&mut self, code: &str, message: String, span: Option<Span>) {
+        let mut diagnostic = Diagnostic::error(code, message);
+        if let Some(span) = span {
+            diagnostic = diagnostic.with_span(span);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Names the functions and structs, and evaluates the constants in order.
+    fn declare(&mut self, code_elements: &[CodeElement], path: &[String]) {
+        for element in code_elements {
+            match element {
+                CodeElement::Function(Function { name, .. }) | CodeElement::Struct(name, ..) => {
+                    self.declarations
+                        .insert(qualified(path, &name.token.lexeme));
+                }
+                CodeElement::Const(name, value, _) => {
+                    if let Some(value) = self.evaluate(path, value) {
+                        self.constants
+                            .insert(qualified(path, &name.token.lexeme), value);
+                    }
+                }
+                CodeElement::NameSpace(name, members, _) => {
+                    let mut path = path.to_vec();
+                    path.push(name.token.lexeme.clone());
+                    self.declare(members, &path);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Reports why the value could not be computed, once.
+    fn evaluate(&mut self, path: &[String], expr: &Expr) -> Option<i128> {
+        let reported = self.diagnostics.len();
+        let operands = |flattener: &mut Self| {
+            let left = flattener.evaluate(path, expr.left.as_ref()?)?;
+            let right = flattener.evaluate(path, expr.right.as_ref()?)?;
+            Some((left, right))
+        };
+        if let (ExprType::TupleOrParen, [ExprAssignment::Expr(inner)]) =
+            (&expr.expr_type, expr.paren_args.as_slice())
+        {
+            return self.evaluate(path, inner);
+        }
+        let value = match expr.expr_type {
+            ExprType::IntegerLiteral => {
+                let token = expr.token.as_ref()?;
+                match token.lexeme.parse::<Felt>() {
+                    Ok(value) => value.to_i128(),
+                    Err(message) => {
+                        self.error(codes::INVALID_LITERAL, message, Some(token.span));
+                        return None;
+                    }
+                }
+            }
+            ExprType::Identifier => {
+                let ident = expr.ident.as_ref()?;
+                let value = self.constant(path, &ident.token.lexeme);
+                if value.is_none() {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            codes::UNKNOWN_IDENTIFIER,
+                            format!("Unknown constant '{}'", ident.token.lexeme),
+                        )
+                        .with_label(
+                            ident.token.span,
+                            "not a constant declared before this one".to_string(),
+                        ),
+                    );
+                    return None;
+                }
+                value
+            }
+            ExprType::Add => operands(self).and_then(|(left, right)| left.checked_add(right)),
+            ExprType::Sub => operands(self).and_then(|(left, right)| left.checked_sub(right)),
+            ExprType::Mul => operands(self).and_then(|(left, right)| left.checked_mul(right)),
+            ExprType::Pow => operands(self)
+                .and_then(|(left, right)| left.checked_pow(u32::try_from(right).ok()?)),
+            ExprType::Neg => self.evaluate(path, expr.left.as_ref()?)?.checked_neg(),
+            _ => {
+                self.error(
+                    codes::UNSUPPORTED,
+                    "Constants are integer expressions of literals and other constants".to_string(),
+                    expr.span(),
+                );
+                return None;
+            }
+        };
+        if value.is_none() && self.diagnostics.len() == reported {
+            self.error(
+                codes::INVALID_LITERAL,
+                "Constant out of the range of 128-bit integers".to_string(),
+                expr.span(),
+            );
+        }
+        value
+    }
+
+    fn elements(
+        &mut self,
+        code_elements: &[CodeElement],
+        path: &[String],
+        out: &mut Vec<CodeElement>,
+    ) {
+        for element in code_elements {
+            match element {
+                CodeElement::Function(function) => {
+                    let mut function = function.clone();
+                    function.name =
+                        renamed(&function.name, qualified(path, &function.name.token.lexeme));
+                    let mut bound = HashSet::new();
+                    for arg in function
+                        .implicit_args
+                        .iter_mut()
+                        .chain(function.args.iter_mut())
+                    {
+                        bound.insert(arg.ident.token.lexeme.clone());
+                        self.typed_identifier(path, arg);
+                    }
+                    if let Some(return_type) = &mut function.return_type {
+                        self.type_(path, return_type);
+                    }
+                    self.block(path, &mut bound, &mut function.body);
+                    out.push(CodeElement::Function(function));
+                }
+                CodeElement::Struct(name, members, doc) => {
+                    let mut members = members.clone();
+                    for member in members.iter_mut() {
+                        self.typed_identifier(path, member);
+                    }
+                    let name = renamed(name, qualified(path, &name.token.lexeme));
+                    out.push(CodeElement::Struct(name, members, doc.clone()));
+                }
+                // those that could not be evaluated were reported by declare
+                CodeElement::Const(name, _, doc) => {
+                    let name = renamed(name, qualified(path, &name.token.lexeme));
+                    if let Some(value) = self.constants.get(&name.token.lexeme) {
+                        let value = literal(*value, name.token.span);
+                        out.push(CodeElement::Const(name, value, doc.clone()));
+                    }
+                }
+                // the namespace stays for its doc comment, its members follow it
+                CodeElement::NameSpace(name, members, doc) => {
+                    let mut path = path.to_vec();
+                    path.push(name.token.lexeme.clone());
+                    let name = renamed(name, path.join("."));
+                    out.push(CodeElement::NameSpace(name, Vec::new(), doc.clone()));
+                    self.elements(members, &path, out);
+                }
+                element => out.push(element.clone()),
+            }
+        }
+    }
+
+    fn typed_identifier(&self, path: &[String], typed_identifier: &mut TypedIdentifier) {
+        if let Some(type_) = &mut typed_identifier.type_ {
+            self.type_(path, type_);
+        }
+    }
+
+    fn type_(&self, path: &[String], type_: &mut Type) {
+        match type_ {
+            Type::Struct(name) => {
+                if let Some(declaration) = self.declaration(path, &name.token.lexeme) {
+                    *name = renamed(name, declaration);
+                }
+            }
+            Type::Pointer(inner) | Type::Pointer2(inner) | Type::Named(_, inner) => {
+                self.type_(path, inner)
+            }
+            Type::Tuple(types) => {
+                for type_ in types.iter_mut() {
+                    self.type_(path, type_);
+                }
+            }
+            Type::Felt | Type::CodeOffset | Type::Error => {}
+        }
+    }
+
+    // `bound` holds the arguments and references of the function, which hide the constants
+    // of the same name.
+    fn block(&self, path: &[String], bound: &mut HashSet<String>, body: &mut [CodeElement]) {
+        for element in body.iter_mut() {
+            match element {
+                CodeElement::LocalVar(ident, expr) => {
+                    bound.insert(ident.ident.token.lexeme.clone());
+                    self.typed_identifier(path, ident);
+                    if let Some(expr) = expr {
+                        self.expr(path, bound, expr);
+                    }
+                }
+                CodeElement::Reference(ident, expr) => {
+                    self.expr(path, bound, expr);
+                    bound.insert(ident.ident.token.lexeme.clone());
+                    self.typed_identifier(path, ident);
+                }
+                CodeElement::Instruction(instruction) => {
+                    if let Some(ident) = &mut instruction.ident
+                        && let Some(declaration) = self.declaration(path, &ident.token.lexeme)
+                    {
+                        *ident = renamed(ident, declaration);
+                    }
+                    for arg in instruction.args.iter_mut() {
+                        self.expr(path, bound, arg);
+                    }
+                }
+                CodeElement::CompoundAssertEqual(left, right) => {
+                    self.expr(path, bound, left);
+                    self.expr(path, bound, right);
+                }
+                CodeElement::Return(expr) | CodeElement::FuncCall(expr) => {
+                    self.expr(path, bound, expr)
+                }
+                CodeElement::If(condition, body, else_body) => {
+                    self.expr(path, bound, condition);
+                    self.block(path, bound, body);
+                    self.block(path, bound, else_body);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn expr(&self, path: &[String], bound: &HashSet<String>, expr: &mut Expr) {
+        match expr.expr_type {
+            ExprType::Identifier => {
+                let Some(ident) = &expr.ident else {
+                    return;
+                };
+                // members of a reference are not constants
+                let name = &ident.token.lexeme;
+                let base = name.split('.').next().unwrap_or_default();
+                if !bound.contains(base)
+                    && let Some(value) = self.constant(path, name)
+                {
+                    *expr = literal(value, ident.token.span);
+                }
+                return;
+            }
+            ExprType::FunctionCall => {
+                if let Some(callee) = &mut expr.ident
+                    && let Some(declaration) = self.declaration(path, &callee.token.lexeme)
+                {
+                    *callee = renamed(callee, declaration);
+                }
+            }
+            _ => {}
+        }
+        if let Some(type_) = &mut expr.type_arg {
+            self.type_(path, type_);
+        }
+        for child in [&mut expr.left, &mut expr.right].into_iter().flatten() {
+            self.expr(path, bound, child);
+        }
+        for arg in expr.paren_args.iter_mut().chain(expr.brace_args.iter_mut()) {
+            match arg {
+                ExprAssignment::Expr(expr) | ExprAssignment::Assign(_, expr) => {
+                    self.expr(path, bound, expr)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cst, formatter};
+
+    fn flattened(source: &str) -> Vec<CodeElement> {
+        let (tokens, _) = cst::tokenize(source);
+        let (_, code_elements, diagnostics) = cst::parse(tokens, source);
+        assert!(diagnostics.is_empty());
+        let (code_elements, diagnostics) = flatten(&code_elements);
+        assert!(diagnostics.is_empty());
+        code_elements
+    }
+
+    #[test]
+    fn qualifies_members_and_replaces_constants() {
+        let source = "const N = 3;\n\
+            namespace math {\n\
+                const M = -(N * 2);\n\
+                func f(x: felt) -> felt {\n\
+                    return g(x) + M;\n\
+                }\n\
+                func g(N: felt) -> felt {\n\
+                    return N;\n\
+                }\n\
+            }\n";
+        let code_elements = flattened(source);
+        let names: Vec<String> = code_elements
+            .iter()
+            .map(|element| match element {
+                CodeElement::Const(name, value, _) => {
+                    format!(
+                        "{} = {}",
+                        name.token.lexeme,
+                        formatter::expression(value).unwrap()
+                    )
+                }
+                CodeElement::NameSpace(name, ..) => name.token.lexeme.clone(),
+                CodeElement::Function(function) => {
+                    let CodeElement::Return(value) = &function.body[0] else {
+                        panic!("expected a return, got {:?}", function.body[0]);
+                    };
+                    format!(
+                        "{} returns {}",
+                        function.name.token.lexeme,
+                        formatter::expression(value).unwrap()
+                    )
+                }
+                element => format!("{:?}", element),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "N = 3",
+                "math",
+                "math.M = -6",
+                "math.f returns math.g(x) + -6",
+                "math.g returns N",
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_follow_operator_precedence() {
+        let source = "const A = 2 * 3 + 4 * 5;\n\
+            const B = 100 - 2 * 3 - 4;\n\
+            const C = 2 ** 3 ** 2 + 1;\n\
+            const D = -2 ** 2 * 3;\n";
+        let values: Vec<String> = flattened(source)
+            .iter()
+            .map(|element| match element {
+                CodeElement::Const(_, value, _) => formatter::expression(value).unwrap(),
+                element => format!("{:?}", element),
+            })
+            .collect();
+        assert_eq!(values, ["26", "90", "513", "-12"]);
+    }
+}
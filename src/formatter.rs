@@ -0,0 +1,606 @@
+// Prints the AST back as source, in a canonical layout: 4-space indents, one
+// statement per line, and only the parentheses the parser needs. Comments come from
+// the lexer's token stream and stay next to the code they were written next to.
+
+use crate::ast::*;
+use crate::cst::{SyntaxTree, Trivia};
+use crate::lexer::{Token, TokenType};
+
+const INDENT: &str = "    ";
+// calls and signatures longer than this get one argument per line
+const MAX_WIDTH: usize = 100;
+
+// Binding strength of an expression, following the levels of the parser.
+fn level(expr: &Expr) -> u8 {
+    match expr.expr_type {
+        ExprType::Add | ExprType::Sub => 1,
+        ExprType::Mul | ExprType::Div => 2,
+        ExprType::Neg | ExprType::AddressOf | ExprType::New => 3,
+        ExprType::Pow => 4,
+        ExprType::And => 5,
+        ExprType::Eq | ExprType::Neq => 6,
+        _ => 7,
+    }
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    // tokens of the code, ending with Eof, and the comments between them
+    tokens: Vec<&'a Token>,
+    comments: Vec<&'a Trivia>,
+    // first token of the code and first comment not printed yet
+    cursor: usize,
+    comment: usize,
+    // end of the last token or comment printed
+    last_end: usize,
+    out: String,
+}
+
+// Returns an error for the parts of the language the formatter does not handle yet.
+pub fn format(
+    code_elements: &[CodeElement],
+    tree: &SyntaxTree,
+    source: &str,
+) -> Result<String, String> {
+    let mut formatter = Formatter {
+        source,
+        tokens: tree.code_tokens().collect(),
+        comments: tree.comments().collect(),
+        cursor: 0,
+        comment: 0,
+        last_end: 0,
+        out: String::new(),
+    };
+    formatter.declarations(code_elements, 0)?;
+    formatter.comments_before(source.len() + 1, 0);
+    Ok(formatter.out)
+}
+
+// Whether a blank line goes between two declarations of a file or a namespace.
+fn separated(previous: &CodeElement, element: &CodeElement) -> bool {
+    let is_block = |element: &CodeElement| {
+        matches!(
+            element,
+            CodeElement::Function(_) | CodeElement::Struct(..) | CodeElement::NameSpace(..)
+        )
+    };
+    is_block(element) || is_block(previous) || matches!(previous, CodeElement::Directive(..))
+}
+
+impl Formatter<'_> {
+    // start of the next token of the code
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.cursor)
+            .map_or(self.source.len(), |token| token.span.0)
+    }
+
+    // Index of the first token from `from` on for which `stop` holds, outside of the
+    // parentheses, brackets and braces opened after `from`.
+    fn find_from(&self, from: usize, stop: impl Fn(&TokenType) -> bool) -> usize {
+        let mut depth = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(from) {
+            if depth == 0 && stop(&token.token_type) {
+                return i;
+            }
+            match token.token_type {
+                TokenType::LParen | TokenType::LBracket | TokenType::LBrace => depth += 1,
+                TokenType::RParen | TokenType::RBracket | TokenType::RBrace => depth -= 1,
+                TokenType::Eof => return i,
+                _ => {}
+            }
+        }
+        self.tokens.len().saturating_sub(1)
+    }
+
+    fn find(&self, token_type: TokenType) -> usize {
+        self.find_from(self.cursor, |other| *other == token_type)
+    }
+
+    // At most one blank line, and none at the start of a block.
+    fn blank_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("{\n") && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    // Keeps a blank line if the source has some before `start`.
+    fn blank_lines_before(&mut self, start: usize) {
+        let gap = &self.source[self.last_end.min(start)..start];
+        if gap.matches('\n').count() > 1 {
+            self.blank_line();
+        }
+    }
+
+    // Prints the comments that start before `end`, each on its own line.
+    fn comments_before(&mut self, end: usize, indent: usize) {
+        while let Some(&comment) = self.comments.get(self.comment)
+            && comment.span.0 < end
+        {
+            self.blank_lines_before(comment.span.0);
+            self.out.push_str(&format!(
+                "{}{}\n",
+                INDENT.repeat(indent),
+                comment.text.trim_end()
+            ));
+            self.last_end = comment.span.1;
+            self.comment += 1;
+        }
+    }
+
+    // Prints `text`, the layout of the tokens up to `last`, followed by the comment written
+    // after them on the same line. Comments between the tokens go on the lines before.
+    fn line(&mut self, text: String, indent: usize, last: usize) {
+        let Some(&last_token) = self.tokens.get(last) else {
+            return;
+        };
+        self.comments_before(last_token.span.0, indent);
+        self.out.push_str(&text);
+        self.cursor = last + 1;
+        self.last_end = last_token.span.1;
+        if let Some(&comment) = self.comments.get(self.comment)
+            && comment.span.0 < self.position()
+            && !self.source[self.last_end..comment.span.0].contains('\n')
+        {
+            self.out.push_str("  ");
+            self.out.push_str(comment.text.trim_end());
+            self.last_end = comment.span.1;
+            self.comment += 1;
+        }
+        self.out.push('\n');
+    }
+
+    // Prints the line of the `}` closing a block, and `extra` tokens after it.
+    fn close(&mut self, text: String, indent: usize, extra: usize) {
+        let brace = self.find(TokenType::RBrace);
+        // comments at the end of the block are indented with it
+        let start = self.tokens[brace].span.0;
+        self.comments_before(start, indent + 1);
+        self.line(text, indent, brace + extra);
+    }
+
+    fn block(&mut self, body: &[CodeElement], indent: usize) -> Result<(), String> {
+        for element in body.iter() {
+            self.code_element(element, indent)?;
+        }
+        Ok(())
+    }
+
+    fn declarations(&mut self, elements: &[CodeElement], indent: usize) -> Result<(), String> {
+        for (i, element) in elements.iter().enumerate() {
+            if i > 0 && separated(&elements[i - 1], element) {
+                self.blank_line();
+            }
+            self.code_element(element, indent)?;
+        }
+        Ok(())
+    }
+
+    fn code_element(&mut self, element: &CodeElement, indent: usize) -> Result<(), String> {
+        let pad = INDENT.repeat(indent);
+        self.comments_before(self.position(), indent);
+        self.blank_lines_before(self.position());
+        match element {
+            CodeElement::If(condition, body, else_body) => {
+                let brace = self.find(TokenType::LBrace);
+                self.line(
+                    format!("{}if ({}) {{", pad, expression(condition)?),
+                    indent,
+                    brace,
+                );
+                self.block(body, indent + 1)?;
+                let brace = self.find(TokenType::RBrace);
+                if self
+                    .tokens
+                    .get(brace + 1)
+                    .is_some_and(|token| token.token_type == TokenType::Else)
+                {
+                    self.close(format!("{}}} else {{", pad), indent, 2);
+                    self.block(else_body, indent + 1)?;
+                }
+                self.close(format!("{}}}", pad), indent, 0);
+            }
+            CodeElement::Function(function) => {
+                let open = self.find(TokenType::LParen);
+                let close = self.find_from(open + 1, |token| *token == TokenType::RParen);
+                let brace = self.find_from(close + 1, |token| *token == TokenType::LBrace);
+                let mut header = String::new();
+                for decorator in function.decorators.iter() {
+                    header.push_str(&format!("{}@{}\n", pad, decorator.token.lexeme));
+                }
+                header.push_str(&signature(function, &pad)?);
+                self.line(header, indent, brace);
+                self.block(&function.body, indent + 1)?;
+                self.close(format!("{}}}", pad), indent, 0);
+            }
+            CodeElement::NameSpace(name, members, _) => {
+                let brace = self.find(TokenType::LBrace);
+                self.line(
+                    format!("{}namespace {} {{", pad, name.token.lexeme),
+                    indent,
+                    brace,
+                );
+                self.declarations(members, indent + 1)?;
+                self.close(format!("{}}}", pad), indent, 0);
+            }
+            CodeElement::Struct(name, members, _) => {
+                let brace = self.find(TokenType::LBrace);
+                self.line(
+                    format!("{}struct {} {{", pad, name.token.lexeme),
+                    indent,
+                    brace,
+                );
+                for member in members.iter() {
+                    self.comments_before(self.position(), indent + 1);
+                    let end = self.find_from(self.cursor, |token| {
+                        matches!(token, TokenType::Comma | TokenType::RBrace)
+                    });
+                    let last = match self.tokens[end].token_type {
+                        TokenType::Comma => end,
+                        _ => end - 1,
+                    };
+                    self.line(
+                        format!(
+                            "{}{}{},",
+                            pad,
+                            INDENT,
+                            typed_identifiers(std::slice::from_ref(member))?
+                        ),
+                        indent + 1,
+                        last,
+                    );
+                }
+                self.close(format!("{}}}", pad), indent, 0);
+            }
+            _ => {
+                let last = match element {
+                    CodeElement::Hint(_) => self.cursor,
+                    CodeElement::Directive(_, args) => self.cursor + args.len(),
+                    _ => self.find(TokenType::Semicolon),
+                };
+                self.line(statement(element, &pad)?, indent, last);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn statement(element: &CodeElement, pad: &str) -> Result<String, String> {
+    Ok(match element {
+        CodeElement::Instruction(instr) => format!("{}{};", pad, instruction(instr)?),
+        CodeElement::Reference(ident, expr) => wrapped(
+            pad,
+            &format!("let {} = ", typed_identifiers(std::slice::from_ref(ident))?),
+            expr,
+            ";",
+        )?,
+        CodeElement::LocalVar(ident, None) => format!(
+            "{}local {};",
+            pad,
+            typed_identifiers(std::slice::from_ref(ident))?
+        ),
+        CodeElement::LocalVar(ident, Some(expr)) => wrapped(
+            pad,
+            &format!(
+                "local {} = ",
+                typed_identifiers(std::slice::from_ref(ident))?
+            ),
+            expr,
+            ";",
+        )?,
+        CodeElement::CompoundAssertEqual(left, right) => {
+            wrapped(pad, &format!("assert {} = ", expression(left)?), right, ";")?
+        }
+        CodeElement::Const(name, value, _) => {
+            wrapped(pad, &format!("const {} = ", name.token.lexeme), value, ";")?
+        }
+        CodeElement::Return(expr) => wrapped(pad, "return ", expr, ";")?,
+        CodeElement::FuncCall(expr) => wrapped(pad, "", expr, ";")?,
+        CodeElement::Hint(token) => format!("{}{}", pad, token.lexeme),
+        CodeElement::Directive(token, args) => {
+            let args: Vec<&str> = args.iter().map(|arg| arg.token.lexeme.as_str()).collect();
+            format!("{}{} {}", pad, token.lexeme, args.join(" "))
+        }
+        CodeElement::AllocLocals(_) => format!("{}alloc_locals;", pad),
+        _ => return Err(format!("Cannot format {:?}", element)),
+    })
+}
+
+// `head expr tail` on one line, or with the arguments of `expr` one per line when that
+// line is too long.
+fn wrapped(pad: &str, head: &str, expr: &Expr, tail: &str) -> Result<String, String> {
+    let line = format!("{}{}{}{}", pad, head, expression(expr)?, tail);
+    let callee = match expr.expr_type {
+        ExprType::FunctionCall if !expr.paren_args.is_empty() => {
+            let name = expr
+                .ident
+                .as_ref()
+                .map(|ident| ident.token.lexeme.clone())
+                .ok_or("Missing function name".to_string())?;
+            if expr.brace_args.is_empty() {
+                name
+            } else {
+                format!("{}{{{}}}", name, arguments(&expr.brace_args)?)
+            }
+        }
+        ExprType::TupleOrParen if expr.paren_args.len() > 1 => String::new(),
+        _ => return Ok(line),
+    };
+    if line.chars().count() <= MAX_WIDTH {
+        return Ok(line);
+    }
+    let mut text = format!("{}{}{}(\n", pad, head, callee);
+    for arg in expr.paren_args.iter() {
+        text.push_str(&format!(
+            "{}{}{},\n",
+            pad,
+            INDENT,
+            arguments(std::slice::from_ref(arg))?
+        ));
+    }
+    text.push_str(&format!("{}){}", pad, tail));
+    Ok(text)
+}
+
+// The line opening a function, with one argument per line when it is too long.
+pub fn signature(function: &Function, pad: &str) -> Result<String, String> {
+    let mut head = format!("func {}", function.name.token.lexeme);
+    if !function.implicit_args.is_empty() {
+        head.push_str(&format!(
+            "{{{}}}",
+            typed_identifiers(&function.implicit_args)?
+        ));
+    }
+    let return_type = match &function.return_type {
+        Some(return_type) => format!(" -> {}", type_(return_type)?),
+        None => String::new(),
+    };
+    let line = format!(
+        "{}{}({}){} {{",
+        pad,
+        head,
+        typed_identifiers(&function.args)?,
+        return_type
+    );
+    if line.chars().count() <= MAX_WIDTH || function.args.is_empty() {
+        return Ok(line);
+    }
+    let mut text = format!("{}{}(\n", pad, head);
+    for arg in function.args.iter() {
+        text.push_str(&format!(
+            "{}{}{},\n",
+            pad,
+            INDENT,
+            typed_identifiers(std::slice::from_ref(arg))?
+        ));
+    }
+    text.push_str(&format!("{}){} {{", pad, return_type));
+    Ok(text)
+}
+
+fn instruction(instruction: &Instruction) -> Result<String, String> {
+    let arg = |i: usize| -> Result<String, String> {
+        instruction
+            .args
+            .get(i)
+            .ok_or("Missing instruction argument".to_string())
+            .and_then(expression)
+    };
+    let label = || -> Result<String, String> {
+        instruction
+            .ident
+            .as_ref()
+            .map(|ident| ident.token.lexeme.clone())
+            .ok_or("Missing label".to_string())
+    };
+    let text = match instruction.instruction_type {
+        InstructionType::CallRel => format!("call rel {}", arg(0)?),
+        InstructionType::CallAbs => format!("call abs {}", arg(0)?),
+        InstructionType::Call => format!("call {}", label()?),
+        InstructionType::AssertEq => format!("{} = {}", arg(0)?, arg(1)?),
+        InstructionType::JmpRel => format!("jmp rel {}", arg(0)?),
+        InstructionType::JmpAbs => format!("jmp abs {}", arg(0)?),
+        InstructionType::Jmp => format!("jmp {}", label()?),
+        InstructionType::Jnz => format!("jmp rel {} if {}", arg(0)?, arg(1)?),
+        InstructionType::JnzLabel => format!("jmp {} if {}", label()?, arg(0)?),
+        InstructionType::Ret => "ret".to_string(),
+        InstructionType::AddAp => format!("ap += {}", arg(0)?),
+        InstructionType::DataWord => format!("dw {}", arg(0)?),
+    };
+    Ok(if instruction.increment_ap {
+        format!("{}, ap++", text)
+    } else {
+        text
+    })
+}
+
+fn typed_identifiers(identifiers: &[TypedIdentifier]) -> Result<String, String> {
+    let identifiers = identifiers
+        .iter()
+        .map(|identifier| match &identifier.type_ {
+            Some(type_arg) => Ok(format!(
+                "{}: {}",
+                identifier.ident.token.lexeme,
+                type_(type_arg)?
+            )),
+            None => Ok(identifier.ident.token.lexeme.clone()),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(identifiers.join(", "))
+}
+
+pub fn type_(type_arg: &Type) -> Result<String, String> {
+    Ok(match type_arg {
+        Type::Felt => "felt".to_string(),
+        Type::CodeOffset => "codeoffset".to_string(),
+        Type::Pointer(inner) => format!("{}*", type_(inner)?),
+        Type::Pointer2(inner) => format!("{}**", type_(inner)?),
+        Type::Tuple(types) => {
+            let types = types.iter().map(type_).collect::<Result<Vec<_>, _>>()?;
+            format!("({})", types.join(", "))
+        }
+        Type::Struct(ident) => ident.token.lexeme.clone(),
+        Type::Named(ident, inner) => format!("{}: {}", ident.token.lexeme, type_(inner)?),
+        Type::Error => return Err("Cannot format an invalid type".to_string()),
+    })
+}
+
+pub fn expression(expr: &Expr) -> Result<String, String> {
+    operand(expr, 1)
+}
+
+fn arguments(args: &[ExprAssignment]) -> Result<String, String> {
+    let args = args
+        .iter()
+        .map(|arg| match arg {
+            ExprAssignment::Expr(expr) => expression(expr),
+            ExprAssignment::Assign(ident, expr) => {
+                Ok(format!("{}={}", ident.token.lexeme, expression(expr)?))
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(args.join(", "))
+}
+
+// Prints `expr` where the parser expects an expression of at least level `min`.
+fn operand(expr: &Expr, min: u8) -> Result<String, String> {
+    if level(expr) < min {
+        Ok(format!("({})", operand(expr, 1)?))
+    } else {
+        term(expr)
+    }
+}
+
+fn term(expr: &Expr) -> Result<String, String> {
+    fn child(child: &Option<Box<Expr>>) -> Result<&Expr, String> {
+        child.as_deref().ok_or("Missing operand".to_string())
+    }
+    let lexeme = |token: &Option<Token>| {
+        token
+            .as_ref()
+            .map(|token| token.lexeme.clone())
+            .ok_or("Missing token".to_string())
+    };
+    let binary = |operator: &str, left_min: u8, right_min: u8| -> Result<String, String> {
+        Ok(format!(
+            "{} {} {}",
+            operand(child(&expr.left)?, left_min)?,
+            operator,
+            operand(child(&expr.right)?, right_min)?
+        ))
+    };
+    match expr.expr_type {
+        ExprType::IntegerLiteral | ExprType::Register => lexeme(&expr.token),
+        ExprType::Hint => Ok(format!("nondet {}", lexeme(&expr.token)?)),
+        ExprType::Identifier => expr
+            .ident
+            .as_ref()
+            .map(|ident| ident.token.lexeme.clone())
+            .ok_or("Missing identifier".to_string()),
+        ExprType::Add => binary("+", 1, 2),
+        ExprType::Sub => binary("-", 1, 2),
+        ExprType::Mul => binary("*", 2, 3),
+        ExprType::Div => binary("/", 2, 3),
+        // right associative, the exponent is a unary expression
+        ExprType::Pow => binary("**", 5, 3),
+        ExprType::And => binary("and", 5, 6),
+        ExprType::Eq => binary("==", 7, 7),
+        ExprType::Neq => binary("!=", 7, 7),
+        ExprType::Neg => Ok(format!("-{}", operand(child(&expr.left)?, 3)?)),
+        ExprType::AddressOf => Ok(format!("&{}", operand(child(&expr.left)?, 3)?)),
+        ExprType::New => Ok(format!("new {}", operand(child(&expr.left)?, 3)?)),
+        ExprType::Deref => Ok(format!("[{}]", expression(child(&expr.left)?)?)),
+        ExprType::Cast => Ok(format!(
+            "cast({}, {})",
+            expression(child(&expr.left)?)?,
+            type_(expr.type_arg.as_ref().ok_or("Missing type".to_string())?)?
+        )),
+        ExprType::FunctionCall => {
+            let name = expr
+                .ident
+                .as_ref()
+                .map(|ident| ident.token.lexeme.clone())
+                .ok_or("Missing function name".to_string())?;
+            if expr.brace_args.is_empty() {
+                Ok(format!("{}({})", name, arguments(&expr.paren_args)?))
+            } else {
+                Ok(format!(
+                    "{}{{{}}}({})",
+                    name,
+                    arguments(&expr.brace_args)?,
+                    arguments(&expr.paren_args)?
+                ))
+            }
+        }
+        ExprType::TupleOrParen => Ok(format!("({})", arguments(&expr.paren_args)?)),
+        ExprType::Subscript | ExprType::ErrorExpr => {
+            Err(format!("Cannot format {:?} expressions", expr.expr_type))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cst;
+
+    fn formatted(source: &str) -> String {
+        let (tokens, _) = cst::tokenize(source);
+        let (tree, code_elements, diagnostics) = cst::parse(tokens, source);
+        assert!(diagnostics.is_empty());
+        format(&code_elements, &tree, source).unwrap()
+    }
+
+    // Comments around and inside declarations, hints, and a signature and a call too long
+    // for one line.
+    const EXAMPLE: &str = r#"%builtins output range_check
+
+// A point.
+struct Point {
+    x: felt,  // abscissa
+    y: felt
+}
+
+const LIMIT = 2 ** 10;   // trailing comment
+namespace geometry {
+    // Sum of the coordinates, with a signature long enough to be wrapped over several lines.
+    func sum_of_all_the_coordinates(first_point: Point, second_point: Point, third_point: Point) -> felt {
+        // leading comment in a body
+        return first_point.x + first_point.y + second_point.x + (second_point.y + third_point.x) * third_point.y;
+    }
+    const SCALE = LIMIT * 1000000 + LIMIT * 2000000 + (LIMIT - 3000000) * LIMIT * 4000000 - 2 ** 3 ** 2;
+}
+
+func main{output_ptr: felt*, range_check_ptr}() {
+    alloc_locals;
+    local p: Point = Point(x=1, y=2);
+    %{ memory[ap] = 5 %}
+    let z = geometry.sum_of_all_the_coordinates(first_point=p, second_point=p, third_point=Point(x=3, y=4));
+    if (z == 9) {
+        assert [output_ptr] = z;   // written
+    } else {
+        assert [output_ptr] = -z * 2;
+    }
+    let output_ptr = output_ptr + 1;
+
+    // end of main
+    return ();
+}
+"#;
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for source in [EXAMPLE, include_str!("../test.cairo")] {
+            let once = formatted(source);
+            assert_eq!(formatted(&once), once);
+        }
+        let once = formatted(EXAMPLE);
+        assert!(
+            once.contains("(\n        first_point: Point,\n"),
+            "{}",
+            once
+        );
+        assert!(once.contains("(\n        first_point=p,\n"), "{}", once);
+        assert!(once.contains("    x: felt,  // abscissa\n"), "{}", once);
+    }
+}
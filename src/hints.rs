@@ -0,0 +1,513 @@
+use crate::casm::Hint;
+use crate::felt::Felt;
+use crate::minivm::{MiniVm, Relocatable, Value};
+use std::collections::HashMap;
+
+// Executes the hints attached to the current pc before the instruction runs.
+pub trait HintProcessor {
+    fn execute_hint(&mut self, vm: &mut MiniVm, hint: &Hint) -> Result<(), String>;
+}
+
+// What a hint implementation can see: the vm, the `ids` of its scope and the
+// `program_input` given on the command line.
+pub struct HintContext<'a> {
+    pub vm: &'a mut MiniVm,
+    pub hint: &'a Hint,
+    pub program_input: &'a json::JsonValue,
+}
+
+impl HintContext<'_> {
+    pub fn ids_addr(&self, name: &str) -> Result<Relocatable, String> {
+        let (_, offset) = self
+            .hint
+            .references
+            .iter()
+            .find(|(ident, _)| ident == name)
+            .ok_or(format!("Unknown identifier ids.{} in hint", name))?;
+        self.vm.fp.add(*offset as i64)
+    }
+
+    pub fn get_ids(&self, name: &str) -> Result<Value, String> {
+        let addr = self.ids_addr(name)?;
+        self.vm
+            .memory
+            .get(addr)
+            .ok_or(format!("ids.{} is not initialized", name))
+    }
+
+    pub fn get_ids_int(&self, name: &str) -> Result<Felt, String> {
+        match self.get_ids(name)? {
+            Value::Int(n) => Ok(n),
+            Value::Ptr(ptr) => Err(format!("ids.{} = {} is not an integer", name, ptr)),
+        }
+    }
+
+    pub fn set_ids(&mut self, name: &str, value: Value) -> Result<(), String> {
+        let addr = self.ids_addr(name)?;
+        self.vm
+            .memory
+            .insert(addr, value)
+            .map_err(|error| error.to_string())
+    }
+
+    // memory[ap] = value
+    pub fn set_ap(&mut self, value: Value) -> Result<(), String> {
+        self.vm
+            .memory
+            .insert(self.vm.ap, value)
+            .map_err(|error| error.to_string())
+    }
+
+    pub fn add_segment(&mut self) -> Relocatable {
+        self.vm.memory.add_segment()
+    }
+}
+
+pub type HintFunc = Box<dyn Fn(&mut HintContext) -> Result<(), String>>;
+
+// Maps hint source code to native implementations.
+pub struct HintRegistry {
+    hints: HashMap<String, HintFunc>,
+    // null unless an input file is given
+    pub program_input: json::JsonValue,
+}
+
+impl Default for HintRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HintRegistry {
+    pub fn new() -> Self {
+        Self {
+            hints: HashMap::new(),
+            program_input: json::JsonValue::Null,
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        code: &str,
+        func: impl Fn(&mut HintContext) -> Result<(), String> + 'static,
+    ) {
+        self.hints.insert(code.trim().to_string(), Box::new(func));
+    }
+
+    // Registry preloaded with the hints of the cairo standard library.
+    pub fn with_builtin_hints() -> Self {
+        let mut registry = Self::new();
+
+        registry.register("memory[ap] = segments.add()", |ctx| {
+            let segment = ctx.add_segment();
+            ctx.set_ap(Value::Ptr(segment))
+        });
+
+        registry.register("memory[ap] = to_felt_or_relocatable(ids.x % 2)", |ctx| {
+            let x = ctx.get_ids_int("x")?;
+            ctx.set_ap(Value::Int(Felt::from(x.bit(0) as u64)))
+        });
+
+        registry.register(
+            "from starkware.cairo.common.math_utils import assert_integer
+assert_integer(ids.div)
+assert 0 < ids.div <= PRIME // range_check_builtin.bound, \\
+    f'div={hex(ids.div)} is out of the valid range.'
+ids.q, ids.r = divmod(ids.value, ids.div)",
+            |ctx| {
+                let field = ctx.vm.field;
+                let value = ctx.get_ids_int("value")?;
+                let div = ctx.get_ids_int("div")?;
+                let (max_div, _) = field.prime.div_rem(&field.range_check_bound());
+                if div.is_zero() || div > max_div {
+                    return Err(format!("div={:#x} is out of the valid range.", div));
+                }
+                let (q, r) = value.div_rem(&div);
+                ctx.set_ids("q", Value::Int(q))?;
+                ctx.set_ids("r", Value::Int(r))
+            },
+        );
+
+        registry.register(
+            "memory[ap] = 0 if 0 <= (ids.a % PRIME) < range_check_builtin.bound else 1",
+            |ctx| {
+                let field = ctx.vm.field;
+                let a = field.reduce(&ctx.get_ids_int("a")?);
+                ctx.set_ap(bool_value(a >= field.range_check_bound()))
+            },
+        );
+
+        registry.register(
+            "memory[ap] = 0 if 0 <= ((-ids.a - 1) % PRIME) < range_check_builtin.bound else 1",
+            |ctx| {
+                let field = ctx.vm.field;
+                let a = ctx.get_ids_int("a")?;
+                let value = field.sub(&field.neg(&a), &Felt::ONE);
+                ctx.set_ap(bool_value(value >= field.range_check_bound()))
+            },
+        );
+
+        registry.register(
+            "memory[ap] = 0 if (ids.a % PRIME) <= (ids.b % PRIME) else 1",
+            |ctx| {
+                let field = ctx.vm.field;
+                let a = field.reduce(&ctx.get_ids_int("a")?);
+                let b = field.reduce(&ctx.get_ids_int("b")?);
+                ctx.set_ap(bool_value(a > b))
+            },
+        );
+
+        registry.register(
+            "from starkware.cairo.common.math_utils import assert_integer
+assert_integer(ids.a)
+assert 0 <= ids.a % PRIME < range_check_builtin.bound, f'a = {ids.a} is out of range.'",
+            |ctx| {
+                let field = ctx.vm.field;
+                let a = ctx.get_ids_int("a")?;
+                if field.reduce(&a) >= field.range_check_bound() {
+                    return Err(format!("a = {} is out of range.", a));
+                }
+                Ok(())
+            },
+        );
+
+        registry.register(
+            "from starkware.cairo.common.math_utils import assert_integer
+assert_integer(ids.value)
+assert ids.value % PRIME != 0, f'assert_not_zero failed: {ids.value} = 0.'",
+            |ctx| {
+                let value = ctx.get_ids_int("value")?;
+                if ctx.vm.field.reduce(&value).is_zero() {
+                    return Err(format!("assert_not_zero failed: {} = 0.", value));
+                }
+                Ok(())
+            },
+        );
+
+        registry.register(
+            "from starkware.cairo.lang.vm.relocatable import RelocatableValue
+both_ints = isinstance(ids.a, int) and isinstance(ids.b, int)
+both_relocatable = (
+    isinstance(ids.a, RelocatableValue) and isinstance(ids.b, RelocatableValue) and
+    ids.a.segment_index == ids.b.segment_index)
+assert both_ints or both_relocatable, \\
+    f'assert_not_equal failed: non-comparable values: {ids.a}, {ids.b}.'
+assert (ids.a - ids.b) % PRIME != 0, f'assert_not_equal failed: {ids.a} = {ids.b}.'",
+            |ctx| {
+                let a = ctx.get_ids("a")?;
+                let b = ctx.get_ids("b")?;
+                let diff = a.sub(&b, &ctx.vm.field).map_err(|_| {
+                    format!(
+                        "assert_not_equal failed: non-comparable values: {}, {}.",
+                        a, b
+                    )
+                })?;
+                if diff.is_zero(&ctx.vm.field) {
+                    return Err(format!("assert_not_equal failed: {} = {}.", a, b));
+                }
+                Ok(())
+            },
+        );
+
+        registry
+    }
+}
+
+impl HintProcessor for HintRegistry {
+    fn execute_hint(&mut self, vm: &mut MiniVm, hint: &Hint) -> Result<(), String> {
+        let code = hint.code.trim();
+        let mut ctx = HintContext {
+            vm,
+            hint,
+            program_input: &self.program_input,
+        };
+        if let Some(func) = self.hints.get(code) {
+            return func(&mut ctx);
+        }
+
+        // generic forms, as produced by `nondet %{ ... %}` and by allocating hints
+        if let Some(expr) = code
+            .strip_prefix("memory[ap] = to_felt_or_relocatable(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            let value = value_of(&ctx, expr)?;
+            return ctx.set_ap(value);
+        }
+        if let Some((name, expr)) = code
+            .strip_prefix("ids.")
+            .and_then(|rest| rest.split_once(" = "))
+            .filter(|(name, _)| is_name(name))
+        {
+            let value = match expr.trim() {
+                "segments.add()" => Value::Ptr(ctx.add_segment()),
+                expr => match expr
+                    .strip_prefix("segments.gen_arg(")
+                    .and_then(|rest| rest.strip_suffix(')'))
+                {
+                    Some(arg) => {
+                        let input = ctx.program_input;
+                        let tokens = tokenize(arg)?;
+                        let mut pos = 0;
+                        let arg = eval_input(&ctx, input, &tokens, &mut pos)?;
+                        if pos != tokens.len() {
+                            return Err(format!("Unsupported hint expression: {}", expr));
+                        }
+                        gen_arg(&mut ctx, arg)?
+                    }
+                    None => value_of(&ctx, expr)?,
+                },
+            };
+            return ctx.set_ids(name, value);
+        }
+
+        Err(format!("Unknown hint: {}", code))
+    }
+}
+
+// Python booleans as written to memory by the hints above.
+fn bool_value(b: bool) -> Value {
+    Value::Int(Felt::from(b as u64))
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Value of a hint expression: ids.<name> is read as is, so that it may be a pointer,
+// program_input entries are read as felts of any size, the rest goes through eval.
+fn value_of(ctx: &HintContext, expr: &str) -> Result<Value, String> {
+    let expr = expr.trim();
+    if let Some(name) = expr.strip_prefix("ids.")
+        && is_name(name)
+    {
+        return ctx.get_ids(name);
+    }
+    let tokens = tokenize(expr)?;
+    if tokens.first().map(|t| t.as_str()) == Some("program_input") {
+        let mut pos = 0;
+        let value = eval_input(ctx, ctx.program_input, &tokens, &mut pos)?;
+        if pos == tokens.len() {
+            return json_felt(ctx, value).map(Value::Int);
+        }
+    }
+    Ok(Value::Int(ctx.vm.field.reduce_i128(eval(ctx, expr)?)))
+}
+
+// Follows the subscripts of `program_input['key'][index]...`, starting at `input`.
+fn eval_input<'b>(
+    ctx: &HintContext,
+    input: &'b json::JsonValue,
+    tokens: &[String],
+    pos: &mut usize,
+) -> Result<&'b json::JsonValue, String> {
+    if tokens.get(*pos).map(|t| t.as_str()) != Some("program_input") {
+        return Err("Expected program_input in hint expression".to_string());
+    }
+    *pos += 1;
+    let mut value = input;
+    let mut path = "program_input".to_string();
+    while tokens.get(*pos).map(|t| t.as_str()) == Some("[") {
+        *pos += 1;
+        let token = tokens
+            .get(*pos)
+            .ok_or("Unexpected end of hint expression".to_string())?;
+        if let Some(key) = token
+            .strip_prefix(['\'', '"'])
+            .and_then(|rest| rest.strip_suffix(['\'', '"']))
+        {
+            *pos += 1;
+            path = format!("{}['{}']", path, key);
+            value = &value[key];
+        } else {
+            let index = eval_sum(ctx, tokens, pos)?;
+            path = format!("{}[{}]", path, index);
+            value = usize::try_from(index)
+                .ok()
+                .filter(|index| *index < value.len())
+                .map_or(&json::JsonValue::Null, |index| &value[index]);
+        }
+        if tokens.get(*pos).map(|t| t.as_str()) != Some("]") {
+            return Err("Expected ']' in hint expression".to_string());
+        }
+        *pos += 1;
+        if value.is_null() {
+            return Err(format!("{} is not defined", path));
+        }
+    }
+    Ok(value)
+}
+
+// Integers of the input are json numbers, or strings for those too large for a number.
+fn json_felt(ctx: &HintContext, value: &json::JsonValue) -> Result<Felt, String> {
+    let field = ctx.vm.field;
+    if let Some(n) = value.as_u64() {
+        return Ok(field.reduce(&Felt::from(n)));
+    }
+    if let Some(n) = value.as_i64() {
+        return Ok(field.reduce_i128(n as i128));
+    }
+    let text = value
+        .as_str()
+        .ok_or(format!("{} is not an integer", value))?;
+    match text.strip_prefix('-') {
+        Some(n) => n.parse::<Felt>().map(|n| field.neg(&field.reduce(&n))),
+        None => text.parse::<Felt>().map(|n| field.reduce(&n)),
+    }
+}
+
+// Writes a json array to a new segment, nested arrays to segments of their own, and
+// returns its pointer. Anything else is a felt.
+fn gen_arg(ctx: &mut HintContext, value: &json::JsonValue) -> Result<Value, String> {
+    if !value.is_array() {
+        return json_felt(ctx, value).map(Value::Int);
+    }
+    let segment = ctx.add_segment();
+    for (i, item) in value.members().enumerate() {
+        let item = gen_arg(ctx, item)?;
+        ctx.vm
+            .memory
+            .insert(segment.add(i as i64)?, item)
+            .map_err(|error| error.to_string())?;
+    }
+    Ok(Value::Ptr(segment))
+}
+
+// Evaluates the python integer expressions commonly found in nondet hints:
+// literals, ids.<name>, program_input[...], PRIME, parentheses, unary minus and
+// + - * // % **.
+// Values are 128 bit integers, which is enough for M31 but not for PRIME itself in
+// larger fields.
+fn eval(ctx: &HintContext, expr: &str) -> Result<i128, String> {
+    let tokens = tokenize(expr)?;
+    let mut pos = 0;
+    let value = eval_sum(ctx, &tokens, &mut pos)?;
+    if pos != tokens.len() {
+        return Err(format!("Unsupported hint expression: {}", expr));
+    }
+    Ok(value)
+}
+
+fn tokenize(expr: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else if (c == '/' || c == '*') && chars.get(i + 1) == Some(&c) {
+            tokens.push(format!("{}{}", c, c));
+            i += 2;
+        } else if c == '\'' || c == '"' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err("Unterminated string in hint expression".to_string());
+            }
+            i += 1;
+            tokens.push(chars[start..i].iter().collect());
+        } else if "+-*%()[]".contains(c) {
+            tokens.push(c.to_string());
+            i += 1;
+        } else {
+            return Err(format!("Unsupported character '{}' in hint expression", c));
+        }
+    }
+    Ok(tokens)
+}
+
+fn eval_sum(ctx: &HintContext, tokens: &[String], pos: &mut usize) -> Result<i128, String> {
+    let mut value = eval_product(ctx, tokens, pos)?;
+    while let Some(op) = tokens.get(*pos).filter(|op| *op == "+" || *op == "-") {
+        *pos += 1;
+        let right = eval_product(ctx, tokens, pos)?;
+        value = if op == "+" {
+            value + right
+        } else {
+            value - right
+        };
+    }
+    Ok(value)
+}
+
+fn eval_product(ctx: &HintContext, tokens: &[String], pos: &mut usize) -> Result<i128, String> {
+    let mut value = eval_unary(ctx, tokens, pos)?;
+    while let Some(op) = tokens
+        .get(*pos)
+        .filter(|op| *op == "*" || *op == "//" || *op == "%")
+    {
+        *pos += 1;
+        let right = eval_unary(ctx, tokens, pos)?;
+        if op != "*" && right == 0 {
+            return Err("Division by zero in hint".to_string());
+        }
+        value = match op.as_str() {
+            "*" => value * right,
+            "//" => value.div_euclid(right),
+            _ => value.rem_euclid(right),
+        };
+    }
+    Ok(value)
+}
+
+fn eval_unary(ctx: &HintContext, tokens: &[String], pos: &mut usize) -> Result<i128, String> {
+    if tokens.get(*pos).map(|t| t.as_str()) == Some("-") {
+        *pos += 1;
+        return Ok(-eval_unary(ctx, tokens, pos)?);
+    }
+    let base = eval_atom(ctx, tokens, pos)?;
+    if tokens.get(*pos).map(|t| t.as_str()) == Some("**") {
+        *pos += 1;
+        let exp = eval_unary(ctx, tokens, pos)?;
+        let exp = u32::try_from(exp).map_err(|_| "Invalid exponent in hint".to_string())?;
+        return base
+            .checked_pow(exp)
+            .ok_or("Overflow in hint expression".to_string());
+    }
+    Ok(base)
+}
+
+fn eval_atom(ctx: &HintContext, tokens: &[String], pos: &mut usize) -> Result<i128, String> {
+    let token = tokens
+        .get(*pos)
+        .ok_or("Unexpected end of hint expression".to_string())?;
+    *pos += 1;
+    if token == "(" {
+        let value = eval_sum(ctx, tokens, pos)?;
+        if tokens.get(*pos).map(|t| t.as_str()) != Some(")") {
+            return Err("Expected ')' in hint expression".to_string());
+        }
+        *pos += 1;
+        Ok(value)
+    } else if token == "PRIME" {
+        to_i128(ctx.vm.field.prime)
+    } else if let Some(name) = token.strip_prefix("ids.") {
+        to_i128(ctx.get_ids_int(name)?)
+    } else if token == "program_input" {
+        *pos -= 1;
+        let value = eval_input(ctx, ctx.program_input, tokens, pos)?;
+        to_i128(json_felt(ctx, value)?)
+    } else if let Some(hex) = token.strip_prefix("0x") {
+        i128::from_str_radix(hex, 16).map_err(|e| e.to_string())
+    } else {
+        token
+            .parse::<i128>()
+            .map_err(|_| format!("Unsupported token '{}' in hint expression", token))
+    }
+}
+
+fn to_i128(n: Felt) -> Result<i128, String> {
+    n.to_i128()
+        .ok_or(format!("{} is too large for a hint expression", n))
+}
@@ -1,4 +1,4 @@
-use ariadne::{Color, Label, Report, ReportKind, Source};
+use crate::diagnostic::{Diagnostic, codes};
 use logos::Logos;
 
 #[derive(Debug, Clone)]
@@ -8,16 +8,17 @@ pub struct Token {
     pub span: (usize, usize),
 }
 
-
 #[derive(Logos, Debug, Clone, PartialEq, Eq)]
-#[logos(skip r"[\t\n ]+")]
-#[logos(skip r"//.*\n")]
+#[logos(skip r"[\t\r\n ]+")]
 pub enum TokenType {
+    // kept for the formatter, the parser never sees them
+    #[regex(r"//[^\n]*")]
+    Comment,
 
     #[regex("[0-9]+")]
     Int,
 
-    #[regex(r"%\{(.*)%\}")]
+    #[regex(r"%\{([^%]|%[^}])*%\}")]
     Hint,
 
     #[regex(r"0x[0-9a-fA-F]+")]
@@ -59,7 +60,7 @@ pub enum TokenType {
     LParen,
     #[token(")")]
     RParen,
-    
+
     #[token("=")]
     Equal,
 
@@ -96,7 +97,6 @@ pub enum TokenType {
 
     #[token("and")]
     And,
-    
 
     #[token("local")]
     Local,
@@ -105,7 +105,6 @@ pub enum TokenType {
     Ret,
 
     //Instructions
-
     #[token("call")]
     Call,
     #[token("rel")]
@@ -147,11 +146,16 @@ pub enum TokenType {
     #[token("else")]
     Else,
 
+    // Directives
+    #[token("%builtins")]
+    Builtins,
+    #[token("%lang")]
+    Lang,
+
     // Cairo file
     // #[token("\n")]
     // Newline,
 
-
     // Code elements
     #[token(";")]
     Semicolon,
@@ -172,41 +176,35 @@ pub enum TokenType {
     #[token("alloc_locals")]
     AllocLocals,
 
-    EOF,
+    Eof,
     Error,
-
 }
 
-
-pub fn lex(input: &str, file_name: &str) -> (Vec<Token>, u32) {
-    let mut error_counter = 0;
+// Every token, comments included: the source is the tokens and the whitespace between
+// their spans.
+pub fn lex_with_comments(input: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
     let mut lex = TokenType::lexer(input);
     let mut tokens = Vec::new();
     while let Some(token) = lex.next() {
         let lexeme = lex.slice().to_string();
         if let Ok(token) = token {
-            let lexeme = lex.slice().to_string();
             tokens.push(Token {
                 token_type: token,
                 lexeme,
                 span: (lex.span().start, lex.span().end),
             });
         } else {
-            let error_span = (file_name, lex.span().start..lex.span().end);
-            let _ = Report::build(ReportKind::Error, error_span.clone())
-                .with_message("Lexer error")
-                .with_label(Label::new(error_span)
-                    .with_message(format!("Unknown token '{}'", lexeme))
-                    .with_color(Color::Red))
-                .finish()
-                .print((file_name, Source::from(input)));
-            error_counter += 1;
+            diagnostics.push(
+                Diagnostic::error(codes::UNKNOWN_TOKEN, format!("Unknown token '{}'", lexeme))
+                    .with_label((lex.span().start, lex.span().end), "unknown token".to_string()),
+            );
         }
     }
     tokens.push(Token {
-        token_type: TokenType::EOF,
+        token_type: TokenType::Eof,
         lexeme: "".to_string(),
-        span: (0, 0),
+        span: (input.len(), input.len()),
     });
-    (tokens, error_counter)
-}
\ No newline at end of file
+    (tokens, diagnostics)
+}
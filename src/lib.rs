@@ -0,0 +1,218 @@
+// Compiler, assembler and vm for Cairo Zero programs.
+//
+//     let compiled = czc::compile_source(source, czc::Options::default())?;
+//     let mut vm = czc::Vm::new();
+//     vm.run(&compiled.program()?)?;
+//     println!("{}", vm.state.summary(1));
+
+pub(crate) mod assembler;
+pub(crate) mod ast;
+pub(crate) mod builtins;
+pub(crate) mod casm;
+pub(crate) mod casm_parser;
+// the command line of the czc binary
+pub mod cli;
+pub(crate) mod cst;
+pub(crate) mod debugger;
+pub(crate) mod diagnostic;
+pub(crate) mod disasm;
+pub(crate) mod doc;
+pub(crate) mod felt;
+pub(crate) mod flatten;
+pub(crate) mod formatter;
+pub(crate) mod hints;
+pub(crate) mod lexer;
+pub(crate) mod lints;
+pub(crate) mod lower_to_casm;
+pub(crate) mod lsp;
+pub(crate) mod minivm;
+pub(crate) mod parser;
+pub(crate) mod poseidon;
+pub(crate) mod profiler;
+pub(crate) mod resolve;
+pub(crate) mod source_map;
+pub(crate) mod stdlib;
+pub(crate) mod testing;
+pub(crate) mod trace;
+pub(crate) mod typecheck;
+
+pub use assembler::Assembler;
+pub use casm::CasmInstruction;
+pub use diagnostic::{Diagnostic, Diagnostics, MessageFormat, Severity};
+pub use felt::{Felt, Field};
+pub use minivm::{MiniVm, Program, VmError};
+
+use ast::CodeElement;
+use hints::HintRegistry;
+use lexer::Token;
+use lower_to_casm::Compiler;
+use minivm::{Relocatable, VmErrorKind};
+
+pub struct Options {
+    // target field: m31 (default), stark, or the prime itself
+    pub field: Field,
+    // name of the source in diagnostics and debug_info
+    pub file_name: String,
+    // fail on lint warnings, as if they were errors
+    pub deny_warnings: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            field: Field::M31,
+            file_name: "<input>".to_string(),
+            deny_warnings: false,
+        }
+    }
+}
+
+// Every stage of the compilation of a source file.
+pub struct CompiledProgram {
+    // the tokens of the source, without trivia
+    tokens: Vec<Token>,
+    // namespaces flattened and constants replaced by their values
+    ast: Vec<CodeElement>,
+    // casm as lowered, with labels
+    casm: Vec<CasmInstruction>,
+    // resolved casm, encoded instructions and json output
+    assembler: Assembler,
+    // function signatures and frame layouts
+    compiler: Compiler,
+    // lint warnings of a program that compiled
+    warnings: Vec<Diagnostic>,
+}
+
+impl CompiledProgram {
+    // The program to run, started from main.
+    pub fn program(&self) -> Result<Program, String> {
+        Program::from_assembler(&self.assembler, "main")
+    }
+
+    pub fn casm(&self) -> &[CasmInstruction] {
+        &self.casm
+    }
+
+    pub fn assembler(&self) -> &Assembler {
+        &self.assembler
+    }
+
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    // the stages whose types are internal to the crate
+
+    pub(crate) fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub(crate) fn ast(&self) -> &[CodeElement] {
+        &self.ast
+    }
+
+    pub(crate) fn compiler(&self) -> &Compiler {
+        &self.compiler
+    }
+}
+
+pub fn compile_source(source: &str, options: Options) -> Result<CompiledProgram, Diagnostics> {
+    // syntax errors after an unknown token are mostly caused by it
+    let (tokens, diagnostics) = cst::tokenize(source);
+    if !diagnostics.is_empty() {
+        return Err(Diagnostics(diagnostics));
+    }
+    // lowering assumes a well-formed tree
+    let (syntax, ast, diagnostics) = cst::parse(tokens, source);
+    if !diagnostics.is_empty() {
+        return Err(Diagnostics(diagnostics));
+    }
+    let tokens = syntax.code_tokens().cloned().collect();
+    let (ast, diagnostics) = flatten::flatten(&ast);
+    if !diagnostics.is_empty() {
+        return Err(Diagnostics(diagnostics));
+    }
+
+    let mut diagnostics = lints::check(&ast);
+    if options.deny_warnings {
+        for diagnostic in diagnostics.iter_mut() {
+            diagnostic.severity = Severity::Error;
+        }
+    }
+    // lowering expects every name to be defined
+    diagnostics.extend(resolve::check(&ast));
+    diagnostics.extend(typecheck::check(&ast));
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(Diagnostics(diagnostics));
+    }
+    let mut compiler = Compiler::new(ast.clone());
+    let casm = compiler.compile();
+    diagnostics.append(&mut compiler.diagnostics);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(Diagnostics(diagnostics));
+    }
+    let mut assembler = assemble(casm.clone(), compiler.builtins.clone(), &options)?;
+    assembler.source = Some((options.file_name.clone(), source.to_string()));
+    assembler.functions = compiler.frame_layouts.keys().cloned().collect();
+    Ok(CompiledProgram {
+        tokens,
+        ast,
+        casm,
+        assembler,
+        compiler,
+        warnings: diagnostics,
+    })
+}
+
+// Resolves the labels of `casm` and encodes its instructions.
+pub fn assemble(
+    casm: Vec<CasmInstruction>,
+    builtins: Vec<String>,
+    options: &Options,
+) -> Result<Assembler, Diagnostics> {
+    let mut assembler = Assembler::new();
+    assembler.field = options.field;
+    assembler.casm = casm;
+    assembler.builtins = builtins;
+    assembler.resolve_jumps()?;
+    assembler.build_instructions()?;
+    Ok(assembler)
+}
+
+// Runs programs from main to the end, with the hints czc knows about.
+pub struct Vm {
+    // registers, memory and trace of the last run
+    pub state: MiniVm,
+    pub hint_processor: HintRegistry,
+    // explicit arguments of main
+    pub args: Vec<Felt>,
+    pub max_steps: Option<u64>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self {
+            state: MiniVm::new(),
+            hint_processor: HintRegistry::with_builtin_hints(),
+            args: Vec::new(),
+            max_steps: None,
+        }
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<(), VmError> {
+        self.state
+            .load_program(program, &self.args)
+            .map_err(|error| VmError {
+                pc: Relocatable::new(0, program.main),
+                kind: VmErrorKind::Other(error),
+            })?;
+        self.state.max_steps = self.max_steps;
+        self.state.run(&mut self.hint_processor)
+    }
+}
@@ -0,0 +1,416 @@
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, codes};
+use crate::stdlib;
+use std::collections::HashMap;
+
+// Warnings about code that compiles but is most likely a mistake.
+// A function opts out of a lint with the `@allow_<lint>` decorator, or of all of them
+// with `@allow_warnings`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lint {
+    UnusedLocal,
+    UnusedArgument,
+    UnreachableCode,
+    AllocLocals,
+    Shadowing,
+}
+
+impl Lint {
+    fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedLocal => "unused_local",
+            Lint::UnusedArgument => "unused_argument",
+            Lint::UnreachableCode => "unreachable_code",
+            Lint::AllocLocals => "alloc_locals",
+            Lint::Shadowing => "shadowing",
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Lint::UnusedLocal => codes::UNUSED_LOCAL,
+            Lint::UnusedArgument => codes::UNUSED_ARGUMENT,
+            Lint::UnreachableCode => codes::UNREACHABLE_CODE,
+            Lint::AllocLocals => codes::ALLOC_LOCALS,
+            Lint::Shadowing => codes::SHADOWING,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BindingKind {
+    ImplicitArgument,
+    Argument,
+    Local,
+    Reference,
+}
+
+struct Binding {
+    name: String,
+    span: Span,
+    kind: BindingKind,
+    used: bool,
+}
+
+// Bindings a name may refer to, several of them after an if whose branches rebind it.
+type Scope = HashMap<String, Vec<usize>>;
+
+struct FunctionLinter<'a> {
+    // implicit arguments of every function, taken from the caller's scope when not passed
+    implicit_args: &'a HashMap<String, Vec<String>>,
+    allowed: Vec<String>,
+    bindings: Vec<Binding>,
+    warnings: Vec<Diagnostic>,
+}
+
+pub fn check(code_elements: &[CodeElement]) -> Vec<Diagnostic> {
+    let mut implicit_args: HashMap<String, Vec<String>> = stdlib::functions()
+        .iter()
+        .chain(code_elements.iter().filter_map(|element| match element {
+            CodeElement::Function(function) => Some(function),
+            _ => None,
+        }))
+        .map(|function| {
+            (
+                function.name.token.lexeme.clone(),
+                names(&function.implicit_args),
+            )
+        })
+        .collect();
+    // the builtin pointers are the implicit arguments of main
+    implicit_args.remove("main");
+
+    let mut warnings = Vec::new();
+    for element in code_elements {
+        if let CodeElement::Function(function) = element {
+            let mut linter = FunctionLinter {
+                implicit_args: &implicit_args,
+                allowed: function
+                    .decorators
+                    .iter()
+                    .filter_map(|decorator| decorator.token.lexeme.strip_prefix("allow_"))
+                    .map(|name| name.to_string())
+                    .collect(),
+                bindings: Vec::new(),
+                warnings: Vec::new(),
+            };
+            linter.function(function);
+            linter.warnings.sort_by_key(|warning| warning.span);
+            warnings.extend(linter.warnings);
+        }
+    }
+    warnings
+}
+
+fn names(args: &[TypedIdentifier]) -> Vec<String> {
+    args.iter()
+        .map(|arg| arg.ident.token.lexeme.clone())
+        .collect()
+}
+
+// `p.x` is a read of p
+fn base_name(lexeme: &str) -> &str {
+    lexeme.split('.').next().unwrap_or(lexeme)
+}
+
+// Names a hint refers to as `ids.name`.
+fn hint_names(code: &str) -> Vec<String> {
+    code.match_indices("ids.")
+        .filter(|(i, _)| {
+            !code[..*i]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_')
+        })
+        .map(|(i, _)| {
+            code[i + 4..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect()
+        })
+        .collect()
+}
+
+fn n_locals(body: &[CodeElement]) -> usize {
+    body.iter()
+        .map(|element| match element {
+            CodeElement::LocalVar(_, _) => 1,
+            CodeElement::If(_, body, else_body) => n_locals(body) + n_locals(else_body),
+            _ => 0,
+        })
+        .sum()
+}
+
+fn first_local(body: &[CodeElement]) -> Option<Span> {
+    body.iter().find_map(|element| match element {
+        CodeElement::LocalVar(ident, _) => Some(ident.ident.token.span),
+        CodeElement::If(_, body, else_body) => first_local(body).or(first_local(else_body)),
+        _ => None,
+    })
+}
+
+// Whether control never reaches past the element.
+fn returns(element: &CodeElement) -> bool {
+    match element {
+        CodeElement::Return(_) => true,
+        CodeElement::Instruction(instruction) => {
+            instruction.instruction_type == InstructionType::Ret
+        }
+        CodeElement::If(_, body, else_body) => {
+            body.iter().any(returns) && else_body.iter().any(returns)
+        }
+        _ => false,
+    }
+}
+
+impl FunctionLinter<'_> {
+    fn warn(&mut self, lint: Lint, diagnostic: Diagnostic) {
+        let allowed = self
+            .allowed
+            .iter()
+            .any(|name| name == "warnings" || name == lint.name());
+        if !allowed {
+            self.warnings.push(diagnostic.with_note(format!(
+                "@allow_{} on the function silences this warning",
+                lint.name()
+            )));
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        let mut scope = Scope::new();
+        for (kind, args) in [
+            (BindingKind::ImplicitArgument, &function.implicit_args),
+            (BindingKind::Argument, &function.args),
+        ] {
+            for arg in args {
+                self.declare(&mut scope, &arg.ident, kind);
+            }
+        }
+        self.block(&mut scope, &function.body);
+
+        let name = &function.name.token.lexeme;
+        let alloc_locals = function.body.iter().find_map(|element| match element {
+            CodeElement::AllocLocals(token) => Some(token.span),
+            _ => None,
+        });
+        match (alloc_locals, n_locals(&function.body)) {
+            (Some(span), 0) => self.warn(
+                Lint::AllocLocals,
+                Diagnostic::warning(
+                    Lint::AllocLocals.code(),
+                    format!("alloc_locals in '{}', which has no locals", name),
+                )
+                .with_span(span)
+                .with_help("remove alloc_locals".to_string()),
+            ),
+            (None, n) if n > 0 => {
+                let span = first_local(&function.body).unwrap_or(function.name.token.span);
+                self.warn(
+                    Lint::AllocLocals,
+                    Diagnostic::warning(
+                        Lint::AllocLocals.code(),
+                        format!("'{}' declares locals without alloc_locals", name),
+                    )
+                    .with_label(span, "first local".to_string())
+                    .with_note(
+                        "czc allocates the frame anyway, but cairo-lang rejects the function"
+                            .to_string(),
+                    )
+                    .with_help(format!("add alloc_locals; at the start of '{}'", name)),
+                );
+            }
+            _ => {}
+        }
+
+        for i in 0..self.bindings.len() {
+            let binding = &self.bindings[i];
+            if binding.used || binding.name.starts_with('_') {
+                continue;
+            }
+            let (lint, what) = match binding.kind {
+                BindingKind::Local => (Lint::UnusedLocal, "local variable"),
+                BindingKind::Argument => (Lint::UnusedArgument, "argument"),
+                _ => continue,
+            };
+            let diagnostic =
+                Diagnostic::warning(lint.code(), format!("Unused {} '{}'", what, binding.name))
+                    .with_label(binding.span, "never read".to_string())
+                    .with_help(format!(
+                        "if this is intentional, prefix it with an underscore: '_{}'",
+                        binding.name
+                    ));
+            self.warn(lint, diagnostic);
+        }
+    }
+
+    fn declare(&mut self, scope: &mut Scope, ident: &Identifier, kind: BindingKind) {
+        let name = ident.token.lexeme.clone();
+        let span = ident.token.span;
+        // references are rebound on purpose, so are the implicit arguments
+        let shadowed = scope.get(&name).and_then(|previous| {
+            previous.iter().copied().find(|i| {
+                let previous = self.bindings[*i].kind;
+                !(kind == BindingKind::Reference
+                    && matches!(
+                        previous,
+                        BindingKind::Reference | BindingKind::ImplicitArgument
+                    ))
+            })
+        });
+        if let Some(previous) = shadowed {
+            let previous_span = self.bindings[previous].span;
+            self.warn(
+                Lint::Shadowing,
+                Diagnostic::warning(
+                    Lint::Shadowing.code(),
+                    format!("'{}' shadows an earlier declaration", name),
+                )
+                .with_span(span)
+                .with_secondary(previous_span, "previously declared here".to_string()),
+            );
+        }
+        scope.insert(name.clone(), vec![self.bindings.len()]);
+        self.bindings.push(Binding {
+            name,
+            span,
+            kind,
+            used: false,
+        });
+    }
+
+    fn read(&mut self, scope: &Scope, name: &str) {
+        for i in scope.get(base_name(name)).into_iter().flatten() {
+            self.bindings[*i].used = true;
+        }
+    }
+
+    fn expr(&mut self, scope: &mut Scope, expr: &Expr) {
+        match expr.expr_type {
+            ExprType::Identifier => {
+                if let Some(ident) = &expr.ident {
+                    self.read(scope, &ident.token.lexeme);
+                }
+            }
+            ExprType::Hint => {
+                if let Some(token) = &expr.token {
+                    for name in hint_names(&token.lexeme) {
+                        self.read(scope, &name);
+                    }
+                }
+            }
+            ExprType::FunctionCall => {
+                // implicit arguments not passed explicitly are read from the scope
+                let callee = expr.ident.as_ref().map(|ident| &ident.token.lexeme);
+                let implicit_args = callee
+                    .and_then(|callee| self.implicit_args.get(callee))
+                    .cloned()
+                    .unwrap_or_default();
+                for name in implicit_args.iter() {
+                    let passed = expr.brace_args.iter().any(|arg| {
+                        matches!(arg, ExprAssignment::Assign(ident, _) if ident.token.lexeme == *name)
+                    });
+                    if !passed {
+                        self.read(scope, name);
+                    }
+                }
+            }
+            _ => {}
+        }
+        for child in [&expr.left, &expr.right].into_iter().flatten() {
+            self.expr(scope, child);
+        }
+        for arg in expr.paren_args.iter().chain(expr.brace_args.iter()) {
+            match arg {
+                ExprAssignment::Expr(expr) | ExprAssignment::Assign(_, expr) => {
+                    self.expr(scope, expr)
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, scope: &mut Scope, body: &[CodeElement]) {
+        let mut unreachable_after = None;
+        for element in body {
+            if let Some(span) = unreachable_after {
+                let mut diagnostic = Diagnostic::warning(
+                    Lint::UnreachableCode.code(),
+                    "Unreachable code".to_string(),
+                );
+                if let Some(element_span) = element.span() {
+                    diagnostic = diagnostic.with_span(element_span);
+                }
+                if let Some(span) = span {
+                    diagnostic = diagnostic
+                        .with_secondary(span, "any code following this is unreachable".to_string());
+                }
+                self.warn(Lint::UnreachableCode, diagnostic);
+                // the rest of the block is still checked for unused names
+                unreachable_after = None;
+            }
+            self.code_element(scope, element);
+            if returns(element) {
+                unreachable_after = Some(element.span());
+            }
+        }
+    }
+
+    fn code_element(&mut self, scope: &mut Scope, element: &CodeElement) {
+        match element {
+            CodeElement::LocalVar(ident, expr) => {
+                if let Some(expr) = expr {
+                    self.expr(scope, expr);
+                }
+                self.declare(scope, &ident.ident, BindingKind::Local);
+            }
+            CodeElement::Reference(ident, expr) => {
+                self.expr(scope, expr);
+                self.declare(scope, &ident.ident, BindingKind::Reference);
+            }
+            CodeElement::Instruction(instruction) => {
+                for (i, arg) in instruction.args.iter().enumerate() {
+                    // `assert x = value` writes x
+                    if i == 0
+                        && instruction.instruction_type == InstructionType::AssertEq
+                        && matches!(arg.expr_type, ExprType::Identifier)
+                    {
+                        continue;
+                    }
+                    self.expr(scope, arg);
+                }
+            }
+            CodeElement::CompoundAssertEqual(left, right) => {
+                self.expr(scope, left);
+                self.expr(scope, right);
+            }
+            CodeElement::Return(expr) | CodeElement::FuncCall(expr) => self.expr(scope, expr),
+            CodeElement::If(condition, body, else_body) => {
+                self.expr(scope, condition);
+                let mut body_scope = scope.clone();
+                self.block(&mut body_scope, body);
+                let mut else_scope = scope.clone();
+                self.block(&mut else_scope, else_body);
+                // names bound in both branches refer to either binding afterwards
+                scope.clear();
+                for (name, mut bindings) in body_scope {
+                    if let Some(others) = else_scope.get(&name) {
+                        for i in others {
+                            if !bindings.contains(i) {
+                                bindings.push(*i);
+                            }
+                        }
+                        bindings.sort();
+                        scope.insert(name, bindings);
+                    }
+                }
+            }
+            CodeElement::Hint(token) => {
+                for name in hint_names(&token.lexeme) {
+                    self.read(scope, &name);
+                }
+            }
+            _ => {}
+        }
+    }
+}
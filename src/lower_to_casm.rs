@@ -1,14 +1,167 @@
 use crate::ast::*;
-use crate::casm::{CasmInstruction, Operand};
+use crate::builtins::BuiltinKind;
+use crate::casm::{CasmInstruction, Hint, Operand, accessible_scopes};
+use crate::diagnostic::{Diagnostic, codes};
+use crate::felt::Felt;
+use crate::lexer::{Token, TokenType};
+use crate::stdlib;
 use std::collections::HashMap;
 
+// What callers need to know about a function to call it.
+#[derive(Clone)]
+struct FunctionSignature {
+    implicit_args: Vec<String>,
+    args: Vec<String>,
+    n_return_values: usize,
+    // name of the declaration, for functions of the compiled source
+    span: Option<Span>,
+}
+
 pub struct Compiler {
     code_elements: Vec<CodeElement>,
     casm_instructions: Vec<CasmInstruction>,
     local_variables: HashMap<String, i32>,
-    size_of_locals: u64,
     current_local_offset: u64,
     label_counter: u64,
+    current_function: String,
+    current_implicit_args: Vec<String>,
+    alloc_locals_index: Option<usize>,
+    functions: HashMap<String, FunctionSignature>,
+    // library functions not defined by the program, compiled once they are called
+    library: HashMap<String, Function>,
+    pending_library_functions: Vec<Function>,
+    pub builtins: Vec<String>,
+    // names of the fp-relative cells of each function, in allocation order
+    pub frame_layouts: HashMap<String, Vec<(String, i32)>>,
+    // spans of the elements being compiled, innermost last
+    location_stack: Vec<Span>,
+    // errors found while lowering, the instructions are unusable if there are any
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn signature(function: &Function) -> FunctionSignature {
+    FunctionSignature {
+        implicit_args: function
+            .implicit_args
+            .iter()
+            .map(|arg| arg.ident.token.lexeme.clone())
+            .collect(),
+        args: function
+            .args
+            .iter()
+            .map(|arg| arg.ident.token.lexeme.clone())
+            .collect(),
+        n_return_values: n_return_values(function),
+        span: None,
+    }
+}
+
+// Number of values pushed by a `return`, implicit arguments excluded.
+fn n_return_values(function: &Function) -> usize {
+    match &function.return_type {
+        Some(Type::Tuple(types)) => types.len(),
+        Some(_) => 1,
+        None => function
+            .body
+            .iter()
+            .find_map(|code_element| match code_element {
+                CodeElement::Return(expr) => Some(match expr.expr_type {
+                    ExprType::TupleOrParen => expr.paren_args.len(),
+                    _ => 1,
+                }),
+                _ => None,
+            })
+            .unwrap_or(0),
+    }
+}
+
+fn identifier_expr(name: &str) -> Expr {
+    Expr::new_identifier(Identifier {
+        token: Token {
+            token_type: TokenType::Identifier,
+            lexeme: name.to_string(),
+            span: (0, 0),
+        },
+    })
+}
+
+fn contains_call(expr: &Expr) -> bool {
+    matches!(expr.expr_type, ExprType::FunctionCall)
+        || expr.left.as_ref().is_some_and(|left| contains_call(left))
+        || expr
+            .right
+            .as_ref()
+            .is_some_and(|right| contains_call(right))
+        || expr.paren_args.iter().any(|arg| match arg {
+            ExprAssignment::Expr(expr) | ExprAssignment::Assign(_, expr) => contains_call(expr),
+        })
+}
+
+// literals wider than 256 bits are reported by resolve and never lowered
+fn parse_int(lexeme: &str) -> Felt {
+    lexeme.parse().unwrap_or_default()
+}
+
+// The operand once `pushed` more cells are pushed: ap-relative cells move down.
+fn shift(operand: Operand, pushed: i32) -> Operand {
+    match operand {
+        Operand::DerefAp(offset) => Operand::DerefAp(offset - pushed),
+        operand => operand,
+    }
+}
+
+fn is_top(operand: &Operand) -> bool {
+    matches!(operand, Operand::DerefAp(-1))
+}
+
+fn is_zero(expr: &Expr) -> bool {
+    matches!(expr.expr_type, ExprType::IntegerLiteral)
+        && expr
+            .token
+            .as_ref()
+            .is_some_and(|token| parse_int(&token.lexeme) == Felt::ZERO)
+}
+
+// The constant k of `p + k` or `p - k`, when it fits in the offset of an instruction.
+fn folded_offset(expr: &Expr) -> Option<i32> {
+    let sign = match expr.expr_type {
+        ExprType::Add => 1,
+        ExprType::Sub => -1,
+        _ => return None,
+    };
+    let right = expr.right.as_ref()?;
+    if !matches!(right.expr_type, ExprType::IntegerLiteral) {
+        return None;
+    }
+    let k = parse_int(&right.token.as_ref()?.lexeme).to_i128()?;
+    i32::try_from(k)
+        .ok()
+        .filter(|k| *k < 1 << 15)
+        .map(|k| sign * k)
+}
+
+// Strips the `%{ %}` delimiters and the common indentation of a hint block.
+fn hint_code(lexeme: &str) -> String {
+    let inner = &lexeme[2..lexeme.len() - 2];
+    let lines: Vec<&str> = inner.lines().collect();
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let dedented: Vec<&str> = lines
+        .iter()
+        .map(|line| {
+            if line.len() >= indent {
+                &line[indent..]
+            } else {
+                line.trim_start()
+            }
+        })
+        .map(|line| line.trim_end())
+        .collect();
+    dedented.join("\n").trim().to_string()
 }
 
 impl Compiler {
@@ -17,269 +170,785 @@ impl Compiler {
             code_elements,
             casm_instructions: Vec::new(),
             local_variables: HashMap::new(),
-            size_of_locals: 0,
             current_local_offset: 0,
             label_counter: 0,
+            current_function: String::new(),
+            current_implicit_args: Vec::new(),
+            alloc_locals_index: None,
+            functions: HashMap::new(),
+            library: HashMap::new(),
+            pending_library_functions: Vec::new(),
+            builtins: Vec::new(),
+            frame_layouts: HashMap::new(),
+            location_stack: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
+    // Number of explicit arguments of a compiled function.
+    pub fn n_args(&self, name: &str) -> Option<usize> {
+        self.functions
+            .get(name)
+            .map(|signature| signature.args.len())
+    }
+
+    // Number of values a compiled function returns, implicit arguments excluded.
+    pub fn n_return_values(&self, name: &str) -> Option<usize> {
+        self.functions
+            .get(name)
+            .map(|signature| signature.n_return_values)
+    }
+
     pub fn compile(&mut self) -> Vec<CasmInstruction> {
+        for code_element in self.code_elements.iter() {
+            if let CodeElement::Directive(directive, args) = code_element
+                && directive.token_type == TokenType::Builtins
+            {
+                for arg in args {
+                    if BuiltinKind::from_name(&arg.token.lexeme).is_none() {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                codes::UNKNOWN_BUILTIN,
+                                format!("Unknown builtin '{}'", arg.token.lexeme),
+                            )
+                            .with_span(arg.token.span)
+                            .with_help(
+                                "the supported builtins are output, range_check, bitwise and poseidon"
+                                    .to_string(),
+                            ),
+                        );
+                        continue;
+                    }
+                    self.builtins.push(arg.token.lexeme.clone());
+                }
+            }
+        }
+        // the runner passes the builtin pointers to main as implicit arguments
+        let builtin_pointers: Vec<TypedIdentifier> = self
+            .builtins
+            .iter()
+            .map(|builtin| TypedIdentifier {
+                ident: identifier_expr(&format!("{}_ptr", builtin)).ident.unwrap(),
+                type_: None,
+            })
+            .collect();
+        for code_element in self.code_elements.iter_mut() {
+            if let CodeElement::Function(function) = code_element {
+                if function.name.token.lexeme == "main" && function.implicit_args.is_empty() {
+                    function.implicit_args = builtin_pointers.clone();
+                }
+                let mut signature = signature(function);
+                signature.span = Some(function.name.token.span);
+                self.functions
+                    .insert(function.name.token.lexeme.clone(), signature);
+            }
+        }
+        for function in stdlib::functions() {
+            let name = function.name.token.lexeme.clone();
+            if !self.functions.contains_key(&name) {
+                self.functions.insert(name.clone(), signature(&function));
+                self.library.insert(name, function);
+            }
+        }
+
         for code_element in self.code_elements.clone() {
             self.compile_code_element(code_element);
         }
+        while let Some(function) = self.pending_library_functions.pop() {
+            self.compile_function(function);
+        }
         self.casm_instructions.clone()
     }
 
-    // pushes litteral on stack and returns ap offset (ie 1)
-    fn compile_int_literal(&mut self, expr: Expr) -> i32 {
-        assert!(matches!(expr.expr_type, ExprType::IntegerLiteral));
+    // pushes the value of the operand, returning the number of cells pushed (ie 1)
+    fn push(&mut self, operand: Operand) -> i32 {
         self.casm_instructions.push(CasmInstruction::Set {
             left: Operand::DerefAp(0),
-            op: Operand::Int(expr.token.unwrap().lexeme.parse::<u64>().unwrap()),
+            op: operand,
             incr_ap: true,
         });
-        return 1;
+        1
     }
 
-    fn compile_add(&mut self, expr: Expr) -> i32 {
-        assert!(matches!(expr.expr_type, ExprType::Add));
-        let left_offset = self.compile_expr(*expr.left.unwrap());
-        let right_offset = self.compile_expr(*expr.right.unwrap());
-        self.casm_instructions.push(CasmInstruction::Add {
-            left: Operand::DerefAp(0),
-            op1: Operand::DerefAp(-1 - right_offset),
-            op2: Operand::DerefAp(-1),
-        });
-        left_offset + right_offset + 1
+    // attaches a hint to the next emitted instruction, exposing the current locals as ids
+    fn push_hint(&mut self, code: String) {
+        // temporaries like %tmp0 are not names of the program, hints cannot refer to them
+        let mut references: Vec<(String, i32)> = self
+            .local_variables
+            .iter()
+            .filter(|(name, _)| !name.starts_with('%'))
+            .map(|(name, offset)| (name.clone(), *offset))
+            .collect();
+        references.sort();
+        self.casm_instructions.push(CasmInstruction::Hint(Hint {
+            code,
+            accessible_scopes: accessible_scopes(&self.current_function),
+            references,
+        }));
     }
 
-    fn compile_sub(&mut self, expr: Expr) -> i32 {
-        assert!(matches!(expr.expr_type, ExprType::Sub));
-        let left_offset = self.compile_expr(*expr.left.unwrap());
-        let right_offset = self.compile_expr(*expr.right.unwrap());
-        self.casm_instructions.push(CasmInstruction::Add {
-            left: Operand::DerefAp(-1 - right_offset),
-            op1: Operand::DerefAp(0),
-            op2: Operand::DerefAp(-1),
+    // nondet %{ x %} is lowered to a hint writing x at [ap], followed by ap += 1
+    fn compile_nondet(&mut self, expr: Expr) -> i32 {
+        assert!(matches!(expr.expr_type, ExprType::Hint));
+        let code = hint_code(&expr.token.unwrap().lexeme);
+        self.push_hint(format!("memory[ap] = to_felt_or_relocatable({})", code));
+        self.casm_instructions.push(CasmInstruction::IncrAp(1));
+        1
+    }
+
+    // a + b and a * b are computed from the operands of a and b, a - b is deduced from
+    // a = [ap] + b; immediates can only be the second operand
+    fn compile_binary(&mut self, expr: Expr) -> (Operand, i32) {
+        let expr_type = expr.expr_type;
+        let right = *expr.right.unwrap();
+        let (left, mut pushed) = self.compile_operand(*expr.left.unwrap());
+        let left = self.keep(left, &right);
+        let (mut right, right_pushed) = self.compile_operand(right);
+        let mut left = shift(left, right_pushed);
+        pushed += right_pushed;
+        if !matches!(expr_type, ExprType::Sub) && matches!(left, Operand::Int(_)) {
+            std::mem::swap(&mut left, &mut right);
+        }
+        if matches!(left, Operand::Int(_)) {
+            pushed += self.push(left);
+            right = shift(right, 1);
+            left = Operand::DerefAp(-1);
+        }
+        self.casm_instructions.push(match expr_type {
+            ExprType::Add => CasmInstruction::Add {
+                left: Operand::DerefAp(0),
+                op1: left,
+                op2: right,
+            },
+            ExprType::Mul => CasmInstruction::Mul {
+                left: Operand::DerefAp(0),
+                op1: left,
+                op2: right,
+            },
+            _ => CasmInstruction::Add {
+                left,
+                op1: Operand::DerefAp(0),
+                op2: right,
+            },
         });
-        left_offset + right_offset + 1
+        (Operand::DerefAp(-1), pushed + 1)
     }
 
-    fn compile_mul(&mut self, expr: Expr) -> i32 {
-        assert!(matches!(expr.expr_type, ExprType::Mul));
-        let left_offset = self.compile_expr(*expr.left.unwrap());
-        let right_offset = self.compile_expr(*expr.right.unwrap());
-        self.casm_instructions.push(CasmInstruction::Mul {
-            left: Operand::DerefAp(0),
-            op1: Operand::DerefAp(-1 - right_offset),
-            op2: Operand::DerefAp(-1),
+    // a call moves ap by an unknown amount, so an ap-relative operand that must outlive the
+    // evaluation of `next` is stored in a local first
+    fn keep(&mut self, operand: Operand, next: &Expr) -> Operand {
+        if matches!(operand, Operand::DerefAp(_)) && contains_call(next) {
+            let (_, slot) = self.store(operand);
+            return Operand::DerefFp(slot);
+        }
+        operand
+    }
+
+    // evaluates the expressions and leaves their values contiguous at the top of the stack,
+    // copying them when intermediate results were pushed in between
+    fn push_values(&mut self, exprs: Vec<Expr>) -> i32 {
+        let n_values = exprs.len();
+        // a call moves ap by an unknown amount, so call results are stored in locals first
+        let exprs: Vec<Expr> = if n_values > 1 {
+            exprs
+                .into_iter()
+                .map(|expr| {
+                    if contains_call(&expr) {
+                        self.spill(expr)
+                    } else {
+                        expr
+                    }
+                })
+                .collect()
+        } else {
+            exprs
+        };
+        // each value is pushed right above the previous one, until one leaves intermediate
+        // results below itself
+        let mut operands: Vec<Operand> = Vec::new();
+        let mut total = 0;
+        let mut contiguous = true;
+        for (i, expr) in exprs.into_iter().enumerate() {
+            let (mut operand, pushed) = self.compile_operand(expr);
+            total += pushed;
+            let in_place = pushed > 0 && is_top(&operand);
+            if i > 0 && pushed > 0 && !(pushed == 1 && in_place) {
+                contiguous = false;
+            }
+            let mut moved = pushed;
+            if contiguous && !in_place {
+                total += self.push(operand);
+                operand = Operand::DerefAp(-1);
+                moved += 1;
+            }
+            for previous in operands.iter_mut() {
+                *previous = shift(previous.clone(), moved);
+            }
+            operands.push(operand);
+        }
+        if contiguous {
+            return total;
+        }
+        for (i, operand) in operands.into_iter().enumerate() {
+            // i accounts for the values copied before this one
+            total += self.push(shift(operand, i as i32));
+        }
+        total
+    }
+
+    // stores the operand in a new local, returning its name and slot
+    fn store(&mut self, operand: Operand) -> (String, i32) {
+        let name = format!("%tmp{}", self.current_local_offset);
+        let slot = self.new_slot(name.clone());
+        self.casm_instructions.push(CasmInstruction::Set {
+            left: Operand::DerefFp(slot),
+            op: operand,
+            incr_ap: false,
         });
-        left_offset + right_offset + 1
+        (name, slot)
+    }
+
+    // evaluates an expression into a new local and returns a reference to it
+    fn spill(&mut self, expr: Expr) -> Expr {
+        let (operand, _) = self.compile_operand(expr);
+        let (name, _) = self.store(operand);
+        identifier_expr(&name)
     }
 
     fn compile_function_call(&mut self, expr: Expr) -> i32 {
         assert!(matches!(expr.expr_type, ExprType::FunctionCall));
         let func_name = expr.ident.unwrap().token.lexeme;
-        let mut arg_offsets = Vec::new();
-        // evaluating each argument and storing the ap offset
-        for arg in expr.paren_args {
-            let arg_offset = match arg {
-                ExprAssignment::Expr(expr) => self.compile_expr(expr),
-                ExprAssignment::Assign(ident, expr) => todo!(),
-            };
-            arg_offsets.push(arg_offset);
+        let signature = self.functions.get(&func_name).cloned();
+        if let Some(function) = self.library.remove(&func_name) {
+            self.pending_library_functions.push(function);
         }
-        // Calculate sum of all elements after each index, ie cumulative offset to reach each arg
-        let mut total = 0;
-        for arg_offset in arg_offsets.iter_mut().rev() {
-            let current = *arg_offset;
-            *arg_offset = total;
-            total += current;
-        }
-        // Push args to stack
-        for (i, arg_offset) in arg_offsets.iter().enumerate() {
-            let instr = CasmInstruction::Set {
-                left: Operand::DerefAp(0),
-                op: Operand::DerefAp(-1 - arg_offset - i as i32), // i accounts for the fact that pushing each argument further increases the ap offset
-                incr_ap: true,
-            };
-            self.casm_instructions.push(instr);
+
+        // implicit arguments are taken from the brace arguments, or from the variable of the same name
+        let mut implicit_exprs = Vec::new();
+        let mut rebound = Vec::new();
+        if let Some(signature) = &signature {
+            for name in signature.implicit_args.iter() {
+                let value = expr.brace_args.iter().find_map(|arg| match arg {
+                    ExprAssignment::Assign(ident, value) if ident.token.lexeme == *name => {
+                        Some(value.clone())
+                    }
+                    ExprAssignment::Expr(value)
+                        if value.ident.as_ref().map(|ident| &ident.token.lexeme) == Some(name) =>
+                    {
+                        Some(value.clone())
+                    }
+                    _ => None,
+                });
+                let value = value.unwrap_or_else(|| identifier_expr(name));
+                rebound.push(match value.expr_type {
+                    ExprType::Identifier => Some(value.ident.clone().unwrap().token.lexeme),
+                    _ => None,
+                });
+                implicit_exprs.push(value);
+            }
         }
+
+        // named arguments are reordered following the declaration
+        let mut args = Vec::new();
+        for (i, arg) in expr.paren_args.into_iter().enumerate() {
+            match arg {
+                ExprAssignment::Expr(expr) => args.push((i, expr)),
+                ExprAssignment::Assign(ident, expr) => {
+                    let position = signature.as_ref().and_then(|signature| {
+                        signature
+                            .args
+                            .iter()
+                            .position(|name| *name == ident.token.lexeme)
+                    });
+                    match position {
+                        Some(position) => args.push((position, expr)),
+                        None => self.unknown_argument(&ident, &func_name, signature.as_ref()),
+                    }
+                }
+            }
+        }
+        args.sort_by_key(|(position, _)| *position);
+        implicit_exprs.extend(args.into_iter().map(|(_, expr)| expr));
+        let _ = self.push_values(implicit_exprs);
+
         // calling function
         let instr = CasmInstruction::Call(func_name);
         self.casm_instructions.push(instr);
+
+        // the callee returns its implicit arguments first, followed by its return values
+        if let Some(signature) = signature {
+            let n_returned = (signature.implicit_args.len() + signature.n_return_values) as i32;
+            for (i, name) in rebound.into_iter().enumerate() {
+                if let Some(name) = name {
+                    let slot = self.new_slot(name);
+                    self.casm_instructions.push(CasmInstruction::Set {
+                        left: Operand::DerefFp(slot),
+                        op: Operand::DerefAp(-n_returned + i as i32),
+                        incr_ap: false,
+                    });
+                }
+            }
+        }
         // return value is at top of stack
         1
     }
 
-    // pushes local variable on stack and returns ap offset (ie 1)
-    fn compile_identifier(&mut self, expr: Expr) -> i32 {
-        assert!(matches!(expr.expr_type, ExprType::Identifier));
-        let ident = expr.ident.unwrap().token.lexeme;
-        self.casm_instructions.push(CasmInstruction::Set {
+    fn unknown_argument(
+        &mut self,
+        ident: &Identifier,
+        func_name: &str,
+        signature: Option<&FunctionSignature>,
+    ) {
+        let mut diagnostic = Diagnostic::error(
+            codes::UNKNOWN_ARGUMENT,
+            format!(
+                "Unknown argument '{}' of '{}'",
+                ident.token.lexeme, func_name
+            ),
+        )
+        .with_label(ident.token.span, "no argument of this name".to_string());
+        if let Some(signature) = signature {
+            if let Some(span) = signature.span {
+                diagnostic =
+                    diagnostic.with_secondary(span, format!("'{}' is declared here", func_name));
+            }
+            diagnostic = diagnostic.with_note(match signature.args.is_empty() {
+                true => format!("'{}' takes no arguments", func_name),
+                false => format!("'{}' takes {}", func_name, signature.args.join(", ")),
+            });
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    // reports a construct the compiler cannot lower, at the innermost known location
+    fn unsupported(&mut self, what: String, span: Option<Span>) {
+        let mut diagnostic = Diagnostic::error(codes::UNSUPPORTED, format!("Unsupported {}", what));
+        if let Some(span) = span.or(self.location_stack.last().copied()) {
+            diagnostic = diagnostic.with_span(span);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    // the pointer of [p + k], with k folded into the offset of the instruction reading it
+    fn compile_pointer(&mut self, expr: Expr) -> (Operand, i32, i32) {
+        let (base, offset) = match folded_offset(&expr) {
+            Some(offset) => (*expr.left.unwrap(), offset),
+            None => (expr, 0),
+        };
+        let (pointer, mut pushed) = self.compile_operand(base);
+        let pointer = match pointer {
+            Operand::Int(_) => {
+                pushed += self.push(pointer);
+                Operand::DerefAp(-1)
+            }
+            pointer => pointer,
+        };
+        (pointer, offset, pushed)
+    }
+
+    // dereferences the pointer computed by the inner expression
+    fn compile_deref(&mut self, expr: Expr) -> (Operand, i32) {
+        assert!(matches!(expr.expr_type, ExprType::Deref));
+        let (pointer, offset, pushed) = self.compile_pointer(*expr.left.unwrap());
+        self.casm_instructions.push(CasmInstruction::Deref {
             left: Operand::DerefAp(0),
-            op: Operand::DerefFp(self.local_variables[&ident] as i32),
+            op: pointer,
+            offset,
             incr_ap: true,
         });
-        return 1;
+        (Operand::DerefAp(-1), pushed + 1)
+    }
+
+    // a parenthesized expression is its value, a tuple is pushed
+    fn compile_tuple(&mut self, expr: Expr) -> (Operand, i32) {
+        assert!(matches!(expr.expr_type, ExprType::TupleOrParen));
+        let mut values = Vec::new();
+        for arg in expr.paren_args {
+            match arg {
+                ExprAssignment::Expr(expr) => values.push(expr),
+                ExprAssignment::Assign(_, expr) => values.push(expr),
+            }
+        }
+        if values.len() == 1 {
+            return self.compile_operand(values.pop().unwrap());
+        }
+        (Operand::DerefAp(-1), self.push_values(values))
+    }
+
+    // fp offset of a name, which the resolve pass checked is bound
+    fn lookup(&mut self, ident: &Identifier) -> i32 {
+        if let Some(slot) = self.local_variables.get(&ident.token.lexeme) {
+            return *slot;
+        }
+        let span = Some(ident.token.span)
+            .filter(|span| span.0 < span.1)
+            .or(self.location_stack.last().copied());
+        // structs have no layout in memory yet
+        if ident.token.lexeme.contains('.') {
+            self.unsupported(format!("member access '{}'", ident.token.lexeme), span);
+            return 0;
+        }
+        let mut diagnostic = Diagnostic::error(
+            codes::UNKNOWN_IDENTIFIER,
+            format!("Unknown identifier '{}'", ident.token.lexeme),
+        );
+        if let Some(span) = span {
+            diagnostic = diagnostic.with_span(span);
+        }
+        self.diagnostics.push(diagnostic);
+        0
+    }
+
+    // the following instructions come from `span`, until the matching leave_location
+    fn enter_location(&mut self, span: Option<Span>) -> bool {
+        let Some(span) = span else {
+            return false;
+        };
+        self.casm_instructions.push(CasmInstruction::Location(span));
+        self.location_stack.push(span);
+        true
+    }
+
+    // gives the instructions that follow back to the enclosing element
+    fn leave_location(&mut self, entered: bool) {
+        if !entered {
+            return;
+        }
+        self.location_stack.pop();
+        if let Some(span) = self.location_stack.last() {
+            self.casm_instructions
+                .push(CasmInstruction::Location(*span));
+        }
     }
 
-    pub fn compile_expr(&mut self, expr: Expr) -> i32 {
-        // compiles an expression and returns the total number of times ap was incremented
-        // operations are done at the top of the stack
-        // note that for ease of implementation, integers as well as references to variables are all pushed on stack
-        // which creates a lot of unecessary copies and instructions
+    // compiles an expression into the operand holding its value, returning the operand and
+    // the number of times ap was incremented
+    fn compile_operand(&mut self, expr: Expr) -> (Operand, i32) {
+        let entered = self.enter_location(expr.span());
+        let result = self.compile_operand_inner(expr);
+        self.leave_location(entered);
+        result
+    }
+
+    fn compile_operand_inner(&mut self, expr: Expr) -> (Operand, i32) {
+        // literals and names are used where they are, as immediates and frame cells, only
+        // the results of operations are pushed
         match expr.expr_type {
-            ExprType::IntegerLiteral => self.compile_int_literal(expr),
-            ExprType::Add => self.compile_add(expr),
-            ExprType::Sub => self.compile_sub(expr),
-            ExprType::Mul => self.compile_mul(expr),
-            ExprType::FunctionCall => self.compile_function_call(expr),
-            ExprType::Identifier => self.compile_identifier(expr),
+            ExprType::IntegerLiteral => (Operand::Int(parse_int(&expr.token.unwrap().lexeme)), 0),
+            ExprType::Identifier => (Operand::DerefFp(self.lookup(&expr.ident.unwrap())), 0),
+            ExprType::Add | ExprType::Sub | ExprType::Mul => self.compile_binary(expr),
+            // -x is 0 - x
+            ExprType::Neg => {
+                let zero = Token {
+                    token_type: TokenType::Int,
+                    lexeme: "0".to_string(),
+                    span: (0, 0),
+                };
+                self.compile_binary(Expr::new_binary(
+                    ExprType::Sub,
+                    Expr::new_terminal(ExprType::IntegerLiteral, zero),
+                    *expr.left.unwrap(),
+                ))
+            }
+            ExprType::FunctionCall => (Operand::DerefAp(-1), self.compile_function_call(expr)),
+            ExprType::Hint => (Operand::DerefAp(-1), self.compile_nondet(expr)),
+            ExprType::Deref => self.compile_deref(expr),
+            ExprType::TupleOrParen => self.compile_tuple(expr),
+            // the type checker validated the cast, the cell holds the same value
+            ExprType::Cast => self.compile_operand_inner(*expr.left.unwrap()),
 
-            _ => todo!(),
+            _ => {
+                self.unsupported(format!("expression {:?}", expr.expr_type), expr.span());
+                (Operand::Int(Felt::ZERO), 0)
+            }
         }
     }
 
-    pub fn compile_function(
-        &mut self,
-        name: Identifier,
-        args: Vec<Identifier>,
-        body: Vec<CodeElement>,
-    ) {
+    // evaluates an expression into the cell `dst` of the frame
+    fn compile_into(&mut self, expr: Expr, dst: Operand) {
+        if matches!(expr.expr_type, ExprType::Deref) {
+            let entered = self.enter_location(expr.span());
+            let (pointer, offset, _) = self.compile_pointer(*expr.left.unwrap());
+            self.casm_instructions.push(CasmInstruction::Deref {
+                left: dst,
+                op: pointer,
+                offset,
+                incr_ap: false,
+            });
+            self.leave_location(entered);
+            return;
+        }
+        let (operand, _) = self.compile_operand(expr);
+        self.casm_instructions.push(CasmInstruction::Set {
+            left: dst,
+            op: operand,
+            incr_ap: false,
+        });
+    }
+
+    pub fn compile_function(&mut self, function: Function) {
         self.local_variables.clear();
-        self.size_of_locals = 0;
         self.current_local_offset = 0;
-        // counting number of local declarations
-        for code_element in body.clone() {
-            match code_element {
-                CodeElement::LocalVar(ident, expr) => {
-                    self.size_of_locals += 1;
-                }
-                _ => {}
-            }
-        }
+        self.alloc_locals_index = None;
+        self.current_function = function.name.token.lexeme.clone();
+        self.current_implicit_args = function
+            .implicit_args
+            .iter()
+            .map(|arg| arg.ident.token.lexeme.clone())
+            .collect();
 
         self.casm_instructions
-            .push(CasmInstruction::Label(name.token.lexeme));
+            .push(CasmInstruction::Label(function.name.token.lexeme.clone()));
+        let name_span = function.name.token.span;
+        let entered = self.enter_location((name_span.0 < name_span.1).then_some(name_span));
+        let frame_index = self.casm_instructions.len();
 
+        // implicit arguments come first, at [fp - 2 - n + i] like the explicit ones
+        let args: Vec<TypedIdentifier> = function
+            .implicit_args
+            .into_iter()
+            .chain(function.args)
+            .collect();
+        let mut frame_layout = Vec::new();
         for (i, arg) in args.iter().enumerate() {
-            self.local_variables.insert(
-                arg.token.lexeme.clone(),
-                -(args.len() as i32 + 2) + i as i32,
-            );
+            let offset = -(args.len() as i32 + 2) + i as i32;
+            self.local_variables
+                .insert(arg.ident.token.lexeme.clone(), offset);
+            frame_layout.push((arg.ident.token.lexeme.clone(), offset));
         }
-        for code_element in body {
+        self.frame_layouts
+            .insert(function.name.token.lexeme, frame_layout);
+        for code_element in function.body {
             self.compile_code_element(code_element);
         }
+
+        // the frame size is only known once the body is compiled
+        let size_of_locals = self.current_local_offset;
+        match self.alloc_locals_index {
+            Some(index) => self.casm_instructions[index] = CasmInstruction::IncrAp(size_of_locals),
+            None if size_of_locals > 0 => self
+                .casm_instructions
+                .insert(frame_index, CasmInstruction::IncrAp(size_of_locals)),
+            None => {}
+        }
+        self.leave_location(entered);
     }
 
-    fn compile_local_var(&mut self, ident: Identifier, expr: Option<Expr>) {
-        self.local_variables
-            .insert(ident.token.lexeme, self.current_local_offset as i32);
+    // allocates a new local cell [fp + k], named `name` in the frame layout
+    fn allocate(&mut self, name: &str) -> i32 {
+        let slot = self.current_local_offset as i32;
+        if let Some(layout) = self.frame_layouts.get_mut(&self.current_function) {
+            layout.push((name.to_string(), slot));
+        }
         self.current_local_offset += 1;
+        slot
+    }
 
-        match expr {
-            Some(expr) => {
-                let _ = self.compile_expr(expr);
-                let instr = CasmInstruction::Set {
-                    left: Operand::DerefFp(self.current_local_offset as i32 - 1),
-                    op: Operand::DerefAp(-1),
-                    incr_ap: false,
-                };
-                self.casm_instructions.push(instr);
-            }
-            None => {}
+    // allocates a new local cell [fp + k] for the given name
+    fn new_slot(&mut self, name: String) -> i32 {
+        let slot = self.allocate(&name);
+        self.local_variables.insert(name, slot);
+        slot
+    }
+
+    fn compile_local_var(&mut self, ident: Identifier, expr: Option<Expr>) {
+        let slot = self.new_slot(ident.token.lexeme);
+
+        if let Some(expr) = expr {
+            self.compile_into(expr, Operand::DerefFp(slot));
         }
     }
 
+    // references are stored in a fresh local, which allows rebinding a name with `let`;
+    // the name is bound once the value is computed, which may read the previous binding
+    fn compile_reference(&mut self, ident: Identifier, expr: Expr) {
+        let slot = self.allocate(&ident.token.lexeme);
+        self.compile_into(expr, Operand::DerefFp(slot));
+        self.local_variables.insert(ident.token.lexeme, slot);
+    }
+
     fn compile_return(&mut self, expr: Expr) {
-        // calculating return value
-        // it is automatically at top of stack
-        let _ = self.compile_expr(expr);
+        // the current values of the implicit arguments are returned first,
+        // the return values end up at the top of the stack
+        let mut values: Vec<Expr> = self
+            .current_implicit_args
+            .clone()
+            .iter()
+            .map(|name| identifier_expr(name))
+            .collect();
+        match expr.expr_type {
+            ExprType::TupleOrParen => {
+                for arg in expr.paren_args {
+                    match arg {
+                        ExprAssignment::Expr(expr) => values.push(expr),
+                        ExprAssignment::Assign(_, expr) => values.push(expr),
+                    }
+                }
+            }
+            _ => values.push(expr),
+        }
+        let _ = self.push_values(values);
 
         self.casm_instructions.push(CasmInstruction::Ret);
     }
 
     fn compile_compound_assert_equal(&mut self, expr1: Expr, expr2: Expr) {
-        let value1 = self.compile_expr(expr1);
-        let value2 = self.compile_expr(expr2);
-        let instr = CasmInstruction::Set {
-            left: Operand::DerefAp(0),
-            op: Operand::DerefAp(-value2),
-            incr_ap: false,
+        // writing through a pointer: the value is computed first, and the memory cell
+        // behind the pointer is deduced by the vm from the assertion
+        let (target, value) = match (&expr1.expr_type, &expr2.expr_type) {
+            (ExprType::Deref, _) => (expr1, expr2),
+            (_, ExprType::Deref) => (expr2, expr1),
+            _ => {
+                let (left, _) = self.compile_operand(expr1);
+                let left = self.keep(left, &expr2);
+                let (mut right, pushed) = self.compile_operand(expr2);
+                let mut left = shift(left, pushed);
+                if matches!(left, Operand::Int(_)) {
+                    std::mem::swap(&mut left, &mut right);
+                }
+                if matches!(left, Operand::Int(_)) {
+                    self.push(left);
+                    right = shift(right, 1);
+                    left = Operand::DerefAp(-1);
+                }
+                self.casm_instructions.push(CasmInstruction::Set {
+                    left,
+                    op: right,
+                    incr_ap: false,
+                });
+                return;
+            }
         };
-        self.casm_instructions.push(instr);
+        let pointer = *target.left.unwrap();
+        let (value, _) = self.compile_operand(value);
+        let value = match value {
+            Operand::Int(_) => {
+                self.push(value);
+                Operand::DerefAp(-1)
+            }
+            value => value,
+        };
+        let value = self.keep(value, &pointer);
+        let (pointer, offset, pushed) = self.compile_pointer(pointer);
+        self.casm_instructions.push(CasmInstruction::Deref {
+            left: shift(value, pushed),
+            op: pointer,
+            offset,
+            incr_ap: false,
+        });
+    }
+
+    // compiles a branch on its own, returning its instructions and the resulting bindings
+    fn compile_branch(
+        &mut self,
+        body: Vec<CodeElement>,
+    ) -> (Vec<CasmInstruction>, HashMap<String, i32>) {
+        let bindings = self.local_variables.clone();
+        let start = self.casm_instructions.len();
+        for code_element in body {
+            self.compile_code_element(code_element);
+        }
+        let instructions = self.casm_instructions.split_off(start);
+        let branch_bindings = std::mem::replace(&mut self.local_variables, bindings);
+        (instructions, branch_bindings)
     }
 
     fn compile_if(&mut self, expr: Expr, body: Vec<CodeElement>, else_body: Vec<CodeElement>) {
-        match expr.expr_type {
-            ExprType::Neq => {
-                let _ = self.compile_expr(Expr::new_binary(
-                    ExprType::Sub,
-                    *expr.left.unwrap(),
-                    *expr.right.unwrap(),
-                ));
-                self.casm_instructions.push(CasmInstruction::JmpIfNeq(
-                    format!("if{}", self.label_counter),
-                    Operand::DerefAp(-1),
-                ));
-                for code_element in else_body {
-                    self.compile_code_element(code_element);
+        // the conditional jump skips the fallthrough branch when the difference is non zero
+        let (fallthrough, target, target_label) = match expr.expr_type {
+            ExprType::Neq => (else_body, body, format!("if{}", self.label_counter)),
+            ExprType::Eq => (body, else_body, format!("else{}", self.label_counter)),
+            _ => {
+                let mut diagnostic = Diagnostic::error(
+                    codes::INVALID_CONDITION,
+                    "Invalid condition for if statement".to_string(),
+                )
+                .with_help("compare two values with == or !=".to_string());
+                if let Some(span) = expr.span() {
+                    diagnostic = diagnostic.with_label(span, "not a comparison".to_string());
                 }
-                self.casm_instructions
-                    .push(CasmInstruction::Jmp(format!("end{}", self.label_counter)));
-                self.casm_instructions
-                    .push(CasmInstruction::Label(format!("if{}", self.label_counter)));
-                for code_element in body {
-                    self.compile_code_element(code_element);
-                }
-                self.casm_instructions
-                    .push(CasmInstruction::Label(format!("end{}", self.label_counter)));
-                self.label_counter += 1;
+                self.diagnostics.push(diagnostic);
+                // the branches may hold errors of their own
+                let _ = self.compile_branch(body);
+                let _ = self.compile_branch(else_body);
+                return;
             }
-            ExprType::Eq => {
-                let _ = self.compile_expr(Expr::new_binary(
-                    ExprType::Sub,
-                    *expr.left.unwrap(),
-                    *expr.right.unwrap(),
-                ));
-                self.casm_instructions.push(CasmInstruction::JmpIfNeq(
-                    format!("else{}", self.label_counter),
-                    Operand::DerefAp(-1),
-                ));
-                for code_element in body {
-                    self.compile_code_element(code_element);
-                }
-                self.casm_instructions
-                    .push(CasmInstruction::Jmp(format!("end{}", self.label_counter)));
-                self.casm_instructions.push(CasmInstruction::Label(format!(
-                    "else{}",
-                    self.label_counter
-                )));
-                for code_element in else_body {
-                    self.compile_code_element(code_element);
-                }
-                self.casm_instructions
-                    .push(CasmInstruction::Label(format!("end{}", self.label_counter)));
-                self.label_counter += 1;
+        };
+        let end_label = format!("end{}", self.label_counter);
+        self.label_counter += 1;
+
+        // comparing with zero needs no subtraction
+        let (left, right) = (*expr.left.unwrap(), *expr.right.unwrap());
+        let difference = if is_zero(&right) {
+            left
+        } else if is_zero(&left) {
+            right
+        } else {
+            Expr::new_binary(ExprType::Sub, left, right)
+        };
+        let condition = match self.compile_operand(difference) {
+            (Operand::Int(value), _) => {
+                self.push(Operand::Int(value));
+                Operand::DerefAp(-1)
+            }
+            (condition, _) => condition,
+        };
+        let (mut fallthrough, fallthrough_bindings) = self.compile_branch(fallthrough);
+        let (mut target, target_bindings) = self.compile_branch(target);
+
+        // names rebound differently in the two branches are merged into a common slot
+        let mut names: Vec<&String> = fallthrough_bindings.keys().collect();
+        names.sort();
+        for name in names {
+            let (Some(a), Some(b)) = (fallthrough_bindings.get(name), target_bindings.get(name))
+            else {
+                continue;
+            };
+            if a == b {
+                self.local_variables.insert(name.clone(), *a);
+                continue;
+            }
+            let slot = self.new_slot(name.clone());
+            for (instructions, source) in [(&mut fallthrough, *a), (&mut target, *b)] {
+                instructions.push(CasmInstruction::Set {
+                    left: Operand::DerefFp(slot),
+                    op: Operand::DerefFp(source),
+                    incr_ap: false,
+                });
             }
-            _ => panic!("Invalid expression type for if statement"),
         }
+
+        self.casm_instructions
+            .push(CasmInstruction::JmpIfNeq(target_label.clone(), condition));
+        self.casm_instructions.extend(fallthrough);
+        self.casm_instructions
+            .push(CasmInstruction::Jmp(end_label.clone()));
+        self.casm_instructions
+            .push(CasmInstruction::Label(target_label));
+        self.casm_instructions.extend(target);
+        self.casm_instructions
+            .push(CasmInstruction::Label(end_label));
     }
 
     fn compile_assert_equal(&mut self, expr1: Expr, expr2: Expr) {
         if matches!(expr1.expr_type, ExprType::Identifier) {
-            let ident = expr1.ident.unwrap().token.lexeme;
-            let res = self.local_variables[&ident];
-            let _ = self.compile_expr(expr2);
+            let res = self.lookup(&expr1.ident.unwrap());
+            let (operand, _) = self.compile_operand(expr2);
             self.casm_instructions.push(CasmInstruction::Set {
                 left: Operand::DerefFp(res),
-                op: Operand::DerefAp(-1),
+                op: operand,
                 incr_ap: false,
             });
         } else {
-            panic!("Can't assign to non-identifier");
+            let mut diagnostic = Diagnostic::error(
+                codes::INVALID_ASSIGNMENT,
+                "Can't assign to non-identifier".to_string(),
+            )
+            .with_help("write through a pointer with `[p] = value;`".to_string());
+            if let Some(span) = expr1.span() {
+                diagnostic = diagnostic.with_label(span, "not a local".to_string());
+            }
+            self.diagnostics.push(diagnostic);
         }
     }
 
@@ -289,27 +958,144 @@ impl Compiler {
             InstructionType::AssertEq => {
                 self.compile_assert_equal(instr.args[0].clone(), instr.args[1].clone())
             }
-            _ => todo!(),
+            instruction_type => {
+                self.unsupported(format!("instruction {:?}", instruction_type), None)
+            }
         }
     }
 
+    // the number of locals is patched in by compile_function
     fn compile_alloc_locals(&mut self) {
-        self.casm_instructions
-            .push(CasmInstruction::IncrAp(self.size_of_locals));
+        self.alloc_locals_index = Some(self.casm_instructions.len());
+        self.casm_instructions.push(CasmInstruction::IncrAp(0));
     }
 
     pub fn compile_code_element(&mut self, code_element: CodeElement) {
+        let entered = self.enter_location(code_element.span());
+        self.compile_code_element_inner(code_element);
+        self.leave_location(entered);
+    }
+
+    fn compile_code_element_inner(&mut self, code_element: CodeElement) {
         match code_element {
-            CodeElement::LocalVar(ident, expr) => self.compile_local_var(ident, expr),
+            CodeElement::LocalVar(ident, expr) => self.compile_local_var(ident.ident, expr),
             CodeElement::Return(expr) => self.compile_return(expr),
-            CodeElement::Function(name, args, body) => self.compile_function(name, args, body),
+            CodeElement::Function(function) => self.compile_function(function),
+            CodeElement::Reference(ident, expr) => self.compile_reference(ident.ident, expr),
+            CodeElement::FuncCall(expr) => {
+                let _ = self.compile_function_call(expr);
+            }
+            // directives are handled before compilation, structs only matter to the type checker,
+            // and the uses of constants and the members of namespaces were flattened
+            CodeElement::Directive(_, _)
+            | CodeElement::Struct(..)
+            | CodeElement::Const(..)
+            | CodeElement::NameSpace(..) => {}
             CodeElement::CompoundAssertEqual(expr1, expr2) => {
                 self.compile_compound_assert_equal(expr1, expr2)
             }
             CodeElement::If(expr, body, else_body) => self.compile_if(expr, body, else_body),
             CodeElement::Instruction(instr) => self.compile_instruction(instr),
-            CodeElement::AllocLocals => self.compile_alloc_locals(),
-            _ => todo!(),
+            CodeElement::AllocLocals(_) => self.compile_alloc_locals(),
+            CodeElement::Hint(token) => self.push_hint(hint_code(&token.lexeme)),
+            code_element => {
+                let span = code_element.span();
+                self.unsupported("statement".to_string(), span)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cst;
+
+    // the casm of `source`, without the locations
+    fn lowered(source: &str) -> Vec<String> {
+        let (tokens, _) = cst::tokenize(source);
+        let (_, code_elements, diagnostics) = cst::parse(tokens, source);
+        assert!(diagnostics.is_empty());
+        let mut compiler = Compiler::new(code_elements);
+        let casm = compiler.compile();
+        assert!(
+            compiler.diagnostics.is_empty(),
+            "{:?}",
+            compiler.diagnostics
+        );
+        casm.iter()
+            .filter(|instruction| !matches!(instruction, CasmInstruction::Location(_)))
+            .map(|instruction| format!("{:?}", instruction))
+            .collect()
+    }
+
+    #[test]
+    fn casts_keep_the_value_of_their_operand() {
+        let source = "func main() {\n\
+                let p = cast(nondet %{ 5 %}, felt*);\n\
+                let x = cast(p, felt);\n\
+                return ();\n\
+            }\n";
+        assert_eq!(
+            lowered(source),
+            [
+                "main:",
+                "ap += 2;",
+                "%{ memory[ap] = to_felt_or_relocatable(5) %} scope(__main__.main)",
+                "ap += 1;",
+                "[fp + 0] = [ap + -1];",
+                "[fp + 1] = [fp + 0];",
+                "ret;",
+            ]
+        );
+    }
+
+    #[test]
+    fn operands_are_used_in_place() {
+        // neither x nor 1 is copied to the stack before the addition
+        let source = "func inc(x: felt) -> felt {\n\
+                return x + 1;\n\
+            }\n";
+        assert_eq!(
+            lowered(source),
+            ["inc:", "[ap + 0] = [fp + -3] + 1, ap++;", "ret;"]
+        );
+    }
+
+    #[test]
+    fn values_are_kept_in_locals_across_calls() {
+        // the callee writes over the stack, so x * 2 and g() are read back
+        // from [fp + 0] after the call
+        let source = "func g() -> felt {\n\
+                return 5;\n\
+            }\n\
+            func f(x: felt) -> felt {\n\
+                return x * 2 + g();\n\
+            }\n\
+            func h(x: felt) -> (a: felt, b: felt) {\n\
+                return (a=x * 3, b=g());\n\
+            }\n";
+        assert_eq!(
+            lowered(source),
+            [
+                "g:",
+                "[ap + 0] = 5, ap++;",
+                "ret;",
+                "f:",
+                "ap += 1;",
+                "[ap + 0] = [fp + -3] * 2, ap++;",
+                "[fp + 0] = [ap + -1];",
+                "call g;",
+                "[ap + 0] = [fp + 0] + [ap + -1], ap++;",
+                "ret;",
+                "h:",
+                "ap += 1;",
+                "call g;",
+                "[fp + 0] = [ap + -1];",
+                "[ap + 0] = [fp + -3] * 3, ap++;",
+                "[ap + 0] = [fp + 0], ap++;",
+                "ret;",
+            ]
+        );
+    }
+}
@@ -0,0 +1,889 @@
+// A language server over stdin and stdout: the diagnostics of the whole compiler, go to
+// definition, hover, document symbols and completion. Documents are synced in full.
+
+use crate::ast::*;
+use crate::cst::{self, SyntaxKind, SyntaxTree};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::formatter;
+use crate::lexer::TokenType;
+use crate::{Options, compile_source, stdlib};
+use json::JsonValue;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// SymbolKind and CompletionItemKind of the protocol
+const SYMBOL_NAMESPACE: u8 = 3;
+const SYMBOL_FIELD: u8 = 8;
+const SYMBOL_FUNCTION: u8 = 12;
+const SYMBOL_CONSTANT: u8 = 14;
+const SYMBOL_STRUCT: u8 = 23;
+const COMPLETION_FUNCTION: u8 = 3;
+const COMPLETION_FIELD: u8 = 5;
+const COMPLETION_VARIABLE: u8 = 6;
+const COMPLETION_STRUCT: u8 = 22;
+
+// JSON-RPC error code of unknown requests
+const METHOD_NOT_FOUND: i32 = -32601;
+
+pub fn run() -> Result<(), String> {
+    serve(&mut io::stdin().lock(), &mut io::stdout().lock())
+}
+
+// Answers the messages of `input` until `exit` or the end of the input.
+fn serve(input: &mut impl BufRead, output: &mut impl Write) -> Result<(), String> {
+    let mut server = Server {
+        documents: HashMap::new(),
+        library: stdlib::functions(),
+    };
+    while let Some(message) = read_message(input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            send(output, reply)?;
+        }
+    }
+    Ok(())
+}
+
+// Messages are a Content-Length header, a blank line and a json body.
+fn read_message(input: &mut impl BufRead) -> Result<Option<JsonValue>, String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        let read = input
+            .read_line(&mut line)
+            .map_err(|e| format!("Could not read a message: {}", e))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or("Message without a Content-Length header".to_string())?;
+    let mut body = vec![0; length];
+    input
+        .read_exact(&mut body)
+        .map_err(|e| format!("Could not read a message: {}", e))?;
+    json::parse(&String::from_utf8_lossy(&body))
+        .map(Some)
+        .map_err(|e| format!("Invalid message: {}", e))
+}
+
+fn send(output: &mut impl Write, message: JsonValue) -> Result<(), String> {
+    let body = message.dump();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("Could not send a message: {}", e))
+}
+
+// Positions count lines from 0 and characters in UTF-16 code units.
+fn position(text: &str, offset: usize) -> JsonValue {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json::object! {
+        line: before.matches('\n').count(),
+        character: before[line_start..].encode_utf16().count(),
+    }
+}
+
+fn offset(text: &str, position: &JsonValue) -> usize {
+    let line = position["line"].as_usize().unwrap_or(0);
+    let character = position["character"].as_usize().unwrap_or(0);
+    let line_start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn range(text: &str, span: Span) -> JsonValue {
+    json::object! {
+        start: position(text, span.0),
+        end: position(text, span.1),
+    }
+}
+
+fn markdown(code: &str, description: &str) -> String {
+    format!("```cairo\n{}\n```\n{}", code, description)
+}
+
+// The doc comment of a declaration goes below its description.
+fn with_doc(value: String, doc: &Option<String>) -> String {
+    match doc {
+        Some(doc) => format!("{}\n\n{}", value, doc),
+        None => value,
+    }
+}
+
+fn typed_identifier(identifier: &TypedIdentifier) -> String {
+    match &identifier.type_ {
+        Some(type_) => format!(
+            "{}: {}",
+            identifier.ident.token.lexeme,
+            formatter::type_(type_).unwrap_or("_".to_string())
+        ),
+        None => identifier.ident.token.lexeme.clone(),
+    }
+}
+
+// The struct a value of this type has the members of, through one pointer.
+fn struct_of(type_: &Type) -> Option<&str> {
+    match type_ {
+        Type::Struct(ident) => Some(&ident.token.lexeme),
+        Type::Pointer(inner) | Type::Named(_, inner) => struct_of(inner),
+        _ => None,
+    }
+}
+
+// A name bound in a function.
+struct Binding<'a> {
+    // argument, implicit argument, local or let
+    kind: &'static str,
+    ident: &'a TypedIdentifier,
+}
+
+fn bindings(function: &Function) -> Vec<Binding<'_>> {
+    fn block<'a>(body: &'a [CodeElement], bindings: &mut Vec<Binding<'a>>) {
+        for element in body {
+            match element {
+                CodeElement::LocalVar(ident, _) => bindings.push(Binding {
+                    kind: "local",
+                    ident,
+                }),
+                CodeElement::Reference(ident, _) => bindings.push(Binding { kind: "let", ident }),
+                CodeElement::If(_, body, else_body) => {
+                    block(body, bindings);
+                    block(else_body, bindings);
+                }
+                _ => {}
+            }
+        }
+    }
+    let mut bindings = Vec::new();
+    for ident in function.implicit_args.iter() {
+        bindings.push(Binding {
+            kind: "implicit argument",
+            ident,
+        });
+    }
+    for ident in function.args.iter() {
+        bindings.push(Binding {
+            kind: "argument",
+            ident,
+        });
+    }
+    block(&function.body, &mut bindings);
+    bindings
+}
+
+enum Symbol<'a> {
+    Binding(Binding<'a>, &'a Function),
+    // the function, and whether it comes from the library
+    Function(&'a Function, bool),
+    Struct(&'a Identifier, &'a [TypedIdentifier]),
+    // the struct and the member
+    Member(&'a Identifier, &'a TypedIdentifier),
+    Const(&'a Identifier, &'a Expr, &'a Option<String>),
+    // with its qualified name, which prefixes those of its members
+    Namespace(&'a Identifier, String, &'a Option<String>),
+}
+
+// The name a declaration introduces in its namespace.
+fn declared_name(element: &CodeElement) -> Option<&Identifier> {
+    match element {
+        CodeElement::Function(function) => Some(&function.name),
+        CodeElement::Struct(name, ..)
+        | CodeElement::Const(name, ..)
+        | CodeElement::NameSpace(name, ..) => Some(name),
+        _ => None,
+    }
+}
+
+// What the server knows of an open file. The tree is that of the recovering parser, so
+// names keep resolving while the file has errors.
+struct Document {
+    text: String,
+    tree: SyntaxTree,
+    code_elements: Vec<CodeElement>,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let (tokens, _) = cst::tokenize(&text);
+        let (tree, code_elements, _) = cst::parse(tokens, &text);
+        Self {
+            text,
+            tree,
+            code_elements,
+        }
+    }
+
+    // The declarations of the file with their qualified names, those of a namespace
+    // following it.
+    fn declarations(&self) -> Vec<(String, &CodeElement)> {
+        let mut declarations = Vec::new();
+        let mut pending: Vec<(String, &CodeElement)> = self
+            .code_elements
+            .iter()
+            .rev()
+            .map(|element| (String::new(), element))
+            .collect();
+        while let Some((path, element)) = pending.pop() {
+            let Some(name) = declared_name(element) else {
+                continue;
+            };
+            let qualified = match path.as_str() {
+                "" => name.token.lexeme.clone(),
+                path => format!("{}.{}", path, name.token.lexeme),
+            };
+            if let CodeElement::NameSpace(_, members, _) = element {
+                pending.extend(
+                    members
+                        .iter()
+                        .rev()
+                        .map(|member| (qualified.clone(), member)),
+                );
+            }
+            declarations.push((qualified, element));
+        }
+        declarations
+    }
+
+    // The declaration `name` refers to at `offset`: names are looked up in the enclosing
+    // namespaces, innermost first, then at the top level.
+    fn find_declaration(&self, name: &str, offset: usize) -> Option<(String, &CodeElement)> {
+        let declarations = self.declarations();
+        let scope = declarations
+            .iter()
+            .filter(|(_, element)| match element {
+                CodeElement::NameSpace(ident, ..) => {
+                    let (start, end) = self.extent(ident);
+                    start <= offset && offset <= end
+                }
+                _ => false,
+            })
+            .map(|(qualified, _)| qualified.as_str())
+            .next_back()
+            .unwrap_or_default();
+        let path: Vec<&str> = scope.split('.').filter(|part| !part.is_empty()).collect();
+        (0..=path.len()).rev().find_map(|depth| {
+            let candidate = path[..depth]
+                .iter()
+                .copied()
+                .chain(std::iter::once(name))
+                .collect::<Vec<_>>()
+                .join(".");
+            declarations
+                .iter()
+                .find(|(qualified, _)| *qualified == candidate)
+                .cloned()
+        })
+    }
+
+    // The symbol of a declaration, by its qualified name.
+    fn declaration_symbol<'a>(qualified: String, element: &'a CodeElement) -> Option<Symbol<'a>> {
+        match element {
+            CodeElement::Function(function) => Some(Symbol::Function(function, false)),
+            CodeElement::Struct(name, members, _) => Some(Symbol::Struct(name, members)),
+            CodeElement::Const(name, value, doc) => Some(Symbol::Const(name, value, doc)),
+            CodeElement::NameSpace(name, _, doc) => Some(Symbol::Namespace(name, qualified, doc)),
+            _ => None,
+        }
+    }
+
+    fn functions(&self) -> impl Iterator<Item = &Function> {
+        self.declarations()
+            .into_iter()
+            .filter_map(|(_, element)| match element {
+                CodeElement::Function(function) => Some(function),
+                _ => None,
+            })
+    }
+
+    fn structs(&self) -> impl Iterator<Item = (&Identifier, &[TypedIdentifier])> {
+        self.declarations()
+            .into_iter()
+            .filter_map(|(_, element)| match element {
+                CodeElement::Struct(name, members, _) => Some((name, members.as_slice())),
+                _ => None,
+            })
+    }
+
+    fn find_struct(&self, name: &str, offset: usize) -> Option<(&Identifier, &[TypedIdentifier])> {
+        match self.find_declaration(name, offset)? {
+            (_, CodeElement::Struct(ident, members, _)) => Some((ident, members.as_slice())),
+            _ => None,
+        }
+    }
+
+    // The function whose code is at `offset`: functions run until the next one starts.
+    fn function_at(&self, offset: usize) -> Option<&Function> {
+        self.functions()
+            .take_while(|function| function.name.token.span.0 <= offset)
+            .last()
+    }
+
+    // Span of the code of a declaration, from its first token to its closing brace.
+    fn extent(&self, name: &Identifier) -> Span {
+        let span = name.token.span;
+        let mut extent = span;
+        let mut nodes = self.tree.nodes.as_slice();
+        // the members of a namespace are the children of its node
+        while let Some(node) = nodes.iter().find(|node| {
+            let (start, end) = self.tree.span(node);
+            start <= span.0 && span.1 <= end
+        }) {
+            extent = self.tree.span(node);
+            if node.kind != SyntaxKind::Namespace {
+                break;
+            }
+            nodes = &node.children;
+        }
+        extent
+    }
+
+    // Functions, structs, constants and namespaces, with the members of structs and
+    // namespaces as children.
+    fn symbols(&self, code_elements: &[CodeElement]) -> JsonValue {
+        let text = self.text.as_str();
+        let mut symbols = JsonValue::new_array();
+        for element in code_elements.iter() {
+            let symbol = match element {
+                CodeElement::Function(function) => json::object! {
+                    name: function.name.token.lexeme.clone(),
+                    detail: formatter::signature(function, "")
+                        .unwrap_or_default()
+                        .trim_end_matches(" {"),
+                    kind: SYMBOL_FUNCTION,
+                    range: range(text, self.extent(&function.name)),
+                    selectionRange: range(text, function.name.token.span),
+                },
+                CodeElement::Struct(name, members, _) => {
+                    let children: Vec<JsonValue> = members
+                        .iter()
+                        .map(|member| {
+                            json::object! {
+                                name: member.ident.token.lexeme.clone(),
+                                detail: typed_identifier(member),
+                                kind: SYMBOL_FIELD,
+                                range: range(text, member.ident.token.span),
+                                selectionRange: range(text, member.ident.token.span),
+                            }
+                        })
+                        .collect();
+                    json::object! {
+                        name: name.token.lexeme.clone(),
+                        kind: SYMBOL_STRUCT,
+                        range: range(text, self.extent(name)),
+                        selectionRange: range(text, name.token.span),
+                        children: children,
+                    }
+                }
+                CodeElement::Const(name, value, _) => json::object! {
+                    name: name.token.lexeme.clone(),
+                    detail: formatter::expression(value).unwrap_or_default(),
+                    kind: SYMBOL_CONSTANT,
+                    range: range(text, self.extent(name)),
+                    selectionRange: range(text, name.token.span),
+                },
+                CodeElement::NameSpace(name, members, _) => json::object! {
+                    name: name.token.lexeme.clone(),
+                    kind: SYMBOL_NAMESPACE,
+                    range: range(text, self.extent(name)),
+                    selectionRange: range(text, name.token.span),
+                    children: self.symbols(members),
+                },
+                _ => continue,
+            };
+            let _ = symbols.push(symbol);
+        }
+        symbols
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        library: &'a [Function],
+        name: &str,
+        offset: usize,
+    ) -> Option<Symbol<'a>> {
+        if let Some(function) = self.function_at(offset)
+            && let Some(binding) = bindings(function).into_iter().rev().find(|binding| {
+                binding.ident.ident.token.lexeme == name
+                    && binding.ident.ident.token.span.0 <= offset
+            })
+        {
+            return Some(Symbol::Binding(binding, function));
+        }
+        if let Some((qualified, element)) = self.find_declaration(name, offset) {
+            return Self::declaration_symbol(qualified, element);
+        }
+        library
+            .iter()
+            .find(|function| function.name.token.lexeme == name)
+            .map(|function| Symbol::Function(function, true))
+    }
+
+    // The struct whose members follow `symbol.`, if any.
+    fn members_of<'a>(
+        &'a self,
+        symbol: &Symbol<'a>,
+        offset: usize,
+    ) -> Option<(&'a Identifier, &'a [TypedIdentifier])> {
+        let type_ = match symbol {
+            Symbol::Binding(binding, _) => binding.ident.type_.as_ref()?,
+            Symbol::Member(_, member) => member.type_.as_ref()?,
+            _ => return None,
+        };
+        self.find_struct(struct_of(type_)?, offset)
+    }
+
+    // The symbol under the cursor, and the span of its name there. In `p.x.y` each part is
+    // its own symbol, members being looked up in the declared type of what precedes them,
+    // and in `m.f` in the namespace `m`.
+    fn symbol_at<'a>(
+        &'a self,
+        library: &'a [Function],
+        offset: usize,
+    ) -> Option<(Symbol<'a>, Span)> {
+        let token = self.tree.code_tokens().find(|token| {
+            token.token_type == TokenType::Identifier
+                && token.span.0 <= offset
+                && offset <= token.span.1
+        })?;
+        let mut parts = Vec::new();
+        let mut start = token.span.0;
+        for part in token.lexeme.split('.') {
+            parts.push((part, (start, start + part.len())));
+            start += part.len() + 1;
+        }
+        let index = parts
+            .iter()
+            .position(|(_, span)| offset <= span.1)
+            .unwrap_or(parts.len() - 1);
+        let (name, span) = parts[0];
+        let mut symbol = (self.lookup(library, name, offset)?, span);
+        for &(member, span) in parts[1..=index].iter() {
+            if let Symbol::Namespace(_, qualified, _) = &symbol.0 {
+                let qualified = format!("{}.{}", qualified, member);
+                let (_, element) = self
+                    .declarations()
+                    .into_iter()
+                    .find(|(candidate, _)| *candidate == qualified)?;
+                symbol = (Self::declaration_symbol(qualified, element)?, span);
+                continue;
+            }
+            let (def, members) = self.members_of(&symbol.0, offset)?;
+            let member = members
+                .iter()
+                .find(|candidate| candidate.ident.token.lexeme == member)?;
+            symbol = (Symbol::Member(def, member), span);
+        }
+        Some(symbol)
+    }
+}
+
+struct Server {
+    documents: HashMap<String, Document>,
+    library: Vec<Function>,
+}
+
+impl Server {
+    // Replies to a message: a response to requests, diagnostics after changes.
+    fn handle(&mut self, message: &JsonValue) -> Vec<JsonValue> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json::object! {
+                capabilities: {
+                    textDocumentSync: 1,
+                    definitionProvider: true,
+                    hoverProvider: true,
+                    documentSymbolProvider: true,
+                    completionProvider: { triggerCharacters: ["."] },
+                },
+                serverInfo: { name: "czc", version: env!("CARGO_PKG_VERSION") },
+            },
+            "shutdown" => JsonValue::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents
+                    .insert(uri.clone(), Document::new(text.to_string()));
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didChange" => {
+                // full sync: the last change holds the whole text
+                let changes = &params["contentChanges"];
+                if let Some(text) = changes[changes.len().saturating_sub(1)]["text"].as_str() {
+                    self.documents
+                        .insert(uri.clone(), Document::new(text.to_string()));
+                }
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![json::object! {
+                    jsonrpc: "2.0",
+                    method: "textDocument/publishDiagnostics",
+                    params: { uri: uri, diagnostics: [] },
+                }];
+            }
+            "textDocument/definition" => self.definition(&uri, &params["position"]),
+            "textDocument/hover" => self.hover(&uri, &params["position"]),
+            "textDocument/documentSymbol" => self.document_symbols(&uri),
+            "textDocument/completion" => self.completion(&uri, &params["position"]),
+            method => {
+                // notifications nobody handles are ignored
+                if message["id"].is_null() {
+                    return Vec::new();
+                }
+                return vec![json::object! {
+                    jsonrpc: "2.0",
+                    id: message["id"].clone(),
+                    error: {
+                        code: METHOD_NOT_FOUND,
+                        message: format!("Unknown method {}", method),
+                    },
+                }];
+            }
+        };
+        if message["id"].is_null() {
+            return Vec::new();
+        }
+        vec![json::object! {
+            jsonrpc: "2.0",
+            id: message["id"].clone(),
+            result: result,
+        }]
+    }
+
+    // Everything the compiler reports: syntax, lints, names, types and lowering.
+    fn diagnostics(&self, uri: &str) -> JsonValue {
+        let Some(document) = self.documents.get(uri) else {
+            return JsonValue::Null;
+        };
+        let text = document.text.as_str();
+        let options = Options {
+            file_name: uri.to_string(),
+            ..Options::default()
+        };
+        let diagnostics = match compile_source(text, options) {
+            Ok(compiled) => compiled.warnings().to_vec(),
+            Err(diagnostics) => diagnostics.0,
+        };
+        let diagnostics: Vec<JsonValue> = diagnostics
+            .iter()
+            .map(|diagnostic| self.diagnostic(uri, text, diagnostic))
+            .collect();
+        json::object! {
+            jsonrpc: "2.0",
+            method: "textDocument/publishDiagnostics",
+            params: { uri: uri, diagnostics: diagnostics },
+        }
+    }
+
+    fn diagnostic(&self, uri: &str, text: &str, diagnostic: &Diagnostic) -> JsonValue {
+        let mut message = diagnostic.message.clone();
+        for note in diagnostic.notes.iter() {
+            message.push_str(&format!("\nnote: {}", note));
+        }
+        if let Some(help) = &diagnostic.help {
+            message.push_str(&format!("\nhelp: {}", help));
+        }
+        let related: Vec<JsonValue> = diagnostic
+            .secondary
+            .iter()
+            .map(|(span, label)| {
+                json::object! {
+                    location: { uri: uri, range: range(text, *span) },
+                    message: label.clone(),
+                }
+            })
+            .collect();
+        json::object! {
+            range: range(text, diagnostic.span.unwrap_or((0, 0))),
+            severity: match diagnostic.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            },
+            code: diagnostic.code.clone(),
+            source: "czc",
+            message: message,
+            relatedInformation: related,
+        }
+    }
+
+    fn definition(&self, uri: &str, position: &JsonValue) -> JsonValue {
+        let Some(document) = self.documents.get(uri) else {
+            return JsonValue::Null;
+        };
+        let offset = offset(&document.text, position);
+        let Some((symbol, _)) = document.symbol_at(&self.library, offset) else {
+            return JsonValue::Null;
+        };
+        let span = match symbol {
+            Symbol::Binding(binding, _) => binding.ident.ident.token.span,
+            Symbol::Function(function, false) => function.name.token.span,
+            // library functions have no file to go to
+            Symbol::Function(_, true) => return JsonValue::Null,
+            Symbol::Struct(ident, _) => ident.token.span,
+            Symbol::Member(_, member) => member.ident.token.span,
+            Symbol::Const(ident, ..) | Symbol::Namespace(ident, ..) => ident.token.span,
+        };
+        json::object! { uri: uri, range: range(&document.text, span) }
+    }
+
+    fn hover(&self, uri: &str, position: &JsonValue) -> JsonValue {
+        let Some(document) = self.documents.get(uri) else {
+            return JsonValue::Null;
+        };
+        let offset = offset(&document.text, position);
+        let Some((symbol, span)) = document.symbol_at(&self.library, offset) else {
+            return JsonValue::Null;
+        };
+        let value = match symbol {
+            Symbol::Binding(binding, function) => {
+                let code = match binding.kind {
+                    "local" | "let" => {
+                        format!("{} {}", binding.kind, typed_identifier(binding.ident))
+                    }
+                    _ => typed_identifier(binding.ident),
+                };
+                markdown(
+                    &code,
+                    &format!("{} of '{}'", binding.kind, function.name.token.lexeme),
+                )
+            }
+            Symbol::Function(function, library) => {
+                let signature = formatter::signature(function, "").unwrap_or_default();
+                let signature = signature.trim_end_matches(" {");
+                let value = markdown(
+                    signature,
+                    if library {
+                        "library function"
+                    } else {
+                        "function"
+                    },
+                );
+                with_doc(value, &function.doc)
+            }
+            Symbol::Struct(ident, members) => {
+                let mut code = format!("struct {} {{\n", ident.token.lexeme);
+                for member in members {
+                    code.push_str(&format!("    {},\n", typed_identifier(member)));
+                }
+                code.push('}');
+                let doc =
+                    document
+                        .declarations()
+                        .into_iter()
+                        .find_map(|(_, element)| match element {
+                            CodeElement::Struct(name, _, doc)
+                                if name.token.span == ident.token.span =>
+                            {
+                                doc.clone()
+                            }
+                            _ => None,
+                        });
+                with_doc(markdown(&code, "struct"), &doc)
+            }
+            Symbol::Member(def, member) => markdown(
+                &typed_identifier(member),
+                &format!("member of '{}'", def.token.lexeme),
+            ),
+            Symbol::Const(ident, value, doc) => {
+                let code = format!(
+                    "const {} = {}",
+                    ident.token.lexeme,
+                    formatter::expression(value).unwrap_or_default()
+                );
+                with_doc(markdown(&code, "constant"), doc)
+            }
+            Symbol::Namespace(_, qualified, doc) => with_doc(
+                markdown(&format!("namespace {}", qualified), "namespace"),
+                doc,
+            ),
+        };
+        json::object! {
+            contents: { kind: "markdown", value: value },
+            range: range(&document.text, span),
+        }
+    }
+
+    fn document_symbols(&self, uri: &str) -> JsonValue {
+        let Some(document) = self.documents.get(uri) else {
+            return JsonValue::Null;
+        };
+        document.symbols(&document.code_elements)
+    }
+
+    // Members after `p.`, otherwise the names in scope, the functions and the structs.
+    fn completion(&self, uri: &str, position: &JsonValue) -> JsonValue {
+        let Some(document) = self.documents.get(uri) else {
+            return JsonValue::Null;
+        };
+        let text = document.text.as_str();
+        let offset = offset(text, position);
+        let word_start = text[..offset]
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .map_or(0, |i| i + 1);
+        let word = &text[word_start..offset];
+
+        let mut items = JsonValue::new_array();
+        let mut item = |label: &str, kind: u8, detail: String| {
+            let _ = items.push(json::object! { label: label, kind: kind, detail: detail });
+        };
+        if let Some((path, _)) = word.rsplit_once('.') {
+            let mut parts = path.split('.');
+            let mut symbol = parts
+                .next()
+                .and_then(|name| document.lookup(&self.library, name, offset));
+            for member in parts {
+                symbol = symbol
+                    .as_ref()
+                    .and_then(|symbol| document.members_of(symbol, offset))
+                    .and_then(|(def, members)| {
+                        members
+                            .iter()
+                            .find(|candidate| candidate.ident.token.lexeme == member)
+                            .map(|member| Symbol::Member(def, member))
+                    });
+            }
+            if let Some((_, members)) =
+                symbol.and_then(|symbol| document.members_of(&symbol, offset))
+            {
+                for member in members {
+                    item(
+                        &member.ident.token.lexeme,
+                        COMPLETION_FIELD,
+                        typed_identifier(member),
+                    );
+                }
+            }
+            return items;
+        }
+
+        if let Some(function) = document.function_at(offset) {
+            let mut seen = Vec::new();
+            // the latest binding of a name is the one in scope
+            for binding in bindings(function).iter().rev() {
+                let name = &binding.ident.ident.token.lexeme;
+                if binding.ident.ident.token.span.0 < offset && !seen.contains(name) {
+                    seen.push(name.clone());
+                    item(name, COMPLETION_VARIABLE, typed_identifier(binding.ident));
+                }
+            }
+        }
+        let library = self.library.iter().filter(|function| {
+            !document
+                .functions()
+                .any(|other| other.name.token.lexeme == function.name.token.lexeme)
+        });
+        for function in document.functions().chain(library) {
+            let signature = formatter::signature(function, "").unwrap_or_default();
+            item(
+                &function.name.token.lexeme,
+                COMPLETION_FUNCTION,
+                signature.trim_end_matches(" {").to_string(),
+            );
+        }
+        for (name, _) in document.structs() {
+            item(&name.token.lexeme, COMPLETION_STRUCT, "struct".to_string());
+        }
+        items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The replies of the server to a session of requests on one document.
+    fn session(text: &str, requests: Vec<JsonValue>) -> Vec<JsonValue> {
+        let uri = "file:///main.cairo";
+        let mut messages = vec![
+            json::object! { jsonrpc: "2.0", id: 0, method: "initialize", params: {} },
+            json::object! {
+                jsonrpc: "2.0",
+                method: "textDocument/didOpen",
+                params: { textDocument: { uri: uri, languageId: "cairo", version: 1, text: text } },
+            },
+        ];
+        for (id, mut request) in requests.into_iter().enumerate() {
+            request["jsonrpc"] = "2.0".into();
+            request["id"] = (id + 1).into();
+            request["params"]["textDocument"] = json::object! { uri: uri };
+            messages.push(request);
+        }
+        messages.push(json::object! { jsonrpc: "2.0", method: "exit" });
+
+        let mut input = Vec::new();
+        for message in messages {
+            send(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(&mut input.as_slice(), &mut output).unwrap();
+        let mut output = output.as_slice();
+        let mut replies = Vec::new();
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    fn at(text: &str, needle: &str, delta: usize) -> JsonValue {
+        position(text, text.find(needle).unwrap() + delta)
+    }
+
+    #[test]
+    fn qualified_names_resolve_to_namespace_members() {
+        let text = "namespace m {\n    \
+                // Doubles.\n    \
+                func g(x: felt) -> felt {\n        \
+                    return x * 2;\n    \
+                }\n\
+            }\n\
+            func main() {\n    \
+                let y = m.g(1);\n    \
+                return ();\n\
+            }\n";
+        let g = at(text, "m.g(1)", 2);
+        let replies = session(
+            text,
+            vec![
+                json::object! { method: "textDocument/definition", params: { position: g.clone() } },
+                json::object! { method: "textDocument/hover", params: { position: g } },
+                json::object! {
+                    method: "textDocument/hover",
+                    params: { position: at(text, "m.g(1)", 0) },
+                },
+            ],
+        );
+        // the initialize response, the diagnostics of didOpen and one reply per request
+        assert_eq!(replies.len(), 5);
+        assert_eq!(replies[0]["result"]["serverInfo"]["name"], "czc");
+        assert_eq!(replies[1]["params"]["diagnostics"].len(), 0);
+        let declaration = text.find("g(x").unwrap();
+        assert_eq!(
+            replies[2]["result"]["range"],
+            range(text, (declaration, declaration + 1))
+        );
+        let hover = replies[3]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains("func g(x: felt) -> felt"), "{}", hover);
+        assert!(hover.contains("Doubles."), "{}", hover);
+        let hover = replies[4]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains("namespace m"), "{}", hover);
+    }
+}
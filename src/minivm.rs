@@ -1,152 +1,841 @@
+use crate::assembler::Assembler;
+use crate::builtins::{BuiltinKind, BuiltinRunner, ValidationRule};
+use crate::casm::Hint;
+use crate::felt::{Felt, Field};
+use crate::hints::HintProcessor;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Relocatable {
+    pub segment: usize,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Int(Felt),
+    Ptr(Relocatable),
+}
+
+impl Relocatable {
+    pub fn new(segment: usize, offset: u64) -> Self {
+        Self { segment, offset }
+    }
+
+    pub fn add(&self, n: i64) -> Result<Relocatable, String> {
+        let offset = self.offset as i64 + n;
+        if offset < 0 {
+            return Err(format!("Negative offset when adding {} to {}", n, self));
+        }
+        Ok(Relocatable::new(self.segment, offset as u64))
+    }
+}
+
+impl Display for Relocatable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.segment, self.offset)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Ptr(ptr) => write!(f, "{}", ptr),
+        }
+    }
+}
+
+// Field arithmetic modulo the prime, relocatable values follow the usual cairo rules:
+// ptr + int, int + ptr and ptr - ptr (same segment) are allowed.
+impl Value {
+    pub fn add(&self, other: &Value, field: &Field) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(field.add(a, b))),
+            (Value::Ptr(p), Value::Int(n)) | (Value::Int(n), Value::Ptr(p)) => {
+                Ok(Value::Ptr(p.add(signed(field, n)?)?))
+            }
+            _ => Err(format!(
+                "Cannot add two relocatable values {} and {}",
+                self, other
+            )),
+        }
+    }
+
+    pub fn sub(&self, other: &Value, field: &Field) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(field.sub(a, b))),
+            (Value::Ptr(p), Value::Int(n)) => Ok(Value::Ptr(p.add(-signed(field, n)?)?)),
+            (Value::Ptr(a), Value::Ptr(b)) if a.segment == b.segment => Ok(Value::Int(
+                field.sub(&Felt::from(a.offset), &Felt::from(b.offset)),
+            )),
+            _ => Err(format!("Cannot subtract {} from {}", other, self)),
+        }
+    }
+
+    pub fn mul(&self, other: &Value, field: &Field) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(field.mul(a, b))),
+            _ => Err(format!("Cannot multiply {} by {}", self, other)),
+        }
+    }
+
+    pub fn div(&self, other: &Value, field: &Field) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Int(_), Value::Int(b)) if field.reduce(b).is_zero() => {
+                Err("Division by zero".to_string())
+            }
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(field.mul(a, &field.inv(b)))),
+            _ => Err(format!("Cannot divide {} by {}", self, other)),
+        }
+    }
+
+    pub fn is_zero(&self, field: &Field) -> bool {
+        matches!(self, Value::Int(n) if field.reduce(n).is_zero())
+    }
+}
+
+// Why the vm stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmErrorKind {
+    // the word at pc is not an instruction
+    InvalidInstruction(String),
+    // dst and res of an assert_eq differ
+    AssertEqFailed(Value, Value),
+    // a cell is written twice with different values
+    InconsistentMemory(Relocatable, Value, Value),
+    // an operand is read from an unknown cell, with the name of the operand
+    UnknownMemory(Relocatable, &'static str),
+    StepLimit(u64),
+    // a builtin rejected a value or could not compute a cell
+    Builtin(String),
+    Hint(String),
+    Other(String),
+}
+
+impl From<String> for VmErrorKind {
+    fn from(message: String) -> Self {
+        VmErrorKind::Other(message)
+    }
+}
+
+impl Display for VmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmErrorKind::InvalidInstruction(message) => write!(f, "{}", message),
+            VmErrorKind::AssertEqFailed(dst, res) => {
+                write!(f, "An ASSERT_EQ instruction failed: {} != {}", dst, res)
+            }
+            VmErrorKind::InconsistentMemory(addr, old, new) => write!(
+                f,
+                "Inconsistent memory assignment at {}: {} != {}",
+                addr, old, new
+            ),
+            VmErrorKind::UnknownMemory(addr, operand) => {
+                write!(f, "Unknown memory cell {} ({})", addr, operand)
+            }
+            VmErrorKind::StepLimit(steps) => write!(f, "Step limit of {} reached", steps),
+            VmErrorKind::Builtin(message) => write!(f, "{}", message),
+            VmErrorKind::Hint(message) => write!(f, "Error in hint: {}", message),
+            VmErrorKind::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// An error raised while executing the instruction at pc.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmError {
+    pub pc: Relocatable,
+    pub kind: VmErrorKind,
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at pc {}", self.kind, self.pc)
+    }
+}
+
+impl std::error::Error for VmError {}
+
+// Field element used as a memory offset, values above prime / 2 are negative.
+pub fn signed(field: &Field, n: &Felt) -> Result<i64, String> {
+    field
+        .signed(n)
+        .ok_or(format!("{} is too large to be used as an offset", n))
+}
+
+#[derive(Default)]
+pub struct Memory {
+    pub segments: Vec<Vec<Option<Value>>>,
+    pub validation_rules: HashMap<usize, ValidationRule>,
+}
+
+impl Memory {
+    pub fn add_segment(&mut self) -> Relocatable {
+        self.segments.push(Vec::new());
+        Relocatable::new(self.segments.len() - 1, 0)
+    }
+
+    pub fn get(&self, addr: Relocatable) -> Option<Value> {
+        self.segments
+            .get(addr.segment)
+            .and_then(|segment| segment.get(addr.offset as usize))
+            .and_then(|value| *value)
+    }
+
+    pub fn insert(&mut self, addr: Relocatable, value: Value) -> Result<(), VmErrorKind> {
+        let segment = self
+            .segments
+            .get_mut(addr.segment)
+            .ok_or(format!("Unknown segment {}", addr.segment))?;
+        let offset = addr.offset as usize;
+        if segment.len() <= offset {
+            segment.resize(offset + 1, None);
+        }
+        match segment[offset] {
+            Some(old) if old != value => Err(VmErrorKind::InconsistentMemory(addr, old, value)),
+            Some(_) => Ok(()),
+            None => {
+                segment[offset] = Some(value);
+                match self.validation_rules.get(&addr.segment) {
+                    Some(rule) => rule(addr, value).map_err(VmErrorKind::Builtin),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+// A compiled program, ready to be loaded in the vm.
+#[derive(Clone)]
+pub struct Program {
+    pub data: Vec<Felt>,
+    pub hints: BTreeMap<u64, Vec<Hint>>,
+    pub builtins: Vec<String>,
+    pub main: u64,
+    pub field: Field,
+}
+
+impl Program {
+    // The assembled program, started from the function named `entry`.
+    pub fn from_assembler(assembler: &Assembler, entry: &str) -> Result<Self, String> {
+        let main = *assembler
+            .function_adresses
+            .get(entry)
+            .ok_or(format!("The program has no {} function", entry))?;
+        let mut data = Vec::new();
+        for instruction in assembler.instructions.iter() {
+            let (bytes, imm) = instruction.to_bytes();
+            data.push(Felt::from(bytes));
+            if let Some(imm) = imm {
+                data.push(imm);
+            }
+        }
+        Ok(Self {
+            data,
+            hints: assembler.hints.clone(),
+            builtins: assembler.builtins.clone(),
+            main,
+            field: assembler.field,
+        })
+    }
+
+    // Loads a program from the json written by `Assembler::to_json`.
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let json = json::parse(text).map_err(|e| format!("Invalid program: {}", e))?;
+        let field = Field::from_name(json["prime"].as_str().ok_or("Missing prime")?)?;
+        let data = json["data"]
+            .members()
+            .map(|word| {
+                word.as_str()
+                    .ok_or("Invalid data word".to_string())?
+                    .parse::<Felt>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let builtins = json["builtins"]
+            .members()
+            .map(|name| name.as_str().map(str::to_string))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("Invalid builtins")?;
+        let main = json["identifiers"]["__main__.main"]["pc"]
+            .as_u64()
+            .ok_or("The program has no main function")?;
+
+        let references = &json["reference_manager"]["references"];
+        let mut hints = BTreeMap::new();
+        for (pc, hint_list) in json["hints"].entries() {
+            let pc: u64 = pc.parse().map_err(|_| format!("Invalid hint pc {}", pc))?;
+            let mut parsed = Vec::new();
+            for hint in hint_list.members() {
+                let mut hint_references = Vec::new();
+                for (name, id) in hint["flow_tracking_data"]["reference_ids"].entries() {
+                    let value = id
+                        .as_usize()
+                        .and_then(|id| references[id]["value"].as_str())
+                        .ok_or(format!("Invalid reference {}", name))?;
+                    let offset = crate::assembler::parse_fp_reference(value)
+                        .ok_or(format!("Unsupported reference {}", value))?;
+                    let name = name.rsplit('.').next().unwrap_or(name);
+                    hint_references.push((name.to_string(), offset));
+                }
+                parsed.push(Hint {
+                    code: hint["code"].as_str().ok_or("Invalid hint")?.to_string(),
+                    accessible_scopes: hint["accessible_scopes"]
+                        .members()
+                        .filter_map(|scope| scope.as_str().map(str::to_string))
+                        .collect(),
+                    references: hint_references,
+                });
+            }
+            hints.insert(pc, parsed);
+        }
+        Ok(Self {
+            data,
+            hints,
+            builtins,
+            main,
+            field,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Register {
+    Ap,
+    Fp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op1Src {
+    Op0,
+    Imm,
+    Fp,
+    Ap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ResLogic {
+    Op1,
+    Add,
+    Mul,
+    Unconstrained,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PcUpdate {
+    Regular,
+    Jump,
+    JumpRel,
+    Jnz,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ApUpdate {
+    Regular,
+    Add,
+    Add1,
+    Add2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Opcode {
+    Nop,
+    Call,
+    Ret,
+    AssertEq,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Decoded {
+    off_dst: i64,
+    off_op0: i64,
+    off_op1: i64,
+    dst_reg: Register,
+    op0_reg: Register,
+    op1_src: Op1Src,
+    res: ResLogic,
+    pc_update: PcUpdate,
+    ap_update: ApUpdate,
+    opcode: Opcode,
+}
+
+impl Decoded {
+    fn size(&self) -> u64 {
+        if self.op1_src == Op1Src::Imm { 2 } else { 1 }
+    }
+}
+
+fn decode(word: u64) -> Result<Decoded, String> {
+    let offset = |shift: u32| ((word >> shift) & 0xffff) as i64 - 0x8000;
+    let flags = word >> 48;
+    if flags >> 15 != 0 {
+        return Err(format!("Invalid instruction {:#x}", word));
+    }
+    let register = |bit: u64| if bit == 0 { Register::Ap } else { Register::Fp };
+    let op1_src = match (flags >> 2) & 7 {
+        0 => Op1Src::Op0,
+        1 => Op1Src::Imm,
+        2 => Op1Src::Fp,
+        4 => Op1Src::Ap,
+        _ => return Err(format!("Invalid op1 source in {:#x}", word)),
+    };
+    let pc_update = match (flags >> 7) & 7 {
+        0 => PcUpdate::Regular,
+        1 => PcUpdate::Jump,
+        2 => PcUpdate::JumpRel,
+        4 => PcUpdate::Jnz,
+        _ => return Err(format!("Invalid pc update in {:#x}", word)),
+    };
+    let res = match ((flags >> 5) & 3, pc_update) {
+        (0, PcUpdate::Jnz) => ResLogic::Unconstrained,
+        (0, _) => ResLogic::Op1,
+        (1, _) => ResLogic::Add,
+        (2, _) => ResLogic::Mul,
+        _ => return Err(format!("Invalid res logic in {:#x}", word)),
+    };
+    let opcode = match (flags >> 12) & 7 {
+        0 => Opcode::Nop,
+        1 => Opcode::Call,
+        2 => Opcode::Ret,
+        4 => Opcode::AssertEq,
+        _ => return Err(format!("Invalid opcode in {:#x}", word)),
+    };
+    let ap_update = match ((flags >> 10) & 3, opcode) {
+        (0, Opcode::Call) => ApUpdate::Add2,
+        (0, _) => ApUpdate::Regular,
+        (1, _) => ApUpdate::Add,
+        (2, _) => ApUpdate::Add1,
+        _ => return Err(format!("Invalid ap update in {:#x}", word)),
+    };
+    Ok(Decoded {
+        off_dst: offset(0),
+        off_op0: offset(16),
+        off_op1: offset(32),
+        dst_reg: register(flags & 1),
+        op0_reg: register((flags >> 1) & 1),
+        op1_src,
+        res,
+        pc_update,
+        ap_update,
+        opcode,
+    })
+}
+
+// Registers at the start of a step.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEntry<T> {
+    pub pc: T,
+    pub ap: T,
+    pub fp: T,
+}
+
 pub struct MiniVm {
-    pub pc: u32,
-    pub fp: u32,
-    pub mem: Vec<u32>,
-    pub exit: bool,
-    pub program_size: u32,
+    pub pc: Relocatable,
+    pub ap: Relocatable,
+    pub fp: Relocatable,
+    pub memory: Memory,
+    pub hints: BTreeMap<u64, Vec<Hint>>,
+    pub program_base: Relocatable,
+    pub execution_base: Relocatable,
+    pub end: Relocatable,
+    pub builtins: Vec<BuiltinRunner>,
+    pub steps: u64,
+    // run fails once this many steps were executed
+    pub max_steps: Option<u64>,
+    pub trace: Vec<TraceEntry<Relocatable>>,
+    pub field: Field,
+}
+
+impl Default for MiniVm {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MiniVm {
     pub fn new() -> Self {
         Self {
-            pc: 0,
-            fp: 0,
-            mem: vec![0; 64],
-            exit: false,
-            program_size: 0,
+            pc: Relocatable::new(0, 0),
+            ap: Relocatable::new(1, 0),
+            fp: Relocatable::new(1, 0),
+            memory: Memory::default(),
+            hints: BTreeMap::new(),
+            program_base: Relocatable::new(0, 0),
+            execution_base: Relocatable::new(1, 0),
+            end: Relocatable::new(0, 0),
+            builtins: Vec::new(),
+            steps: 0,
+            max_steps: None,
+            trace: Vec::new(),
+            field: Field::M31,
         }
     }
 
-    pub fn load_program(&mut self, program: Vec<u32>) {
-        // Copy program to beginning of memory
-        for (i, &instruction) in program.iter().enumerate() {
-            self.mem[i] = instruction;
+    // Loads the program in segment 0 and sets up the initial frame of `main`:
+    // the builtin pointers are pushed as its implicit arguments, then `args` as its
+    // explicit ones, followed by the return fp and the return pc (the start of an
+    // empty segment), so that the final `ret` jumps to `end`.
+    pub fn load_program(&mut self, program: &Program, args: &[Felt]) -> Result<(), String> {
+        self.memory = Memory::default();
+        self.field = program.field;
+        self.program_base = self.memory.add_segment();
+        self.execution_base = self.memory.add_segment();
+        for (i, word) in program.data.iter().enumerate() {
+            self.memory
+                .insert(self.program_base.add(i as i64)?, Value::Int(*word))
+                .map_err(|error| error.to_string())?;
+        }
+        self.hints = program.hints.clone();
+        self.builtins.clear();
+        let mut stack = Vec::new();
+        for name in program.builtins.iter() {
+            let kind = BuiltinKind::from_name(name).ok_or(format!("Unknown builtin {}", name))?;
+            if kind == BuiltinKind::Poseidon && self.field != Field::M31 {
+                return Err("The poseidon builtin is only defined over M31".to_string());
+            }
+            let runner = BuiltinRunner::new(kind, self.memory.add_segment());
+            if let Some(rule) = runner.validation_rule(&self.field) {
+                self.memory
+                    .validation_rules
+                    .insert(runner.base.segment, rule);
+            }
+            stack.push(Value::Ptr(runner.base));
+            self.builtins.push(runner);
         }
-        self.program_size = program.len() as u32;
-        self.fp = self.program_size;
+        stack.extend(args.iter().map(|arg| Value::Int(self.field.reduce(arg))));
+        let return_fp = self.memory.add_segment();
+        self.end = self.memory.add_segment();
+        stack.push(Value::Ptr(return_fp));
+        stack.push(Value::Ptr(self.end));
+        for (i, value) in stack.iter().enumerate() {
+            self.memory
+                .insert(self.execution_base.add(i as i64)?, *value)
+                .map_err(|error| error.to_string())?;
+        }
+        self.ap = self.execution_base.add(stack.len() as i64)?;
+        self.fp = self.ap;
+        self.pc = self.program_base.add(program.main as i64)?;
+        self.steps = 0;
+        self.trace.clear();
+        Ok(())
     }
 
-    pub fn run(&mut self) {
-        while !self.exit {
-            let instr = self.mem[self.pc as usize];
-            match instr {
-                0 => self.mov_fp_fp(),
-                1 => self.mov_fp_imm(),
-                2 => self.add_fp_fp(),
-                3 => self.add_fp_imm(),
-                4 => self.sub_fp_fp(),
-                5 => self.sub_fp_imm(),
-                6 => self.mul_fp_fp(),
-                7 => self.mul_fp_imm(),
-                8 => self.jmp(),
-                9 => self.jnz(),
-                10 => self.call(),
-                11 => self.ret(),
-                _ => panic!("Unknown instruction: {}", instr),
+    pub fn run(&mut self, hint_processor: &mut dyn HintProcessor) -> Result<(), VmError> {
+        while self.pc != self.end {
+            if let Some(max_steps) = self.max_steps
+                && self.steps >= max_steps
+            {
+                return Err(VmError {
+                    pc: self.pc,
+                    kind: VmErrorKind::StepLimit(max_steps),
+                });
             }
-            self.pc += 4;
+            self.step(hint_processor)?;
         }
+        Ok(())
     }
 
-    fn mov_fp_fp(&mut self) {
-        let offdst = self.mem[(self.pc + 1) as usize];
-        let offop0 = self.mem[(self.pc + 2) as usize];
-        self.mem[(self.fp + offdst) as usize] = self.mem[(self.fp + offop0) as usize];
+    pub fn step(&mut self, hint_processor: &mut dyn HintProcessor) -> Result<(), VmError> {
+        let pc = self.pc;
+        self.execute_step(hint_processor)
+            .map_err(|kind| VmError { pc, kind })
     }
 
-    fn mov_fp_imm(&mut self) {
-        let offdst = self.mem[(self.pc + 1) as usize];
-        let imm = self.mem[(self.pc + 2) as usize];
-        self.mem[(self.fp + offdst) as usize] = imm;
-    }
+    fn execute_step(&mut self, hint_processor: &mut dyn HintProcessor) -> Result<(), VmErrorKind> {
+        self.trace.push(TraceEntry {
+            pc: self.pc,
+            ap: self.ap,
+            fp: self.fp,
+        });
+        if self.pc.segment == self.program_base.segment
+            && let Some(hints) = self.hints.get(&self.pc.offset).cloned()
+        {
+            for hint in hints.iter() {
+                hint_processor
+                    .execute_hint(self, hint)
+                    .map_err(VmErrorKind::Hint)?;
+            }
+        }
 
-    fn add_fp_fp(&mut self) {
-        let offdst = self.mem[(self.pc + 1) as usize];
-        let offop0 = self.mem[(self.pc + 2) as usize];
-        let offop1 = self.mem[(self.pc + 3) as usize];
-        self.mem[(self.fp + offdst) as usize] =
-            self.mem[(self.fp + offop0) as usize] + self.mem[(self.fp + offop1) as usize];
-    }
+        let word = match self.memory.get(self.pc) {
+            Some(Value::Int(word)) => word,
+            _ => {
+                return Err(VmErrorKind::InvalidInstruction(
+                    "No instruction".to_string(),
+                ));
+            }
+        };
+        let instr = word
+            .to_u64()
+            .ok_or(format!("Invalid instruction {:#x}", word))
+            .and_then(decode)
+            .map_err(VmErrorKind::InvalidInstruction)?;
+
+        let register = |vm: &Self, reg: Register| match reg {
+            Register::Ap => vm.ap,
+            Register::Fp => vm.fp,
+        };
+        let dst_addr = register(self, instr.dst_reg).add(instr.off_dst)?;
+        let op0_addr = register(self, instr.op0_reg).add(instr.off_op0)?;
+        let mut op0 = self.get_or_deduce(op0_addr)?;
+        let mut dst = self.get_or_deduce(dst_addr)?;
+
+        // the return pc of a call is known even before it is written in memory
+        if instr.opcode == Opcode::Call && op0.is_none() {
+            op0 = Some(Value::Ptr(self.pc.add(instr.size() as i64)?));
+        }
 
-    fn add_fp_imm(&mut self) {
-        let offdst = self.mem[(self.pc + 1) as usize];
-        let offop0 = self.mem[(self.pc + 2) as usize];
-        let imm = self.mem[(self.pc + 3) as usize];
-        self.mem[(self.fp + offdst) as usize] = self.mem[(self.fp + offop0) as usize] + imm;
+        let op1_addr = match instr.op1_src {
+            Op1Src::Imm => self.pc.add(1)?,
+            Op1Src::Fp => self.fp.add(instr.off_op1)?,
+            Op1Src::Ap => self.ap.add(instr.off_op1)?,
+            Op1Src::Op0 => match op0 {
+                Some(Value::Ptr(ptr)) => ptr.add(instr.off_op1)?,
+                Some(_) => return Err("op0 must be a pointer".to_string().into()),
+                None => return Err(VmErrorKind::UnknownMemory(op0_addr, "op0")),
+            },
+        };
+        let mut op1 = self.get_or_deduce(op1_addr)?;
+
+        // deduce unknown operands from the assertion dst = res
+        let field = self.field;
+        if instr.opcode == Opcode::AssertEq
+            && let Some(dst_value) = dst
+        {
+            match (instr.res, op0, op1) {
+                (ResLogic::Op1, _, None) => op1 = Some(dst_value),
+                (ResLogic::Add, Some(a), None) => op1 = Some(dst_value.sub(&a, &field)?),
+                (ResLogic::Add, None, Some(b)) => op0 = Some(dst_value.sub(&b, &field)?),
+                (ResLogic::Mul, Some(a), None) if !a.is_zero(&field) => {
+                    op1 = Some(dst_value.div(&a, &field)?)
+                }
+                (ResLogic::Mul, None, Some(b)) if !b.is_zero(&field) => {
+                    op0 = Some(dst_value.div(&b, &field)?)
+                }
+                _ => {}
+            }
+        }
+
+        let op0 = op0.ok_or(VmErrorKind::UnknownMemory(op0_addr, "op0"))?;
+        let op1 = op1.ok_or(VmErrorKind::UnknownMemory(op1_addr, "op1"))?;
+        self.memory.insert(op0_addr, op0)?;
+        self.memory.insert(op1_addr, op1)?;
+
+        let res = match instr.res {
+            ResLogic::Op1 => Some(op1),
+            ResLogic::Add => Some(op0.add(&op1, &field)?),
+            ResLogic::Mul => Some(op0.mul(&op1, &field)?),
+            ResLogic::Unconstrained => None,
+        };
+
+        match instr.opcode {
+            Opcode::AssertEq => {
+                let res = res.ok_or("Unconstrained res in assert_eq".to_string())?;
+                if dst.is_none() {
+                    dst = Some(res);
+                }
+                if let Some(dst) = dst
+                    && dst != res
+                {
+                    return Err(VmErrorKind::AssertEqFailed(dst, res));
+                }
+            }
+            Opcode::Call => {
+                dst = Some(Value::Ptr(self.fp));
+                if op0 != Value::Ptr(self.pc.add(instr.size() as i64)?) {
+                    return Err("Call does not save the return pc".to_string().into());
+                }
+            }
+            _ => {}
+        }
+
+        let dst = match dst {
+            Some(dst) => {
+                self.memory.insert(dst_addr, dst)?;
+                dst
+            }
+            None if instr.pc_update == PcUpdate::Jnz || instr.opcode == Opcode::Ret => {
+                return Err(VmErrorKind::UnknownMemory(dst_addr, "dst"));
+            }
+            // dst is not used by the remaining instruction kinds
+            None => Value::Int(Felt::ZERO),
+        };
+
+        let new_fp = match instr.opcode {
+            Opcode::Call => self.ap.add(2)?,
+            Opcode::Ret => match dst {
+                Value::Ptr(ptr) => ptr,
+                _ => return Err(format!("Ret with non pointer fp {}", dst).into()),
+            },
+            _ => self.fp,
+        };
+
+        let new_ap = match instr.ap_update {
+            ApUpdate::Regular => self.ap,
+            ApUpdate::Add1 => self.ap.add(1)?,
+            ApUpdate::Add2 => self.ap.add(2)?,
+            ApUpdate::Add => match res {
+                Some(Value::Int(n)) => self.ap.add(signed(&field, &n)?)?,
+                _ => return Err("Invalid ap update".to_string().into()),
+            },
+        };
+
+        let new_pc = match instr.pc_update {
+            PcUpdate::Regular => self.pc.add(instr.size() as i64)?,
+            PcUpdate::Jump => match res {
+                Some(Value::Ptr(ptr)) => ptr,
+                _ => return Err("Absolute jump to a non pointer".to_string().into()),
+            },
+            PcUpdate::JumpRel => match res {
+                Some(Value::Int(n)) => self.pc.add(signed(&field, &n)?)?,
+                _ => return Err("Relative jump by a non integer".to_string().into()),
+            },
+            PcUpdate::Jnz => {
+                if dst.is_zero(&field) {
+                    self.pc.add(instr.size() as i64)?
+                } else {
+                    match op1 {
+                        Value::Int(n) => self.pc.add(signed(&field, &n)?)?,
+                        _ => return Err("Jnz by a non integer".to_string().into()),
+                    }
+                }
+            }
+        };
+
+        self.pc = new_pc;
+        self.ap = new_ap;
+        self.fp = new_fp;
+        self.steps += 1;
+        Ok(())
     }
 
-    fn sub_fp_fp(&mut self) {
-        let offdst = self.mem[(self.pc + 1) as usize];
-        let offop0 = self.mem[(self.pc + 2) as usize];
-        let offop1 = self.mem[(self.pc + 3) as usize];
-        self.mem[(self.fp + offdst) as usize] =
-            self.mem[(self.fp + offop0) as usize] - self.mem[(self.fp + offop1) as usize];
+    // Reads a memory cell, letting the builtins fill in the cells they compute.
+    fn get_or_deduce(&mut self, addr: Relocatable) -> Result<Option<Value>, VmErrorKind> {
+        if let Some(value) = self.memory.get(addr) {
+            return Ok(Some(value));
+        }
+        for builtin in self.builtins.iter() {
+            if builtin.base.segment == addr.segment {
+                let value = builtin
+                    .deduce(&self.memory, addr, &self.field)
+                    .map_err(VmErrorKind::Builtin)?;
+                if let Some(value) = value {
+                    self.memory.insert(addr, value)?;
+                }
+                return Ok(value);
+            }
+        }
+        Ok(None)
     }
 
-    fn sub_fp_imm(&mut self) {
-        let offdst = self.mem[(self.pc + 1) as usize];
-        let offop0 = self.mem[(self.pc + 2) as usize];
-        let imm = self.mem[(self.pc + 3) as usize];
-        self.mem[(self.fp + offdst) as usize] = self.mem[(self.fp + offop0) as usize] - imm;
+    // Cells written to the output builtin, in order.
+    pub fn output(&self) -> Vec<Option<Value>> {
+        self.builtins
+            .iter()
+            .find(|builtin| builtin.kind == BuiltinKind::Output)
+            .and_then(|builtin| self.memory.segments.get(builtin.base.segment))
+            .cloned()
+            .unwrap_or_default()
     }
 
-    fn mul_fp_fp(&mut self) {
-        let offdst = self.mem[(self.pc + 1) as usize];
-        let offop0 = self.mem[(self.pc + 2) as usize];
-        let offop1 = self.mem[(self.pc + 3) as usize];
-        self.mem[(self.fp + offdst) as usize] =
-            self.mem[(self.fp + offop0) as usize] * self.mem[(self.fp + offop1) as usize];
+    // pc of the current instruction, followed by the call instruction of each frame on
+    // the fp chain, innermost first.
+    pub fn call_stack(&self) -> Vec<Relocatable> {
+        let mut stack = vec![self.pc];
+        let mut fp = self.fp;
+        while let (Ok(saved_fp), Ok(return_pc)) = (fp.add(-2), fp.add(-1))
+            && let (Some(Value::Ptr(saved_fp)), Some(Value::Ptr(return_pc))) =
+                (self.memory.get(saved_fp), self.memory.get(return_pc))
+            && return_pc.segment == self.program_base.segment
+            && saved_fp < fp
+        {
+            // calls are two cells long
+            match return_pc.add(-2) {
+                Ok(call_pc) => stack.push(call_pc),
+                Err(_) => break,
+            }
+            fp = saved_fp;
+        }
+        stack
     }
 
-    fn mul_fp_imm(&mut self) {
-        let offdst = self.mem[(self.pc + 1) as usize];
-        let offop0 = self.mem[(self.pc + 2) as usize];
-        let imm = self.mem[(self.pc + 2) as usize];
-        self.mem[(self.fp + offdst) as usize] = self.mem[(self.fp + offop0) as usize] * imm;
+    // Value at [ap - 1], where functions leave their return value.
+    // The last `n` cells below ap: the values returned by main once the run ended, after the
+    // builtin pointers it returns first.
+    pub fn return_values(&self, n: usize) -> Vec<Option<Value>> {
+        (1..=n as i64)
+            .rev()
+            .map(|i| self.memory.get(self.ap.add(-i).ok()?))
+            .collect()
     }
 
-    fn jmp(&mut self) {
-        let offset = self.mem[(self.pc + 1) as usize] * 4;
-        self.pc = offset;
+    // What a finished run printed: the values main returned, if it declares any.
+    pub fn summary(&self, n_return_values: usize) -> String {
+        if n_return_values == 0 {
+            return format!("Program ran in {} steps", self.steps);
+        }
+        let values: Vec<String> = self
+            .return_values(n_return_values)
+            .iter()
+            .map(|value| value.map_or("<missing>".to_string(), |value| value.to_string()))
+            .collect();
+        format!(
+            "Program returned {} in {} steps",
+            values.join(", "),
+            self.steps
+        )
     }
 
-    fn jnz(&mut self) {
-        let address = self.mem[(self.pc + 1) as usize] * 4;
-        let offset = self.mem[(self.pc + 2) as usize];
-        if self.mem[(self.fp + offset) as usize] != 0 {
-            self.pc = address - 4; // Subtract 4 because the main loop will add 4
+    // Segments are laid out one after the other, the first one starting at address 1
+    // like in cairo-lang. Returns the address of each segment.
+    pub fn relocation_table(&self) -> Vec<u64> {
+        let mut table = Vec::new();
+        let mut address = 1;
+        for segment in self.memory.segments.iter() {
+            table.push(address);
+            address += segment.len() as u64;
         }
+        table
     }
 
-    fn call(&mut self) {
-        let func_addr = self.mem[(self.pc + 1) as usize];
-        let frame_size = self.mem[(self.pc + 2) as usize];
-        // push old fp
-        self.mem[(self.fp + frame_size) as usize] = self.fp;
-        // push return adress
-        self.mem[(self.fp + frame_size + 1) as usize] = self.pc + 4;
-        // set new fp
-        self.fp = self.fp + frame_size + 2;
-        // jump to function
-        self.pc = func_addr;
+    pub fn relocate(table: &[u64], addr: Relocatable) -> u64 {
+        table[addr.segment] + addr.offset
     }
 
-    fn ret(&mut self) {
-        if self.fp == self.program_size {
-            self.exit = true;
-            return;
+    fn relocate_value(table: &[u64], value: Value) -> Felt {
+        match value {
+            Value::Int(n) => n,
+            Value::Ptr(ptr) => Felt::from(Self::relocate(table, ptr)),
         }
-        // pop return adress
-        let return_addr = self.mem[(self.fp - 1) as usize];
-        // pop old fp
-        self.fp = self.mem[(self.fp - 2) as usize];
-        // jump to return address
-        self.pc = return_addr;
     }
 
-    pub fn print_mem(&self) {
-        for i in 0..self.mem.len() {
-            println!("{}: {}", i, self.mem[i]);
+    pub fn relocated_trace(&self) -> Vec<TraceEntry<u64>> {
+        let table = self.relocation_table();
+        self.trace
+            .iter()
+            .map(|entry| TraceEntry {
+                pc: Self::relocate(&table, entry.pc),
+                ap: Self::relocate(&table, entry.ap),
+                fp: Self::relocate(&table, entry.fp),
+            })
+            .collect()
+    }
+
+    // Written cells, by increasing address.
+    pub fn relocated_memory(&self) -> Vec<(u64, Felt)> {
+        let table = self.relocation_table();
+        let mut memory = Vec::new();
+        for (segment, cells) in self.memory.segments.iter().enumerate() {
+            for (offset, value) in cells.iter().enumerate() {
+                if let Some(value) = value {
+                    memory.push((
+                        table[segment] + offset as u64,
+                        Self::relocate_value(&table, *value),
+                    ));
+                }
+            }
         }
+        memory
     }
 }
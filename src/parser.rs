@@ -1,20 +1,65 @@
 use crate::ast::*;
+use crate::cst::{SyntaxKind, SyntaxNode};
+use crate::diagnostic::{Diagnostic, codes};
 use crate::lexer::Token;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
     source: String,
-    file_name: String,
+    diagnostics: Vec<Diagnostic>,
+    // set by a syntax error until the parser skips to the end of the statement,
+    // the errors in between are consequences of the first one
+    panic_mode: bool,
+    // statements parsed so far at the current level of nesting
+    nodes: Vec<SyntaxNode>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>, file_name: String, source: String) -> Self {
+    pub fn new(tokens: Vec<Token>, source: String) -> Self {
         Self {
             tokens,
             current: 0,
             source,
-            file_name,
+            diagnostics: Vec::new(),
+            panic_mode: false,
+            nodes: Vec::new(),
+        }
+    }
+
+    // The statements of the last parse, for the concrete syntax tree.
+    pub fn take_nodes(&mut self) -> Vec<SyntaxNode> {
+        std::mem::take(&mut self.nodes)
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.report(Diagnostic::error(codes::SYNTAX_ERROR, message).with_span(span));
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic) {
+        if !self.panic_mode {
+            self.diagnostics.push(diagnostic);
+        }
+        self.panic_mode = true;
+    }
+
+    // Skips the rest of the statement in error: up to and including the next `;`,
+    // or up to the `}` closing the block or the next `func`.
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+        while !self.is_at_end() {
+            match self.peek().token_type {
+                crate::lexer::TokenType::Semicolon => {
+                    self.advance();
+                    return;
+                }
+                crate::lexer::TokenType::RBrace
+                | crate::lexer::TokenType::Func
+                | crate::lexer::TokenType::At => return,
+                _ => {
+                    self.advance();
+                }
+            }
         }
     }
 
@@ -34,12 +79,15 @@ impl Parser {
     }
 
     fn is_at_end(&mut self) -> bool {
-        self.peek().token_type == crate::lexer::TokenType::EOF
+        self.peek().token_type == crate::lexer::TokenType::Eof
     }
 
     fn advance(&mut self) -> crate::lexer::Token {
-        self.current += 1;
-        self.tokens[self.current - 1].clone()
+        let token = self.peek();
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+        token
     }
 
     fn peek(&mut self) -> crate::lexer::Token {
@@ -54,13 +102,14 @@ impl Parser {
         if self.check(token_type) {
             self.advance()
         } else {
-            let span = self.peek().span;
-            crate::error::report_error(
-                self.file_name.clone(),
-                self.source.clone(),
-                span,
-                "Syntax error".to_string(),
-                message.to_string(),
+            let token = self.peek();
+            let found = match token.token_type {
+                crate::lexer::TokenType::Eof => "found end of file".to_string(),
+                _ => format!("found '{}'", token.lexeme),
+            };
+            let span = token.span;
+            self.report(
+                Diagnostic::error(codes::SYNTAX_ERROR, message.to_string()).with_label(span, found),
             );
             Token {
                 token_type: crate::lexer::TokenType::Error,
@@ -70,12 +119,54 @@ impl Parser {
         }
     }
 
-    pub fn parse(&mut self) -> Vec<CodeElement> {
+    // Parses the whole file, recovering from syntax errors to report as many as possible.
+    pub fn parse(&mut self) -> (Vec<CodeElement>, Vec<Diagnostic>) {
         let mut code_elements = Vec::new();
         while !self.is_at_end() {
-            code_elements.push(self.code_element());
+            let start = self.current;
+            code_elements.push(self.statement());
+            // a stray `}` stops synchronization without being parsed
+            if self.current == start {
+                self.advance();
+            }
         }
-        code_elements
+        (code_elements, std::mem::take(&mut self.diagnostics))
+    }
+
+    fn statement(&mut self) -> CodeElement {
+        let start = self.current;
+        // the statements of blocks become children of this one
+        let outer = std::mem::take(&mut self.nodes);
+        let code_element = self.code_element();
+        // no need to skip anything if the statement in error was parsed to its end
+        let ended = self.current > start
+            && matches!(
+                self.tokens[self.current - 1].token_type,
+                crate::lexer::TokenType::Semicolon | crate::lexer::TokenType::RBrace
+            );
+        if self.panic_mode {
+            if ended {
+                self.panic_mode = false;
+            } else {
+                self.synchronize();
+            }
+        }
+        let children = std::mem::replace(&mut self.nodes, outer);
+        self.nodes.push(SyntaxNode {
+            kind: SyntaxKind::of(&code_element),
+            tokens: (start, self.current),
+            children,
+        });
+        code_element
+    }
+
+    // Statements up to the closing `}`, which is left to the caller.
+    fn block(&mut self) -> Vec<CodeElement> {
+        let mut body = Vec::new();
+        while !self.check(crate::lexer::TokenType::RBrace) && !self.is_at_end() {
+            body.push(self.statement());
+        }
+        body
     }
 
     fn type_(&mut self) -> Type {
@@ -141,13 +232,7 @@ impl Parser {
                 Type::Tuple(types)
             }
             _ => {
-                crate::error::report_error(
-                    self.file_name.clone(),
-                    self.source.clone(),
-                    self.peek().span,
-                    "Syntax error".to_string(),
-                    format!("Expected type, got {:?}", self.peek().lexeme),
-                );
+                self.error(token.span, format!("Expected type, got {:?}", token.lexeme));
                 Type::Error
             }
         }
@@ -158,6 +243,58 @@ impl Parser {
         Identifier { token }
     }
 
+    // `@decorator` lines, then the function itself
+    fn function(&mut self) -> CodeElement {
+        let mut decorators = Vec::new();
+        while self.match_token(crate::lexer::TokenType::At) {
+            decorators.push(self.identifier());
+        }
+        self.consume(
+            crate::lexer::TokenType::Func,
+            "Expected 'func' after decorators",
+        );
+        let name = self.identifier();
+
+        let implicit_args = if self.check(crate::lexer::TokenType::LBrace) {
+            self.typed_identifier_list(
+                crate::lexer::TokenType::LBrace,
+                crate::lexer::TokenType::RBrace,
+                "Expected '{' and '}' around implicit arguments",
+            )
+        } else {
+            vec![]
+        };
+        let args = self.typed_identifier_list(
+            crate::lexer::TokenType::LParen,
+            crate::lexer::TokenType::RParen,
+            "Expected '(' and ')' around arguments",
+        );
+        let return_type = if self.match_token(crate::lexer::TokenType::Arrow) {
+            Some(self.type_())
+        } else {
+            None
+        };
+
+        self.consume(
+            crate::lexer::TokenType::LBrace,
+            "Expected '{' after function",
+        );
+        let body = self.block();
+        self.consume(
+            crate::lexer::TokenType::RBrace,
+            "Expected '}' after function",
+        );
+        CodeElement::Function(Function {
+            decorators,
+            name,
+            implicit_args,
+            args,
+            return_type,
+            body,
+            doc: None,
+        })
+    }
+
     fn expression(&mut self) -> Expr {
         self.sum()
     }
@@ -230,7 +367,7 @@ impl Parser {
             || self.check(crate::lexer::TokenType::Slash)
         {
             let operator = self.advance();
-            let right = self.expression();
+            let right = self.unary();
             match operator.token_type {
                 crate::lexer::TokenType::Star => {
                     expr = Expr::new_binary(ExprType::Mul, expr, right);
@@ -268,9 +405,10 @@ impl Parser {
 
     fn pow(&mut self) -> Expr {
         let mut expr = self.bool_and();
+        // right associative: the exponent is itself a power
         while self.check(crate::lexer::TokenType::DoubleStar) {
             self.advance();
-            let right = self.expression();
+            let right = self.unary();
             expr = Expr::new_binary(ExprType::Pow, expr, right);
         }
         expr
@@ -305,31 +443,38 @@ impl Parser {
     }
 
     fn atom(&mut self) -> Expr {
+        let start = self.current;
         let token = self.peek();
         if self.check(crate::lexer::TokenType::LParen) {
             let args = self.paren_arglist();
             // in the case of a single expression, we return it directly
-            if args.len() == 1 {
-                match args[0].clone() {
-                    ExprAssignment::Expr(expr) => {
-                        return expr;
-                    }
-                    _ => {}
-                }
+            if args.len() == 1
+                && let ExprAssignment::Expr(expr) = args[0].clone()
+            {
+                return expr;
             }
-            Expr::new_tuple_or_paren(args)
+            let mut tuple = Expr::new_tuple_or_paren(args);
+            tuple.token = Some(token);
+            tuple
         } else {
             self.advance();
             match token.token_type {
                 crate::lexer::TokenType::Int => Expr::new_terminal(ExprType::IntegerLiteral, token),
                 crate::lexer::TokenType::Identifier => {
-                    if self.check(crate::lexer::TokenType::LBrace) {
-                        let brace_args = self.brace_arglist();
+                    if self.check(crate::lexer::TokenType::LBrace)
+                        || self.check(crate::lexer::TokenType::LParen)
+                    {
+                        let brace_args = if self.check(crate::lexer::TokenType::LBrace) {
+                            self.brace_arglist()
+                        } else {
+                            vec![]
+                        };
                         let paren_args = self.paren_arglist();
-                        Expr::new_function_call(Identifier { token }, paren_args, brace_args)
-                    } else if self.check(crate::lexer::TokenType::LParen) {
-                        let paren_args = self.paren_arglist();
-                        Expr::new_function_call(Identifier { token }, paren_args, vec![])
+                        let mut call =
+                            Expr::new_function_call(Identifier { token }, paren_args, brace_args);
+                        // the closing parenthesis, so that the span of the call ends with it
+                        call.token = Some(self.tokens[self.current - 1].clone());
+                        call
                     } else if self.check(crate::lexer::TokenType::LBracket) {
                         self.advance();
                         let expr = self.expression();
@@ -349,10 +494,8 @@ impl Parser {
                     Expr::new_terminal(ExprType::IntegerLiteral, token)
                 }
                 crate::lexer::TokenType::NonDet => {
-                    let hint = self.consume(
-                        crate::lexer::TokenType::NonDet,
-                        "Expected hint after nondet",
-                    );
+                    let hint =
+                        self.consume(crate::lexer::TokenType::Hint, "Expected hint after nondet");
                     Expr::new_terminal(ExprType::Hint, hint)
                 }
                 crate::lexer::TokenType::Ap | crate::lexer::TokenType::Fp => {
@@ -372,16 +515,14 @@ impl Parser {
                     let expr = self.expression();
                     self.consume(crate::lexer::TokenType::Comma, "Expected ','");
                     let type_ = self.type_();
-                    println!("type_ is :{:?}", type_.clone());
                     self.consume(crate::lexer::TokenType::RParen, "Expected ')'");
                     Expr::new_cast(type_, expr)
                 }
                 _ => {
-                    crate::error::report_error(
-                        self.file_name.clone(),
-                        self.source.clone(),
+                    // the token may end the statement, leave it to synchronize
+                    self.current = start;
+                    self.error(
                         token.span,
-                        "Syntax error".to_string(),
                         format!("Expected expression, got {:?}", token.lexeme),
                     );
                     Expr::new_error()
@@ -492,20 +633,42 @@ impl Parser {
         }
     }
 
-    fn identifier_list_paren(&mut self) -> Vec<Identifier> {
+    fn typed_identifier(&mut self) -> TypedIdentifier {
+        let ident = self.identifier();
+        let type_ = if self.match_token(crate::lexer::TokenType::Colon) {
+            Some(self.type_())
+        } else {
+            None
+        };
+        TypedIdentifier { ident, type_ }
+    }
+
+    fn typed_identifier_list(
+        &mut self,
+        open: crate::lexer::TokenType,
+        close: crate::lexer::TokenType,
+        message: &str,
+    ) -> Vec<TypedIdentifier> {
         let mut identifiers = Vec::new();
-        self.consume(crate::lexer::TokenType::LParen, "Expected '('");
-        while !self.check(crate::lexer::TokenType::RParen) {
-            identifiers.push(self.identifier());
+        self.consume(open, message);
+        while !self.check(close.clone()) {
+            identifiers.push(self.typed_identifier());
             if !self.check(crate::lexer::TokenType::Comma) {
                 break;
             }
             self.advance();
         }
-        self.consume(crate::lexer::TokenType::RParen, "Expected ')'");
+        self.consume(close, message);
         identifiers
     }
 
+    // true if there is no line break between the previous token and the next one
+    fn on_same_line(&mut self) -> bool {
+        let previous_end = self.tokens[self.current - 1].span.1;
+        let next_start = self.peek().span.0;
+        next_start >= previous_end && !self.source[previous_end..next_start].contains('\n')
+    }
+
     fn code_element(&mut self) -> CodeElement {
         let token = self.peek();
         match token.token_type {
@@ -515,17 +678,11 @@ impl Parser {
                 let cond = self.expression();
                 self.consume(crate::lexer::TokenType::RParen, "Expected ')' after if");
                 self.consume(crate::lexer::TokenType::LBrace, "Expected '{' after if");
-                let mut body = Vec::new();
-                while !self.check(crate::lexer::TokenType::RBrace) {
-                    body.push(self.code_element());
-                }
+                let body = self.block();
                 self.consume(crate::lexer::TokenType::RBrace, "Expected '}' after if");
                 if self.match_token(crate::lexer::TokenType::Else) {
                     self.consume(crate::lexer::TokenType::LBrace, "Expected '{' after else");
-                    let mut else_body = Vec::new();
-                    while !self.check(crate::lexer::TokenType::RBrace) {
-                        else_body.push(self.code_element());
-                    }
+                    let else_body = self.block();
                     self.consume(crate::lexer::TokenType::RBrace, "Expected '}' after else");
                     CodeElement::If(cond, body, else_body)
                 } else {
@@ -533,30 +690,97 @@ impl Parser {
                 }
             }
 
-            crate::lexer::TokenType::Func => {
+            crate::lexer::TokenType::At | crate::lexer::TokenType::Func => self.function(),
+
+            crate::lexer::TokenType::Struct => {
                 self.advance();
-                let ident = self.identifier();
+                let name = self.identifier();
+                let members = self.typed_identifier_list(
+                    crate::lexer::TokenType::LBrace,
+                    crate::lexer::TokenType::RBrace,
+                    "Expected '{' and '}' around struct members",
+                );
+                CodeElement::Struct(name, members, None)
+            }
 
-                let args = self.identifier_list_paren();
+            crate::lexer::TokenType::Const => {
+                self.advance();
+                let name = self.identifier();
+                self.consume(crate::lexer::TokenType::Equal, "Expected '=' after const");
+                let value = self.expression();
+                self.consume(
+                    crate::lexer::TokenType::Semicolon,
+                    "Expected ';' after const",
+                );
+                CodeElement::Const(name, value, None)
+            }
 
+            crate::lexer::TokenType::Namespace => {
+                self.advance();
+                let name = self.identifier();
                 self.consume(
                     crate::lexer::TokenType::LBrace,
-                    "Expected '{' after function",
+                    "Expected '{' after namespace",
                 );
-                let mut body = Vec::new();
-                while !self.check(crate::lexer::TokenType::RBrace) {
-                    body.push(self.code_element());
-                }
+                let members = self.block();
                 self.consume(
                     crate::lexer::TokenType::RBrace,
-                    "Expected '}' after function",
+                    "Expected '}' after namespace",
+                );
+                CodeElement::NameSpace(name, members, None)
+            }
+
+            crate::lexer::TokenType::Let => {
+                self.advance();
+                let ident = self.typed_identifier();
+                self.consume(crate::lexer::TokenType::Equal, "Expected '=' after let");
+                let expr = self.expression();
+                self.consume(crate::lexer::TokenType::Semicolon, "Expected ';' after let");
+                CodeElement::Reference(ident, expr)
+            }
+
+            crate::lexer::TokenType::Builtins | crate::lexer::TokenType::Lang => {
+                let directive = self.advance();
+                // directives end at the end of the line
+                let mut args = Vec::new();
+                while self.check(crate::lexer::TokenType::Identifier) && self.on_same_line() {
+                    args.push(self.identifier());
+                }
+                // the directive ends at the end of the line, nothing to skip
+                if args.is_empty() {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            codes::SYNTAX_ERROR,
+                            format!("Expected identifiers after {}", directive.lexeme),
+                        )
+                        .with_span(directive.span),
+                    );
+                }
+                CodeElement::Directive(directive, args)
+            }
+
+            crate::lexer::TokenType::Identifier
+                if matches!(
+                    self.peekpeek().token_type,
+                    crate::lexer::TokenType::LParen | crate::lexer::TokenType::LBrace
+                ) =>
+            {
+                let call = self.expression();
+                // `f(x) + 1;` computes a value nothing uses
+                if !matches!(call.expr_type, ExprType::FunctionCall) {
+                    let span = call.span().unwrap_or(token.span);
+                    self.error(span, "Expected a function call".to_string());
+                }
+                self.consume(
+                    crate::lexer::TokenType::Semicolon,
+                    "Expected ';' after function call",
                 );
-                CodeElement::Function(ident, args, body)
+                CodeElement::FuncCall(call)
             }
 
             crate::lexer::TokenType::Local => {
                 self.advance();
-                let ident = self.identifier();
+                let ident = self.typed_identifier();
                 if self.match_token(crate::lexer::TokenType::Equal) {
                     let expr = self.expression();
                     self.consume(
@@ -595,13 +819,15 @@ impl Parser {
                 CodeElement::Return(expr)
             }
 
+            crate::lexer::TokenType::Hint => CodeElement::Hint(self.advance()),
+
             crate::lexer::TokenType::AllocLocals => {
-                self.advance();
+                let token = self.advance();
                 self.consume(
                     crate::lexer::TokenType::Semicolon,
                     "Expected ';' after alloc_locals",
                 );
-                CodeElement::AllocLocals
+                CodeElement::AllocLocals(token)
             }
 
             _ => {
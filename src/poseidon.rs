@@ -0,0 +1,116 @@
+// Poseidon-style permutation over the Mersenne-31 field, used by the poseidon builtin.
+//
+// Parameters:
+// - state width t = 3, S-box x^5 (a permutation since gcd(5, p - 1) = 1)
+// - 8 full rounds (4 before and 4 after the partial rounds) and 22 partial rounds,
+//   where partial rounds only apply the S-box to the first element
+// - MDS matrix circ(2, 1, 1), i.e. each element becomes itself plus the sum of the state
+// - round constants are drawn from splitmix64 seeded with ROUND_CONSTANTS_SEED, keeping the
+//   top 31 bits of each output and rejecting the value p itself
+//
+// This instance is deterministic and cheap to check, but it is not a standardized one and
+// has not been cryptanalysed.
+
+// 2^31 - 1
+const PRIME: u64 = 0x7fffffff;
+
+pub const STATE_WIDTH: usize = 3;
+const FULL_ROUNDS: usize = 8;
+const PARTIAL_ROUNDS: usize = 22;
+const N_ROUNDS: usize = FULL_ROUNDS + PARTIAL_ROUNDS;
+
+// "czc-pose" in ascii
+const ROUND_CONSTANTS_SEED: u64 = 0x637a632d706f7365;
+
+fn add(a: u64, b: u64) -> u64 {
+    (a + b) % PRIME
+}
+
+fn pow5(x: u64) -> u64 {
+    let x2 = x * x % PRIME;
+    x2 * x2 % PRIME * x % PRIME
+}
+
+const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+const fn round_constants() -> [[u64; STATE_WIDTH]; N_ROUNDS] {
+    let mut constants = [[0; STATE_WIDTH]; N_ROUNDS];
+    let mut state = ROUND_CONSTANTS_SEED;
+    let mut round = 0;
+    while round < N_ROUNDS {
+        let mut i = 0;
+        while i < STATE_WIDTH {
+            let mut value = splitmix64(&mut state) >> 33;
+            while value == PRIME {
+                value = splitmix64(&mut state) >> 33;
+            }
+            constants[round][i] = value;
+            i += 1;
+        }
+        round += 1;
+    }
+    constants
+}
+
+const ROUND_CONSTANTS: [[u64; STATE_WIDTH]; N_ROUNDS] = round_constants();
+
+fn mix(state: &mut [u64; STATE_WIDTH]) {
+    let sum = state.iter().fold(0, |acc, x| add(acc, *x));
+    for x in state.iter_mut() {
+        *x = add(*x, sum);
+    }
+}
+
+pub fn permute(state: &mut [u64; STATE_WIDTH]) {
+    for x in state.iter_mut() {
+        *x %= PRIME;
+    }
+    for (round, constants) in ROUND_CONSTANTS.iter().enumerate() {
+        for (x, c) in state.iter_mut().zip(constants) {
+            *x = add(*x, *c);
+        }
+        let full = !(FULL_ROUNDS / 2..FULL_ROUNDS / 2 + PARTIAL_ROUNDS).contains(&round);
+        if full {
+            for x in state.iter_mut() {
+                *x = pow5(*x);
+            }
+        } else {
+            state[0] = pow5(state[0]);
+        }
+        mix(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Computed by an independent implementation of the parameters above, so that a change of
+    // the constants or of the rounds shows up as a different hash.
+    #[test]
+    fn known_answers() {
+        assert_eq!(ROUND_CONSTANTS[0], [642577380, 1075106311, 245253297]);
+        assert_eq!(
+            ROUND_CONSTANTS[N_ROUNDS - 1],
+            [1551927377, 1171986155, 1394899381]
+        );
+        let cases = [
+            ([0, 0, 0], [1910053744, 44691476, 2080817235]),
+            ([1, 2, 3], [780082287, 1526514663, 1477992140]),
+            (
+                [PRIME - 1, PRIME - 1, PRIME - 1],
+                [888084805, 1897236431, 61197764],
+            ),
+        ];
+        for (mut state, expected) in cases {
+            permute(&mut state);
+            assert_eq!(state, expected);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// Attributes the steps of a run to the functions and instructions that executed them,
+// the main contributors to the cost of proving the run.
+
+use crate::minivm::{MiniVm, Relocatable};
+use crate::source_map::SourceMap;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+// Number of instructions listed in the report.
+const TOP_INSTRUCTIONS: usize = 10;
+
+#[derive(Debug, Clone, Default)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    // steps spent in the function and the functions it called
+    pub inclusive_steps: u64,
+    // steps spent in the function itself
+    pub exclusive_steps: u64,
+    // execution cells allocated by the function itself, i.e. its ap increments
+    pub memory_cells: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub functions: BTreeMap<String, FunctionProfile>,
+    // steps executed by each instruction of the program
+    pub instructions: BTreeMap<u64, u64>,
+    // steps by call stack, outermost function first, joined by ';'
+    pub stacks: BTreeMap<String, u64>,
+    pub steps: u64,
+    // execution cells allocated by the run, the sum of those of the functions
+    pub memory_cells: u64,
+}
+
+impl Profile {
+    // Replays the call stack from the trace: a call moves fp to a new frame, a return
+    // moves it back to a frame already on the stack.
+    pub fn new(vm: &MiniVm, source_map: &SourceMap) -> Self {
+        let mut profile = Profile {
+            steps: vm.trace.len() as u64,
+            ..Default::default()
+        };
+        let mut stack: Vec<(Relocatable, String)> = Vec::new();
+        for (i, entry) in vm.trace.iter().enumerate() {
+            let pc = (entry.pc.segment == vm.program_base.segment).then_some(entry.pc.offset);
+            if let Some(depth) = stack.iter().rposition(|(fp, _)| *fp == entry.fp) {
+                stack.truncate(depth + 1);
+            } else {
+                let name = pc
+                    .and_then(|pc| source_map.function(pc))
+                    .map_or("<unknown>".to_string(), |(_, name)| name.to_string());
+                profile.functions.entry(name.clone()).or_default().calls += 1;
+                stack.push((entry.fp, name));
+            }
+
+            // recursive functions count once in their own inclusive steps
+            let mut seen = HashSet::new();
+            for (_, name) in stack.iter() {
+                if seen.insert(name) {
+                    profile.functions.get_mut(name).unwrap().inclusive_steps += 1;
+                }
+            }
+            let (_, current) = stack.last().unwrap();
+            let function = profile.functions.get_mut(current).unwrap();
+            function.exclusive_steps += 1;
+            let next_ap = vm.trace.get(i + 1).map_or(vm.ap, |next| next.ap);
+            if next_ap.segment == entry.ap.segment && next_ap.offset > entry.ap.offset {
+                function.memory_cells += next_ap.offset - entry.ap.offset;
+                profile.memory_cells += next_ap.offset - entry.ap.offset;
+            }
+            if let Some(pc) = pc {
+                *profile.instructions.entry(pc).or_default() += 1;
+            }
+            let folded = stack
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(";");
+            *profile.stacks.entry(folded).or_default() += 1;
+        }
+        profile
+    }
+
+    pub fn print(&self, source_map: &SourceMap) {
+        println!(
+            "Profile: {} steps, {} execution cells",
+            self.steps, self.memory_cells
+        );
+        let mut functions: Vec<(&String, &FunctionProfile)> = self.functions.iter().collect();
+        functions
+            .sort_by_key(|(name, function)| (std::cmp::Reverse(function.inclusive_steps), *name));
+        println!(
+            "  {:<24} {:>8} {:>10} {:>10} {:>8}",
+            "function", "calls", "inclusive", "exclusive", "cells"
+        );
+        for (name, function) in functions {
+            println!(
+                "  {:<24} {:>8} {:>10} {:>10} {:>8}",
+                name,
+                function.calls,
+                function.inclusive_steps,
+                function.exclusive_steps,
+                function.memory_cells
+            );
+        }
+
+        let mut instructions: Vec<(&u64, &u64)> = self.instructions.iter().collect();
+        instructions.sort_by_key(|(pc, steps)| (std::cmp::Reverse(**steps), **pc));
+        println!("  {:<24} {:>8}  source", "instruction", "steps");
+        for (pc, steps) in instructions.into_iter().take(TOP_INSTRUCTIONS) {
+            let function = match source_map.function(*pc) {
+                Some((start, name)) => format!("{}+{}", name, pc - start),
+                None => format!("pc {}", pc),
+            };
+            let line = source_map
+                .location(*pc)
+                .and_then(|span| source_map.source.get(span.0..span.1))
+                .and_then(|text| text.lines().next())
+                .unwrap_or("");
+            println!("  {:<24} {:>8}  {}", function, steps, line);
+        }
+    }
+
+    // Folded stacks, one `outer;inner steps` line per call stack, as read by
+    // flamegraph.pl and inferno.
+    pub fn write_folded(&self, path: &str) -> Result<(), String> {
+        let mut text = String::new();
+        for (stack, steps) in self.stacks.iter() {
+            text.push_str(&format!("{} {}\n", stack, steps));
+        }
+        fs::write(path, text).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+}
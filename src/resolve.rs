@@ -0,0 +1,276 @@
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, codes};
+use crate::felt::Felt;
+use crate::lexer::TokenType;
+use crate::stdlib;
+use std::collections::HashMap;
+
+// Checks that every identifier and function a program uses is defined, before lowering.
+// The scopes follow lower_to_casm: a name bound in only one branch of an if is gone after it.
+
+// Name and declaration of every binding in scope.
+type Scope = HashMap<String, Option<Span>>;
+
+struct Resolver {
+    // declaration of the functions of the program, None for the library ones
+    functions: HashMap<String, Option<Span>>,
+    implicit_args: HashMap<String, Vec<String>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub fn check(code_elements: &[CodeElement]) -> Vec<Diagnostic> {
+    let mut resolver = Resolver {
+        functions: HashMap::new(),
+        implicit_args: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
+    let mut builtins = Vec::new();
+    for element in code_elements {
+        match element {
+            CodeElement::Function(function) => {
+                let name = function.name.token.lexeme.clone();
+                resolver
+                    .functions
+                    .insert(name.clone(), Some(function.name.token.span));
+                resolver
+                    .implicit_args
+                    .insert(name, names(&function.implicit_args));
+            }
+            CodeElement::Directive(directive, args)
+                if directive.token_type == TokenType::Builtins =>
+            {
+                builtins.extend(args.iter().map(|arg| format!("{}_ptr", arg.token.lexeme)));
+            }
+            _ => {}
+        }
+    }
+    for function in stdlib::functions() {
+        let name = function.name.token.lexeme.clone();
+        if !resolver.functions.contains_key(&name) {
+            resolver.functions.insert(name.clone(), None);
+            resolver
+                .implicit_args
+                .insert(name, names(&function.implicit_args));
+        }
+    }
+
+    for element in code_elements {
+        if let CodeElement::Function(function) = element {
+            let mut scope = Scope::new();
+            // the runner passes the builtin pointers to main as implicit arguments
+            if function.name.token.lexeme == "main" && function.implicit_args.is_empty() {
+                for name in builtins.iter() {
+                    scope.insert(name.clone(), None);
+                }
+            }
+            for arg in function.implicit_args.iter().chain(function.args.iter()) {
+                scope.insert(arg.ident.token.lexeme.clone(), Some(arg.ident.token.span));
+            }
+            resolver.block(&mut scope, &function.body);
+        }
+    }
+    resolver.diagnostics
+}
+
+fn names(args: &[TypedIdentifier]) -> Vec<String> {
+    args.iter()
+        .map(|arg| arg.ident.token.lexeme.clone())
+        .collect()
+}
+
+// Edit distance in characters, a swap of adjacent characters counting as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] is the distance between the first i chars of a and the first j of b
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+// The closest candidate, if it is close enough to be a typo of `name`.
+fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .filter(|candidate| !candidate.starts_with('%'))
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// Adds the suggestion, and where the suggested name is declared.
+fn with_suggestion(
+    diagnostic: Diagnostic,
+    suggestion: Option<(&String, Option<Span>)>,
+) -> Diagnostic {
+    let Some((name, span)) = suggestion else {
+        return diagnostic;
+    };
+    let diagnostic = diagnostic.with_help(format!("did you mean '{}'?", name));
+    match span {
+        Some(span) if span.0 < span.1 => {
+            diagnostic.with_secondary(span, format!("'{}' is declared here", name))
+        }
+        _ => diagnostic,
+    }
+}
+
+impl Resolver {
+    fn identifier(&mut self, scope: &Scope, ident: &Identifier) {
+        // members are checked by the type checker
+        let name = ident.token.lexeme.split('.').next().unwrap_or_default();
+        if scope.contains_key(name) {
+            return;
+        }
+        let suggestion = suggest(name, scope.keys()).map(|name| (name, scope[name]));
+        let diagnostic = Diagnostic::error(
+            codes::UNKNOWN_IDENTIFIER,
+            format!("Unknown identifier '{}'", name),
+        )
+        .with_label(ident.token.span, "not found in this scope".to_string());
+        self.diagnostics
+            .push(with_suggestion(diagnostic, suggestion));
+    }
+
+    fn function_call(&mut self, scope: &Scope, expr: &Expr) {
+        let Some(callee) = &expr.ident else {
+            return;
+        };
+        let name = &callee.token.lexeme;
+        if !self.functions.contains_key(name) {
+            let suggestion =
+                suggest(name, self.functions.keys()).map(|name| (name, self.functions[name]));
+            let diagnostic = Diagnostic::error(
+                codes::UNKNOWN_FUNCTION,
+                format!("Unknown function '{}'", name),
+            )
+            .with_label(callee.token.span, "not defined".to_string());
+            self.diagnostics
+                .push(with_suggestion(diagnostic, suggestion));
+        }
+
+        // implicit arguments not passed in braces are taken from the caller's scope
+        let implicit_args = self.implicit_args.get(name).cloned().unwrap_or_default();
+        for implicit_arg in implicit_args.iter() {
+            let passed = expr.brace_args.iter().any(|arg| match arg {
+                ExprAssignment::Assign(ident, _) => ident.token.lexeme == *implicit_arg,
+                ExprAssignment::Expr(value) => value
+                    .ident
+                    .as_ref()
+                    .is_some_and(|ident| ident.token.lexeme == *implicit_arg),
+            });
+            if passed || scope.contains_key(implicit_arg) {
+                continue;
+            }
+            self.diagnostics.push(
+                Diagnostic::error(
+                    codes::UNKNOWN_IDENTIFIER,
+                    format!("Unknown identifier '{}'", implicit_arg),
+                )
+                .with_label(
+                    expr.span().unwrap_or(callee.token.span),
+                    format!("'{}' is an implicit argument of '{}'", implicit_arg, name),
+                )
+                .with_help(format!(
+                    "declare '{}' in the caller, or pass it with {}{{{}=...}}(...)",
+                    implicit_arg, name, implicit_arg
+                )),
+            );
+        }
+    }
+
+    fn expr(&mut self, scope: &Scope, expr: &Expr) {
+        match expr.expr_type {
+            ExprType::Identifier => {
+                if let Some(ident) = &expr.ident {
+                    self.identifier(scope, ident);
+                }
+            }
+            ExprType::FunctionCall => self.function_call(scope, expr),
+            // larger literals are reduced modulo the prime when assembled
+            ExprType::IntegerLiteral => {
+                if let Some(token) = &expr.token
+                    && let Err(message) = token.lexeme.parse::<Felt>()
+                {
+                    self.diagnostics.push(
+                        Diagnostic::error(codes::INVALID_LITERAL, message)
+                            .with_label(token.span, "not a 256-bit integer".to_string()),
+                    );
+                }
+            }
+            _ => {}
+        }
+        for child in [&expr.left, &expr.right].into_iter().flatten() {
+            self.expr(scope, child);
+        }
+        for arg in expr.paren_args.iter().chain(expr.brace_args.iter()) {
+            match arg {
+                ExprAssignment::Expr(expr) | ExprAssignment::Assign(_, expr) => {
+                    self.expr(scope, expr)
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, scope: &mut Scope, body: &[CodeElement]) {
+        for element in body {
+            self.code_element(scope, element);
+        }
+    }
+
+    fn code_element(&mut self, scope: &mut Scope, element: &CodeElement) {
+        match element {
+            // the slot exists before the value is computed
+            CodeElement::LocalVar(TypedIdentifier { ident, .. }, expr) => {
+                scope.insert(ident.token.lexeme.clone(), Some(ident.token.span));
+                if let Some(expr) = expr {
+                    self.expr(scope, expr);
+                }
+            }
+            CodeElement::Reference(TypedIdentifier { ident, .. }, expr) => {
+                self.expr(scope, expr);
+                scope.insert(ident.token.lexeme.clone(), Some(ident.token.span));
+            }
+            CodeElement::Instruction(instruction) => {
+                for arg in instruction.args.iter() {
+                    self.expr(scope, arg);
+                }
+            }
+            CodeElement::CompoundAssertEqual(left, right) => {
+                self.expr(scope, left);
+                self.expr(scope, right);
+            }
+            CodeElement::Return(expr) | CodeElement::FuncCall(expr) => self.expr(scope, expr),
+            CodeElement::If(condition, body, else_body) => {
+                self.expr(scope, condition);
+                let mut body_scope = scope.clone();
+                self.block(&mut body_scope, body);
+                let mut else_scope = scope.clone();
+                self.block(&mut else_scope, else_body);
+                // names bound in both branches are merged
+                for (name, span) in body_scope {
+                    if else_scope.contains_key(&name) {
+                        scope.entry(name).or_insert(span);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::assembler::Assembler;
+use crate::ast::{Span, line_col};
+use crate::diagnostic::{Diagnostic, MessageFormat, codes};
+use crate::minivm::{MiniVm, Relocatable, VmError};
+use std::collections::BTreeMap;
+
+// Maps program pcs back to the source they were compiled from.
+pub struct SourceMap {
+    pub file_name: String,
+    pub source: String,
+    pub locations: BTreeMap<u64, Span>,
+    // start pc of each function
+    pub functions: BTreeMap<u64, String>,
+}
+
+impl SourceMap {
+    // `functions` tells the function labels apart from the other labels of the program.
+    pub fn new<'a>(
+        assembler: &Assembler,
+        functions: impl IntoIterator<Item = &'a String>,
+    ) -> Option<Self> {
+        let (file_name, source) = assembler.source.clone()?;
+        let functions = functions
+            .into_iter()
+            .filter_map(|name| Some((*assembler.function_adresses.get(name)?, name.clone())))
+            .collect();
+        Some(Self {
+            file_name,
+            source,
+            locations: assembler.locations.clone(),
+            functions,
+        })
+    }
+
+    // Span of the code the instruction at pc comes from.
+    pub fn location(&self, pc: u64) -> Option<Span> {
+        self.locations
+            .range(..=pc)
+            .next_back()
+            .map(|(_, span)| *span)
+    }
+
+    // Function containing pc, with its start pc.
+    pub fn function(&self, pc: u64) -> Option<(u64, &str)> {
+        self.functions
+            .range(..=pc)
+            .next_back()
+            .map(|(start, name)| (*start, name.as_str()))
+    }
+
+    pub fn report_vm_error(&self, vm: &MiniVm, error: &VmError, format: MessageFormat) {
+        self.vm_diagnostic(vm, error)
+            .emit(&self.file_name, &self.source, format);
+    }
+
+    // Locates the error on the offending line, with the caller of the failing function
+    // and a traceback of the functions that led to it.
+    pub fn vm_diagnostic(&self, vm: &MiniVm, error: &VmError) -> Diagnostic {
+        let program = vm.program_base.segment;
+        let span = (error.pc.segment == program)
+            .then(|| self.location(error.pc.offset))
+            .flatten();
+        let mut diagnostic = Diagnostic::error(codes::RUNTIME_ERROR, error.kind.to_string());
+        diagnostic = match span {
+            Some(span) => diagnostic.with_label(span, format!("at pc {}", error.pc)),
+            None => diagnostic.with_note(format!("at pc {}", error.pc)),
+        };
+
+        let call_stack = vm.call_stack();
+        if call_stack.len() < 2 {
+            return diagnostic;
+        }
+        // the first frame is the failing instruction itself, the second one its call
+        if let Some(pc) = call_stack.get(1)
+            && pc.segment == program
+            && let Some(span) = self.location(pc.offset)
+        {
+            let caller = self
+                .function(pc.offset)
+                .map_or("<unknown>", |(_, name)| name);
+            diagnostic = diagnostic.with_secondary(span, format!("called from {}", caller));
+        }
+        let mut traceback = "Traceback (most recent call last):".to_string();
+        let frames: Vec<&Relocatable> = call_stack.iter().rev().collect();
+        let mut i = 0;
+        while i < frames.len() {
+            let pc = frames[i];
+            traceback.push_str(&self.frame(pc, program));
+            // a recursion is written once, with the number of times it repeats
+            let repeated = frames[i..].iter().take_while(|other| **other == pc).count();
+            if repeated > 1 {
+                traceback.push_str(&format!(
+                    "\n  [previous frame repeated {} more times]",
+                    repeated - 1
+                ));
+            }
+            i += repeated;
+        }
+        diagnostic.with_note(traceback)
+    }
+
+    // A line of the traceback: where pc is in the source, with the code there.
+    fn frame(&self, pc: &Relocatable, program: usize) -> String {
+        if pc.segment != program {
+            return format!("\n  pc {}", pc);
+        }
+        let function = self
+            .function(pc.offset)
+            .map_or("<unknown>", |(_, name)| name);
+        match self.location(pc.offset) {
+            Some(span) => {
+                let (line, col) = line_col(&self.source, span.0);
+                let text = self.source.lines().nth(line - 1).unwrap_or("");
+                format!(
+                    "\n  {}:{}:{} in {} (pc {})\n    {}",
+                    self.file_name,
+                    line,
+                    col,
+                    function,
+                    pc,
+                    text.trim()
+                )
+            }
+            None => format!("\n  {} (pc {})", function, pc),
+        }
+    }
+}
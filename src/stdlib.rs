@@ -0,0 +1,21 @@
+use crate::ast::{CodeElement, Function};
+use crate::cst;
+
+// Library functions written in czc, linked into the programs that call them.
+const LIBRARY: &[(&str, &str)] = &[("stdlib/hash.cairo", include_str!("stdlib/hash.cairo"))];
+
+pub fn functions() -> Vec<Function> {
+    let mut functions = Vec::new();
+    for (file_name, source) in LIBRARY {
+        let (tokens, diagnostics) = cst::tokenize(source);
+        assert!(diagnostics.is_empty(), "Lexing {} failed", file_name);
+        let (_, code_elements, diagnostics) = cst::parse(tokens, source);
+        assert!(diagnostics.is_empty(), "Parsing {} failed", file_name);
+        for code_element in code_elements {
+            if let CodeElement::Function(function) = code_element {
+                functions.push(function);
+            }
+        }
+    }
+    functions
+}
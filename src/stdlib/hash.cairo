@@ -0,0 +1,10 @@
+// Hashes two field elements with the poseidon builtin: the result is the first
+// element of the permutation of (x, y, 2).
+func hash2{poseidon_ptr}(x, y) -> felt {
+    assert [poseidon_ptr] = x;
+    assert [poseidon_ptr + 1] = y;
+    assert [poseidon_ptr + 2] = 2;
+    let result = [poseidon_ptr + 3];
+    let poseidon_ptr = poseidon_ptr + 6;
+    return result;
+}
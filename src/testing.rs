@@ -0,0 +1,126 @@
+// Unit tests written in Cairo: the functions of a program named test_*, each run in a fresh
+// vm from its own entry point. A test passes when it returns, or, if it is decorated with
+// @expect_fail, when it fails.
+
+use crate::ast::{CodeElement, Function};
+use crate::builtins::BuiltinKind;
+use crate::cst;
+use crate::hints::HintRegistry;
+use crate::minivm::{Program, VmError, VmErrorKind};
+use crate::{CompiledProgram, Vm};
+
+// Tests stop after this many steps unless told otherwise, so that one that never returns
+// fails instead of hanging the run.
+pub const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+pub struct Test<'a> {
+    pub function: &'a Function,
+    pub expect_fail: bool,
+}
+
+impl Test<'_> {
+    pub fn name(&self) -> &str {
+        &self.function.name.token.lexeme
+    }
+}
+
+pub struct Outcome {
+    // the vm after the run, to locate the error in
+    pub vm: Vm,
+    pub error: Option<VmError>,
+}
+
+impl Outcome {
+    pub fn steps(&self) -> u64 {
+        self.vm.state.steps
+    }
+
+    pub fn hit_step_limit(&self) -> bool {
+        self.error
+            .as_ref()
+            .is_some_and(|error| matches!(error.kind, VmErrorKind::StepLimit(_)))
+    }
+}
+
+fn is_selected(function: &Function, filter: Option<&str>) -> bool {
+    let name = &function.name.token.lexeme;
+    name.starts_with("test_") && filter.is_none_or(|filter| name.contains(filter))
+}
+
+// The tests of the program whose name contains `filter`, in source order.
+pub fn discover<'a>(compiled: &'a CompiledProgram, filter: Option<&str>) -> Vec<Test<'a>> {
+    compiled
+        .ast()
+        .iter()
+        .filter_map(|element| match element {
+            CodeElement::Function(function) => Some(function),
+            _ => None,
+        })
+        .filter(|function| is_selected(function, filter))
+        .map(|function| Test {
+            function,
+            expect_fail: function
+                .decorators
+                .iter()
+                .any(|decorator| decorator.token.lexeme == "expect_fail"),
+        })
+        .collect()
+}
+
+// Whether a source that may not compile declares a test whose name contains `filter`,
+// judging from its syntax alone.
+pub fn declares_test(source: &str, filter: Option<&str>) -> bool {
+    let (tokens, _) = cst::tokenize(source);
+    let (_, code_elements, _) = cst::parse(tokens, source);
+    code_elements.iter().any(|element| {
+        matches!(element, CodeElement::Function(function) if is_selected(function, filter))
+    })
+}
+
+// Runs the test from its first instruction. Its implicit arguments are builtin pointers,
+// given a segment each like those of main.
+pub fn run(
+    compiled: &CompiledProgram,
+    test: &Test,
+    hint_processor: HintRegistry,
+    max_steps: Option<u64>,
+) -> Result<Outcome, String> {
+    let function = test.function;
+    if !function.args.is_empty() {
+        return Err(format!("{} takes arguments, tests cannot", test.name()));
+    }
+    let mut program = Program::from_assembler(compiled.assembler(), test.name())?;
+    program.builtins = Vec::new();
+    for arg in function.implicit_args.iter() {
+        let name = &arg.ident.token.lexeme;
+        let builtin = name
+            .strip_suffix("_ptr")
+            .filter(|builtin| BuiltinKind::from_name(builtin).is_some())
+            .ok_or(format!(
+                "{} takes implicit argument {}, tests can only take builtin pointers",
+                test.name(),
+                name
+            ))?;
+        program.builtins.push(builtin.to_string());
+    }
+    let mut vm = Vm::new();
+    vm.hint_processor = hint_processor;
+    vm.max_steps = max_steps;
+    let error = vm.run(&program).err();
+    Ok(Outcome { vm, error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_selected_tests_in_files_that_do_not_compile() {
+        let source =
+            "func test_sum() {\n    assert y = 1;\n    return ();\n}\n\nfunc helper( {\n}\n";
+        assert!(declares_test(source, None));
+        assert!(declares_test(source, Some("sum")));
+        assert!(!declares_test(source, Some("product")));
+        assert!(!declares_test(source, Some("helper")));
+    }
+}
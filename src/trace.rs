@@ -0,0 +1,128 @@
+// Files consumed by provers, in the binary layout of
+// `cairo-run --trace_file --memory_file --air_public_input`.
+
+use crate::builtins::BuiltinKind;
+use crate::felt::Felt;
+use crate::minivm::{MiniVm, Relocatable, Value};
+use std::fs;
+
+// One entry per step: ap, fp and pc as 8 byte little-endian integers.
+pub fn write_trace_file(vm: &MiniVm, path: &str) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    for entry in vm.relocated_trace() {
+        bytes.extend_from_slice(&entry.ap.to_le_bytes());
+        bytes.extend_from_slice(&entry.fp.to_le_bytes());
+        bytes.extend_from_slice(&entry.pc.to_le_bytes());
+    }
+    fs::write(path, bytes).map_err(|e| format!("Could not write {}: {}", path, e))
+}
+
+// One entry per written cell: the address on 8 bytes and the value on 32 bytes,
+// both little-endian.
+pub fn write_memory_file(vm: &MiniVm, path: &str) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    for (address, value) in vm.relocated_memory() {
+        bytes.extend_from_slice(&address.to_le_bytes());
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fs::write(path, bytes).map_err(|e| format!("Could not write {}: {}", path, e))
+}
+
+// Smallest and largest 16-bit values the range check component sees: the offsets of the
+// executed instructions, biased by 2^15, and the limbs of the range checked values.
+fn range_check_bounds(vm: &MiniVm) -> (u64, u64) {
+    let mut values = Vec::new();
+    for entry in vm.trace.iter() {
+        if let Some(Value::Int(word)) = vm.memory.get(entry.pc)
+            && let Some(word) = word.to_u64()
+        {
+            values.extend([0, 16, 32].map(|shift| (word >> shift) & 0xffff));
+        }
+    }
+    let bound = vm.field.range_check_bound();
+    let limbs = (1..8).find(|n| Felt::pow2(16 * n) == bound).unwrap_or(8);
+    let limb = Felt::from(1 << 16);
+    for builtin in vm.builtins.iter() {
+        if builtin.kind != BuiltinKind::RangeCheck {
+            continue;
+        }
+        for value in vm.memory.segments[builtin.base.segment].iter().flatten() {
+            let Value::Int(mut value) = *value else {
+                continue;
+            };
+            for _ in 0..limbs {
+                let (quotient, remainder) = value.div_rem(&limb);
+                values.extend(remainder.to_u64());
+                value = quotient;
+            }
+        }
+    }
+    let min = values.iter().copied().min().unwrap_or(0);
+    let max = values.iter().copied().max().unwrap_or(0);
+    (min, max)
+}
+
+// Segment bounds, the range of the range checks and the public memory: the program and the
+// output of the run.
+pub fn write_public_input(vm: &MiniVm, path: &str) -> Result<(), String> {
+    let table = vm.relocation_table();
+    let segment = |base: Relocatable| {
+        let mut bounds = json::JsonValue::new_object();
+        let size = vm.memory.segments[base.segment].len() as u64;
+        bounds["begin_addr"] = MiniVm::relocate(&table, base).into();
+        bounds["stop_ptr"] = (MiniVm::relocate(&table, base) + size).into();
+        bounds
+    };
+
+    let (rc_min, rc_max) = range_check_bounds(vm);
+    let mut public_input = json::JsonValue::new_object();
+    // czc has no layouts of its own: the plain one when no builtin is used, otherwise
+    // the one with all of them
+    public_input["layout"] = if vm.builtins.is_empty() {
+        "plain"
+    } else {
+        "all_cairo"
+    }
+    .into();
+    public_input["rc_min"] = rc_min.into();
+    public_input["rc_max"] = rc_max.into();
+    public_input["prime"] = format!("{:#x}", vm.field.prime).into();
+    public_input["n_steps"] = vm.steps.into();
+    public_input["memory_segments"] = json::JsonValue::new_object();
+    public_input["memory_segments"]["program"] = segment(vm.program_base);
+    public_input["memory_segments"]["execution"] = segment(vm.execution_base);
+    for builtin in vm.builtins.iter() {
+        public_input["memory_segments"][builtin.kind.name()] = segment(builtin.base);
+    }
+
+    let mut public_segments = vec![vm.program_base.segment];
+    public_segments.extend(
+        vm.builtins
+            .iter()
+            .filter(|builtin| builtin.kind == BuiltinKind::Output)
+            .map(|builtin| builtin.base.segment),
+    );
+    let public_addresses: Vec<(u64, u64)> = public_segments
+        .iter()
+        .map(|segment| {
+            let begin = table[*segment];
+            (begin, begin + vm.memory.segments[*segment].len() as u64)
+        })
+        .collect();
+    public_input["public_memory"] = json::JsonValue::new_array();
+    for (address, value) in vm.relocated_memory() {
+        if public_addresses
+            .iter()
+            .any(|(begin, end)| (*begin..*end).contains(&address))
+        {
+            let mut cell = json::JsonValue::new_object();
+            cell["address"] = address.into();
+            cell["value"] = format!("{:#x}", value).into();
+            cell["page"] = 0.into();
+            let _ = public_input["public_memory"].push(cell);
+        }
+    }
+    // only the dynamic layout has parameters
+    public_input["dynamic_params"] = json::JsonValue::Null;
+    fs::write(path, public_input.pretty(2)).map_err(|e| format!("Could not write {}: {}", path, e))
+}
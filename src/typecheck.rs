@@ -0,0 +1,708 @@
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, codes};
+use crate::stdlib;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+// Infers the type of every expression and checks it against the declarations.
+// Names the resolve pass rejected, and expressions nothing is known about, get the unknown
+// type, which matches every type so that each mistake is reported once.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Felt,
+    CodeOffset,
+    Pointer(Box<Ty>),
+    Struct(String),
+    Tuple(Vec<Ty>),
+    Unknown,
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Felt => write!(f, "felt"),
+            Ty::CodeOffset => write!(f, "codeoffset"),
+            Ty::Pointer(inner) => write!(f, "{}*", inner),
+            Ty::Struct(name) => write!(f, "{}", name),
+            Ty::Tuple(types) => {
+                let types: Vec<String> = types.iter().map(|ty| ty.to_string()).collect();
+                write!(f, "({})", types.join(", "))
+            }
+            Ty::Unknown => write!(f, "_"),
+        }
+    }
+}
+
+impl Ty {
+    // felt, codeoffset and pointers fit in a single cell
+    fn is_scalar(&self) -> bool {
+        matches!(
+            self,
+            Ty::Felt | Ty::CodeOffset | Ty::Pointer(_) | Ty::Unknown
+        )
+    }
+}
+
+struct Signature {
+    implicit_args: Vec<(String, Ty)>,
+    args: Vec<(String, Ty)>,
+    return_type: Option<Ty>,
+    // name of the declaration, for functions of the compiled source
+    span: Option<Span>,
+}
+
+struct StructDef {
+    span: Span,
+    members: Vec<(String, Ty)>,
+}
+
+// Type of every binding in scope.
+type Scope = HashMap<String, Ty>;
+
+struct TypeChecker {
+    structs: HashMap<String, StructDef>,
+    functions: HashMap<String, Signature>,
+    // declared return type of the function being checked
+    return_type: Option<Ty>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub fn check(code_elements: &[CodeElement]) -> Vec<Diagnostic> {
+    let mut checker = TypeChecker {
+        structs: HashMap::new(),
+        functions: HashMap::new(),
+        return_type: None,
+        diagnostics: Vec::new(),
+    };
+    // structs may refer to each other, all names are known before their members are typed
+    for element in code_elements {
+        if let CodeElement::Struct(name, ..) = element {
+            checker.structs.insert(
+                name.token.lexeme.clone(),
+                StructDef {
+                    span: name.token.span,
+                    members: Vec::new(),
+                },
+            );
+        }
+    }
+    for element in code_elements {
+        if let CodeElement::Struct(name, members, _) = element {
+            let members = checker.typed_identifiers(members);
+            if let Some(def) = checker.structs.get_mut(&name.token.lexeme) {
+                def.members = members;
+            }
+        }
+    }
+
+    let library = stdlib::functions();
+    let program = code_elements.iter().filter_map(|element| match element {
+        CodeElement::Function(function) => Some(function),
+        _ => None,
+    });
+    for function in program {
+        let mut signature = checker.signature(function);
+        signature.span = Some(function.name.token.span);
+        checker
+            .functions
+            .insert(function.name.token.lexeme.clone(), signature);
+    }
+    for function in library.iter() {
+        if !checker.functions.contains_key(&function.name.token.lexeme) {
+            let signature = checker.signature(function);
+            checker
+                .functions
+                .insert(function.name.token.lexeme.clone(), signature);
+        }
+    }
+
+    for element in code_elements {
+        if let CodeElement::Function(function) = element {
+            checker.function(function);
+        }
+    }
+    checker.diagnostics
+}
+
+// Whether a value of type `actual` can be used where `expected` is, a single value
+// standing for a tuple of one element.
+fn matches(expected: &Ty, actual: &Ty) -> bool {
+    match (expected, actual) {
+        (Ty::Unknown, _) | (_, Ty::Unknown) => true,
+        (Ty::Pointer(expected), Ty::Pointer(actual)) => matches(expected, actual),
+        (Ty::Tuple(expected), Ty::Tuple(actual)) => {
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual.iter())
+                    .all(|(expected, actual)| matches(expected, actual))
+        }
+        (Ty::Tuple(expected), actual) if expected.len() == 1 => matches(&expected[0], actual),
+        (expected, Ty::Tuple(actual)) if actual.len() == 1 => matches(expected, &actual[0]),
+        (expected, actual) => expected == actual,
+    }
+}
+
+fn operator(expr_type: &ExprType) -> &'static str {
+    match expr_type {
+        ExprType::Add => "+",
+        ExprType::Sub => "-",
+        ExprType::Mul => "*",
+        ExprType::Div => "/",
+        ExprType::Pow => "**",
+        ExprType::Neg => "-",
+        _ => "?",
+    }
+}
+
+impl TypeChecker {
+    fn error(&mut self, code: &str, message: String, span: Option<Span>, label: String) {
+        let mut diagnostic = Diagnostic::error(code, message);
+        if let Some(span) = span {
+            diagnostic = diagnostic.with_label(span, label);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn ty(&mut self, type_: &Type) -> Ty {
+        match type_ {
+            Type::Felt => Ty::Felt,
+            Type::CodeOffset => Ty::CodeOffset,
+            Type::Pointer(inner) => Ty::Pointer(Box::new(self.ty(inner))),
+            Type::Pointer2(inner) => Ty::Pointer(Box::new(Ty::Pointer(Box::new(self.ty(inner))))),
+            Type::Tuple(types) => Ty::Tuple(types.iter().map(|type_| self.ty(type_)).collect()),
+            Type::Named(_, inner) => self.ty(inner),
+            Type::Struct(ident) => {
+                let name = &ident.token.lexeme;
+                if self.structs.contains_key(name) {
+                    return Ty::Struct(name.clone());
+                }
+                self.diagnostics.push(
+                    Diagnostic::error(codes::UNKNOWN_TYPE, format!("Unknown type '{}'", name))
+                        .with_label(ident.token.span, "not a struct of this program".to_string()),
+                );
+                Ty::Unknown
+            }
+            Type::Error => Ty::Unknown,
+        }
+    }
+
+    // arguments and members without a type are felts
+    fn typed_identifiers(&mut self, identifiers: &[TypedIdentifier]) -> Vec<(String, Ty)> {
+        identifiers
+            .iter()
+            .map(|identifier| {
+                let ty = match &identifier.type_ {
+                    Some(type_) => self.ty(type_),
+                    None => Ty::Felt,
+                };
+                (identifier.ident.token.lexeme.clone(), ty)
+            })
+            .collect()
+    }
+
+    fn signature(&mut self, function: &Function) -> Signature {
+        Signature {
+            implicit_args: self.typed_identifiers(&function.implicit_args),
+            args: self.typed_identifiers(&function.args),
+            return_type: function
+                .return_type
+                .as_ref()
+                .map(|return_type| self.ty(return_type)),
+            span: None,
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        let mut scope = Scope::new();
+        if let Some(signature) = self.functions.get(&function.name.token.lexeme) {
+            for (name, ty) in signature.implicit_args.iter().chain(signature.args.iter()) {
+                scope.insert(name.clone(), ty.clone());
+            }
+            self.return_type = signature.return_type.clone();
+        }
+        self.block(&mut scope, &function.body);
+        self.return_type = None;
+    }
+
+    // Type of `p.x.y`, where members of pointers to structs are accessed through the pointer.
+    fn identifier(&mut self, scope: &Scope, ident: &Identifier) -> Ty {
+        let mut parts = ident.token.lexeme.split('.');
+        let Some(mut ty) = parts.next().and_then(|name| scope.get(name)).cloned() else {
+            return Ty::Unknown;
+        };
+        for member in parts {
+            let name = match &ty {
+                Ty::Unknown => return Ty::Unknown,
+                Ty::Struct(name) => name,
+                Ty::Pointer(inner) if matches!(**inner, Ty::Struct(_)) => match &**inner {
+                    Ty::Struct(name) => name,
+                    _ => unreachable!(),
+                },
+                _ => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            codes::INVALID_MEMBER_ACCESS,
+                            format!("Cannot access member '{}' of '{}'", member, ty),
+                        )
+                        .with_label(ident.token.span, format!("'{}' is not a struct", ty)),
+                    );
+                    return Ty::Unknown;
+                }
+            };
+            let def = &self.structs[name];
+            match def.members.iter().find(|(name, _)| name == member) {
+                Some((_, member_ty)) => ty = member_ty.clone(),
+                None => {
+                    let members: Vec<&str> =
+                        def.members.iter().map(|(name, _)| name.as_str()).collect();
+                    let diagnostic = Diagnostic::error(
+                        codes::INVALID_MEMBER_ACCESS,
+                        format!("'{}' has no member '{}'", name, member),
+                    )
+                    .with_label(ident.token.span, "unknown member".to_string())
+                    .with_secondary(def.span, format!("'{}' is declared here", name))
+                    .with_note(match members.is_empty() {
+                        true => format!("'{}' has no members", name),
+                        false => format!("the members of '{}' are {}", name, members.join(", ")),
+                    });
+                    self.diagnostics.push(diagnostic);
+                    return Ty::Unknown;
+                }
+            }
+        }
+        ty
+    }
+
+    fn arithmetic(&mut self, expr: &Expr, left: Ty, right: Ty) -> Ty {
+        let op = operator(&expr.expr_type);
+        for ty in [&left, &right] {
+            if !ty.is_scalar() {
+                self.error(
+                    codes::INVALID_OPERANDS,
+                    format!("Cannot apply '{}' to '{}'", op, ty),
+                    expr.span(),
+                    format!("'{}' is not a number", ty),
+                );
+                return Ty::Unknown;
+            }
+        }
+        let result = match (&expr.expr_type, &left, &right) {
+            (_, Ty::Unknown, _) | (_, _, Ty::Unknown) => Some(Ty::Unknown),
+            (_, Ty::Felt, Ty::Felt) => Some(Ty::Felt),
+            // pointers and code offsets move by felts
+            (ExprType::Add, offset, Ty::Felt) | (ExprType::Add, Ty::Felt, offset) => {
+                Some(offset.clone())
+            }
+            (ExprType::Sub, offset, Ty::Felt) => Some(offset.clone()),
+            (ExprType::Sub, Ty::Pointer(a), Ty::Pointer(b)) if matches(a, b) => Some(Ty::Felt),
+            (ExprType::Sub, Ty::CodeOffset, Ty::CodeOffset) => Some(Ty::Felt),
+            _ => None,
+        };
+        result.unwrap_or_else(|| {
+            self.error(
+                codes::INVALID_OPERANDS,
+                format!("Cannot apply '{}' to '{}' and '{}'", op, left, right),
+                expr.span(),
+                format!("'{}' on '{}' and '{}'", op, left, right),
+            );
+            Ty::Unknown
+        })
+    }
+
+    fn cast(&mut self, expr: &Expr, source: Ty) -> Ty {
+        let Some(type_arg) = &expr.type_arg else {
+            return Ty::Unknown;
+        };
+        let target = self.ty(type_arg);
+        let valid = matches(&target, &source) || (target.is_scalar() && source.is_scalar());
+        if !valid {
+            self.error(
+                codes::INVALID_CAST,
+                format!("Cannot cast '{}' to '{}'", source, target),
+                expr.span(),
+                format!("this has type '{}'", source),
+            );
+        }
+        target
+    }
+
+    fn call(&mut self, scope: &mut Scope, expr: &Expr) -> Ty {
+        let name = expr
+            .ident
+            .as_ref()
+            .map(|ident| ident.token.lexeme.clone())
+            .unwrap_or_default();
+        let Some(signature) = self.functions.get(&name) else {
+            for arg in expr.paren_args.iter().chain(expr.brace_args.iter()) {
+                let (ExprAssignment::Expr(arg) | ExprAssignment::Assign(_, arg)) = arg;
+                self.expr(scope, arg);
+            }
+            return Ty::Unknown;
+        };
+        let args = signature.args.clone();
+        let implicit_args = signature.implicit_args.clone();
+        let declaration = signature.span;
+        let return_type = signature.return_type.clone();
+
+        if expr.paren_args.len() != args.len() {
+            let mut diagnostic = Diagnostic::error(
+                codes::WRONG_ARGUMENT_COUNT,
+                format!(
+                    "'{}' takes {} argument{} but {} were given",
+                    name,
+                    args.len(),
+                    if args.len() == 1 { "" } else { "s" },
+                    expr.paren_args.len()
+                ),
+            );
+            if let Some(span) = expr.span() {
+                diagnostic = diagnostic.with_label(
+                    span,
+                    format!(
+                        "{} argument{}",
+                        expr.paren_args.len(),
+                        if expr.paren_args.len() == 1 { "" } else { "s" }
+                    ),
+                );
+            }
+            if let Some(span) = declaration {
+                diagnostic =
+                    diagnostic.with_secondary(span, format!("'{}' is declared here", name));
+            }
+            self.diagnostics.push(diagnostic);
+        }
+
+        // named arguments are matched by name, the others by position
+        let mut passed = Vec::new();
+        for (i, arg) in expr.paren_args.iter().enumerate() {
+            match arg {
+                ExprAssignment::Expr(value) => passed.push((args.get(i), value)),
+                ExprAssignment::Assign(ident, value) => passed.push((
+                    args.iter().find(|(name, _)| *name == ident.token.lexeme),
+                    value,
+                )),
+            }
+        }
+        for arg in expr.brace_args.iter() {
+            let (name, value) = match arg {
+                ExprAssignment::Assign(ident, value) => (Some(&ident.token.lexeme), value),
+                ExprAssignment::Expr(value) => {
+                    (value.ident.as_ref().map(|ident| &ident.token.lexeme), value)
+                }
+            };
+            let implicit_arg = implicit_args
+                .iter()
+                .find(|(implicit_arg, _)| Some(implicit_arg) == name);
+            passed.push((implicit_arg, value));
+        }
+        for (declared, value) in passed {
+            let ty = self.expr(scope, value);
+            if let Some((arg_name, expected)) = declared
+                && !matches(expected, &ty)
+            {
+                self.error(
+                    codes::TYPE_MISMATCH,
+                    format!(
+                        "Argument '{}' of '{}' expects '{}', got '{}'",
+                        arg_name, name, expected, ty
+                    ),
+                    value.span(),
+                    format!("expected '{}'", expected),
+                );
+            }
+        }
+
+        // a single return value is left at the top of the stack
+        match return_type {
+            Some(Ty::Tuple(mut types)) if types.len() == 1 => types.remove(0),
+            Some(ty) => ty,
+            None => Ty::Unknown,
+        }
+    }
+
+    fn expr(&mut self, scope: &mut Scope, expr: &Expr) -> Ty {
+        let left = expr.left.as_deref();
+        let right = expr.right.as_deref();
+        match expr.expr_type {
+            ExprType::IntegerLiteral | ExprType::Hint => Ty::Felt,
+            ExprType::Register => Ty::Pointer(Box::new(Ty::Felt)),
+            ExprType::Identifier => match &expr.ident {
+                Some(ident) => self.identifier(scope, ident),
+                None => Ty::Unknown,
+            },
+            ExprType::Add | ExprType::Sub | ExprType::Mul | ExprType::Div | ExprType::Pow => {
+                let left = left.map_or(Ty::Unknown, |left| self.expr(scope, left));
+                let right = right.map_or(Ty::Unknown, |right| self.expr(scope, right));
+                match expr.expr_type {
+                    ExprType::Add | ExprType::Sub => self.arithmetic(expr, left, right),
+                    // only felts are multiplied
+                    _ => {
+                        for ty in [&left, &right] {
+                            if !matches!(ty, Ty::Felt | Ty::Unknown) {
+                                self.error(
+                                    codes::INVALID_OPERANDS,
+                                    format!(
+                                        "Cannot apply '{}' to '{}'",
+                                        operator(&expr.expr_type),
+                                        ty
+                                    ),
+                                    expr.span(),
+                                    "only felts can be multiplied, divided or raised".to_string(),
+                                );
+                                return Ty::Unknown;
+                            }
+                        }
+                        Ty::Felt
+                    }
+                }
+            }
+            ExprType::Neg => {
+                let ty = left.map_or(Ty::Unknown, |left| self.expr(scope, left));
+                if !matches!(ty, Ty::Felt | Ty::Unknown) {
+                    self.error(
+                        codes::INVALID_OPERANDS,
+                        format!("Cannot apply '-' to '{}'", ty),
+                        expr.span(),
+                        "only felts can be negated".to_string(),
+                    );
+                    return Ty::Unknown;
+                }
+                Ty::Felt
+            }
+            ExprType::Deref => match left.map_or(Ty::Unknown, |left| self.expr(scope, left)) {
+                Ty::Pointer(inner) => *inner,
+                Ty::Felt => Ty::Felt,
+                Ty::Unknown => Ty::Unknown,
+                ty => {
+                    self.error(
+                        codes::INVALID_OPERANDS,
+                        format!("Cannot dereference '{}'", ty),
+                        expr.span(),
+                        format!("'{}' is not a pointer", ty),
+                    );
+                    Ty::Unknown
+                }
+            },
+            ExprType::AddressOf | ExprType::New => {
+                let ty = left.map_or(Ty::Unknown, |left| self.expr(scope, left));
+                Ty::Pointer(Box::new(ty))
+            }
+            ExprType::Cast => {
+                let source = left.map_or(Ty::Unknown, |left| self.expr(scope, left));
+                self.cast(expr, source)
+            }
+            ExprType::Eq | ExprType::Neq => {
+                let left = left.map_or(Ty::Unknown, |left| self.expr(scope, left));
+                let right = right.map_or(Ty::Unknown, |right| self.expr(scope, right));
+                if !(left.is_scalar() && right.is_scalar() && matches(&left, &right)) {
+                    self.error(
+                        codes::TYPE_MISMATCH,
+                        format!("Cannot compare '{}' with '{}'", left, right),
+                        expr.span(),
+                        "operands of different types".to_string(),
+                    );
+                }
+                Ty::Felt
+            }
+            ExprType::And => {
+                for child in [left, right].into_iter().flatten() {
+                    self.expr(scope, child);
+                }
+                Ty::Felt
+            }
+            ExprType::FunctionCall => self.call(scope, expr),
+            ExprType::Subscript => {
+                let ty = left.map_or(Ty::Unknown, |left| self.expr(scope, left));
+                if let Some(right) = right {
+                    self.expr(scope, right);
+                }
+                match ty {
+                    Ty::Pointer(inner) => *inner,
+                    _ => Ty::Unknown,
+                }
+            }
+            ExprType::TupleOrParen => {
+                let mut types: Vec<Ty> = Vec::new();
+                for arg in expr.paren_args.iter() {
+                    let (ExprAssignment::Expr(arg) | ExprAssignment::Assign(_, arg)) = arg;
+                    types.push(self.expr(scope, arg));
+                }
+                // parentheses around a single value
+                match expr.paren_args.as_slice() {
+                    [ExprAssignment::Expr(_)] => types.remove(0),
+                    _ => Ty::Tuple(types),
+                }
+            }
+            ExprType::ErrorExpr => Ty::Unknown,
+        }
+    }
+
+    // A declared binding takes its declared type, an undeclared one the type of its value.
+    fn bind(&mut self, scope: &mut Scope, ident: &TypedIdentifier, value: Option<(&Expr, Ty)>) {
+        let declared = ident.type_.as_ref().map(|type_| self.ty(type_));
+        let name = &ident.ident.token.lexeme;
+        let ty = match (declared, value) {
+            (Some(declared), Some((value, ty))) => {
+                if !matches(&declared, &ty) {
+                    self.error(
+                        codes::TYPE_MISMATCH,
+                        format!(
+                            "Cannot assign '{}' to '{}' of type '{}'",
+                            ty, name, declared
+                        ),
+                        value.span(),
+                        format!("expected '{}'", declared),
+                    );
+                }
+                declared
+            }
+            (Some(declared), None) => declared,
+            (None, Some((_, ty))) => ty,
+            (None, None) => Ty::Felt,
+        };
+        scope.insert(name.clone(), ty);
+    }
+
+    fn block(&mut self, scope: &mut Scope, body: &[CodeElement]) {
+        for element in body {
+            self.code_element(scope, element);
+        }
+    }
+
+    fn code_element(&mut self, scope: &mut Scope, element: &CodeElement) {
+        match element {
+            CodeElement::LocalVar(ident, value) => {
+                let value = value.as_ref().map(|value| (value, self.expr(scope, value)));
+                self.bind(scope, ident, value);
+            }
+            CodeElement::Reference(ident, value) => {
+                let ty = self.expr(scope, value);
+                self.bind(scope, ident, Some((value, ty)));
+            }
+            CodeElement::CompoundAssertEqual(left, right) => {
+                let left_ty = self.expr(scope, left);
+                let right_ty = self.expr(scope, right);
+                if !matches(&left_ty, &right_ty) {
+                    self.error(
+                        codes::TYPE_MISMATCH,
+                        format!("Cannot assert that '{}' equals '{}'", left_ty, right_ty),
+                        element.span(),
+                        "the two sides have different types".to_string(),
+                    );
+                }
+            }
+            CodeElement::Instruction(instruction) => {
+                let types: Vec<Ty> = instruction
+                    .args
+                    .iter()
+                    .map(|arg| self.expr(scope, arg))
+                    .collect();
+                if instruction.instruction_type == InstructionType::AssertEq
+                    && let [left, right] = types.as_slice()
+                    && !matches(left, right)
+                {
+                    self.error(
+                        codes::TYPE_MISMATCH,
+                        format!("Cannot assert that '{}' equals '{}'", left, right),
+                        element.span(),
+                        "the two sides have different types".to_string(),
+                    );
+                }
+            }
+            CodeElement::Return(value) => {
+                let ty = self.expr(scope, value);
+                if let Some(expected) = self.return_type.clone()
+                    && !matches(&expected, &ty)
+                {
+                    self.error(
+                        codes::TYPE_MISMATCH,
+                        format!(
+                            "Expected a return value of type '{}', got '{}'",
+                            expected, ty
+                        ),
+                        value.span(),
+                        format!("expected '{}'", expected),
+                    );
+                }
+            }
+            CodeElement::FuncCall(expr) => {
+                self.expr(scope, expr);
+            }
+            CodeElement::If(condition, body, else_body) => {
+                self.expr(scope, condition);
+                let mut body_scope = scope.clone();
+                self.block(&mut body_scope, body);
+                let mut else_scope = scope.clone();
+                self.block(&mut else_scope, else_body);
+                // names bound in both branches keep their type if the branches agree on it
+                for (name, ty) in body_scope {
+                    if let Some(other) = else_scope.get(&name) {
+                        let ty = if ty == *other { ty } else { Ty::Unknown };
+                        scope.insert(name, ty);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cst;
+
+    fn codes(source: &str) -> Vec<String> {
+        let (tokens, _) = cst::tokenize(source);
+        let (_, code_elements, diagnostics) = cst::parse(tokens, source);
+        assert!(diagnostics.is_empty());
+        check(&code_elements)
+            .into_iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect()
+    }
+
+    #[test]
+    fn nondet_values_are_felts_and_cast_to_pointers() {
+        let source = "func main() {\n\
+                let p = cast(nondet %{ 5 %}, felt*);\n\
+                let x: felt = [p];\n\
+                let y: felt* = nondet %{ 6 %};\n\
+                return ();\n\
+            }\n";
+        assert_eq!(codes(source), [codes::TYPE_MISMATCH]);
+    }
+
+    #[test]
+    fn structs_only_cast_to_themselves() {
+        let source = "struct Point {\n\
+                x: felt,\n\
+                y: felt,\n\
+            }\n\
+            func f(p: Point*) -> felt {\n\
+                let q = cast(p, Point*);\n\
+                let a = cast([q], felt);\n\
+                return cast(q, felt);\n\
+            }\n";
+        assert_eq!(codes(source), [codes::INVALID_CAST]);
+    }
+
+    #[test]
+    fn arguments_are_checked_against_the_signature() {
+        let source = "func f(p: felt*) -> felt {\n\
+                return [p];\n\
+            }\n\
+            func main() {\n\
+                let x = f(3);\n\
+                let y = f(cast(3, felt*));\n\
+                let z = f(cast(1, felt*), 2);\n\
+                return ();\n\
+            }\n";
+        assert_eq!(
+            codes(source),
+            [codes::TYPE_MISMATCH, codes::WRONG_ARGUMENT_COUNT]
+        );
+    }
+}